@@ -0,0 +1,332 @@
+use std::{env, ffi::{CStr, CString}, mem::size_of, slice};
+use ash::vk;
+use super::{Instance, Surface, device::QueuePlan};
+
+/// Environment variable which overrides adapter selection. It is read as an index into the instance's physical
+/// devices if it parses as a number, and as a case-insensitive substring of the device name otherwise.
+pub const ADAPTER_OVERRIDE_VARIABLE : &str = "HALOGEN_ADAPTER";
+
+/// Forces a specific adapter to be selected, as long as it meets the selector's requirements.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AdapterOverride {
+    /// Selects the adapter at this index in `Instance::physical_devices`.
+    Index(usize),
+    /// Selects the first adapter whose name contains this string, ignoring case.
+    Name(String),
+}
+
+impl AdapterOverride {
+    /// Parses an override from the `HALOGEN_ADAPTER` environment variable, if it is set.
+    pub fn from_env() -> Option<Self> {
+        Self::parse(&env::var(ADAPTER_OVERRIDE_VARIABLE).ok()?)
+    }
+
+    /// Parses an override as an index if `value` is a number, and as a name otherwise. Returns `None` if `value` is
+    /// blank.
+    fn parse(value : &str) -> Option<Self> {
+        let value = value.trim();
+        if value.is_empty() {
+            return None;
+        }
+        Some(match value.parse::<usize>() {
+            Ok(index) => AdapterOverride::Index(index),
+            Err(_) => AdapterOverride::Name(value.to_string()),
+        })
+    }
+
+    fn matches(&self, index : usize, name : &str) -> bool {
+        match self {
+            AdapterOverride::Index(i) => *i == index,
+            AdapterOverride::Name(n) => name.to_lowercase().contains(&n.to_lowercase()),
+        }
+    }
+}
+
+/// Describes what an adapter needs to support in order to be selected, and picks the best one available.
+/// Adapters meeting every requirement are scored by their type, preferring discrete over integrated over virtual
+/// over software devices, and then by the size of their device local memory.
+pub struct AdapterSelector {
+    required_extensions : Vec<CString>,
+    required_features : vk::PhysicalDeviceFeatures,
+    required_queue_flags : vk::QueueFlags,
+    adapter_override : Option<AdapterOverride>,
+}
+
+impl Default for AdapterSelector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AdapterSelector {
+    /// Creates a selector requiring a graphics queue and the features the renderer relies on. Any override in the
+    /// `HALOGEN_ADAPTER` environment variable is applied.
+    pub fn new() -> Self {
+        Self {
+            required_extensions: Vec::new(),
+            required_features: vk::PhysicalDeviceFeatures::builder()
+                .sampler_anisotropy(true)
+                .fill_mode_non_solid(true)
+                .build(),
+            required_queue_flags: vk::QueueFlags::GRAPHICS,
+            adapter_override: AdapterOverride::from_env(),
+        }
+    }
+
+    /// Requires the adapter to support a device extension. The extension is enabled on the created device.
+    pub fn require_extension(mut self, name : &CStr) -> Self {
+        self.required_extensions.push(name.to_owned());
+        self
+    }
+
+    /// Replaces the set of features the adapter must support. These features are enabled on the created device.
+    pub fn require_features(mut self, features : vk::PhysicalDeviceFeatures) -> Self {
+        self.required_features = features;
+        self
+    }
+
+    /// Requires at least one queue family supporting each of these flags, in addition to the graphics queue every
+    /// selector requires.
+    pub fn require_queue_flags(mut self, flags : vk::QueueFlags) -> Self {
+        self.required_queue_flags |= flags;
+        self
+    }
+
+    /// Forces the given adapter to be selected, taking priority over the environment variable.
+    pub fn with_override(mut self, adapter_override : AdapterOverride) -> Self {
+        self.adapter_override = Some(adapter_override);
+        self
+    }
+
+    pub fn required_extensions(&self) -> &[CString] {
+        &self.required_extensions
+    }
+
+    pub fn required_features(&self) -> vk::PhysicalDeviceFeatures {
+        self.required_features
+    }
+
+    /// Returns the best adapter which meets every requirement, logging why each rejected adapter was unsuitable.
+    /// If an override is set and its adapter is suitable it is always returned, otherwise the override is ignored.
+    /// When a surface is provided, adapters which cannot present to it are rejected.
+    pub fn select(&self, instance : &Instance, surface : Option<&Surface>) -> Option<vk::PhysicalDevice> {
+        let mut candidates = Vec::new();
+        for (index, physical_device) in instance.physical_devices().into_iter().enumerate() {
+            let properties = unsafe {
+                instance.ash_instance().get_physical_device_properties(physical_device)
+            };
+            let name = device_name(&properties);
+            match self.check(instance, physical_device, surface) {
+                Ok(()) => {
+                    let memory_properties = unsafe {
+                        instance.ash_instance().get_physical_device_memory_properties(physical_device)
+                    };
+                    let score = score(properties.device_type, &memory_properties);
+                    debug!("Adapter {} \"{}\" ({:?}) is suitable with a score of {}",
+                        index, name, properties.device_type, score);
+                    candidates.push(Candidate { index, name, physical_device, score });
+                },
+                Err(reason) => info!("Rejected adapter {} \"{}\": {}", index, name, reason),
+            }
+        }
+
+        let selected = select_candidate(&candidates, self.adapter_override.as_ref());
+        if selected.is_none() {
+            error!("No adapter meets the renderer's requirements");
+        }
+        selected.map(|candidate| candidate.physical_device)
+    }
+
+    /// Returns a description of the first requirement the adapter does not meet.
+    fn check(&self,
+             instance : &Instance,
+             physical_device : vk::PhysicalDevice,
+             surface : Option<&Surface>) -> Result<(),String> {
+        let (features, queue_families, extensions) = unsafe {
+            let features = instance
+                .ash_instance()
+                .get_physical_device_features(physical_device);
+            let queue_families = instance
+                .ash_instance()
+                .get_physical_device_queue_family_properties(physical_device);
+            let extensions = instance
+                .ash_instance()
+                .enumerate_device_extension_properties(physical_device)
+                .map_err(|error| format!("failed to enumerate extensions ({})", error))?;
+            (features, queue_families, extensions)
+        };
+
+        let missing_extensions : Vec<_> = self.required_extensions
+            .iter()
+            .filter(|required| !extensions
+                .iter()
+                .any(|extension| unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) } == required.as_c_str()))
+            .collect();
+        if !missing_extensions.is_empty() {
+            return Err(format!("missing extensions {:?}", missing_extensions));
+        }
+
+        let missing_features = features_as_slice(&self.required_features)
+            .iter()
+            .zip(features_as_slice(&features))
+            .filter(|(required, supported)| **required == vk::TRUE && **supported != vk::TRUE)
+            .count();
+        if missing_features > 0 {
+            return Err(format!("missing {} required features", missing_features));
+        }
+
+        let supported_queue_flags = queue_families
+            .iter()
+            .fold(vk::QueueFlags::empty(), |flags, family| flags | family.queue_flags);
+        if !supported_queue_flags.contains(self.required_queue_flags) {
+            return Err(format!("no queue families support {:?}", self.required_queue_flags & !supported_queue_flags));
+        }
+
+        if let Some(surface) = surface {
+            let supports_present = |family_index| surface.supports_present(physical_device, family_index);
+            if QueuePlan::new(&queue_families, Some(&supports_present)).is_none() {
+                return Err("no queue family can present to the surface".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An adapter which meets every requirement of a selector.
+struct Candidate {
+    /// The position of the adapter in `Instance::physical_devices`.
+    index : usize,
+    name : String,
+    physical_device : vk::PhysicalDevice,
+    score : u64,
+}
+
+/// Returns the candidate matching the override if there is one, and otherwise the one with the highest score. Ties
+/// go to the adapter with the lowest index.
+fn select_candidate<'a>(candidates : &'a [Candidate], adapter_override : Option<&AdapterOverride>) -> Option<&'a Candidate> {
+    if let Some(adapter_override) = adapter_override {
+        match candidates.iter().find(|candidate| adapter_override.matches(candidate.index, &candidate.name)) {
+            Some(candidate) => {
+                info!("Selected adapter {} \"{}\" from override {:?}", candidate.index, candidate.name, adapter_override);
+                return Some(candidate);
+            },
+            None => warn!("No suitable adapter matches override {:?}, falling back to the highest score",
+                adapter_override),
+        }
+    }
+
+    let selected = candidates
+        .iter()
+        .max_by_key(|candidate| (candidate.score, std::cmp::Reverse(candidate.index)))?;
+    info!("Selected adapter {} \"{}\" with a score of {}", selected.index, selected.name, selected.score);
+    Some(selected)
+}
+
+/// Ranks adapters by type first, then by the size of their largest device local heap in MiB.
+fn score(device_type : vk::PhysicalDeviceType, memory_properties : &vk::PhysicalDeviceMemoryProperties) -> u64 {
+    let type_rank : u64 = match device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+        vk::PhysicalDeviceType::CPU => 1,
+        _ => 0,
+    };
+    let device_local_mib = memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+        .iter()
+        .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|heap| heap.size / (1024 * 1024))
+        .max()
+        .unwrap_or(0);
+    (type_rank << 32) + device_local_mib.min(u32::MAX as u64)
+}
+
+fn device_name(properties : &vk::PhysicalDeviceProperties) -> String {
+    unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Views the features as a slice, as `vk::PhysicalDeviceFeatures` is made up entirely of `vk::Bool32` fields.
+fn features_as_slice(features : &vk::PhysicalDeviceFeatures) -> &[vk::Bool32] {
+    unsafe {
+        slice::from_raw_parts(
+            features as *const vk::PhysicalDeviceFeatures as *const vk::Bool32,
+            size_of::<vk::PhysicalDeviceFeatures>() / size_of::<vk::Bool32>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB : vk::DeviceSize = 1024 * 1024;
+
+    /// Builds memory properties with the given heap sizes and flags.
+    fn memory(heaps : &[(vk::DeviceSize, vk::MemoryHeapFlags)]) -> vk::PhysicalDeviceMemoryProperties {
+        let mut properties = vk::PhysicalDeviceMemoryProperties {
+            memory_heap_count: heaps.len() as u32,
+            ..Default::default()
+        };
+        for (heap, (size, flags)) in properties.memory_heaps.iter_mut().zip(heaps) {
+            *heap = vk::MemoryHeap { size: *size, flags: *flags };
+        }
+        properties
+    }
+
+    fn candidate(index : usize, name : &str, score : u64) -> Candidate {
+        Candidate { index, name: name.to_string(), physical_device: vk::PhysicalDevice::null(), score }
+    }
+
+    fn selected_index(candidates : &[Candidate], adapter_override : Option<&AdapterOverride>) -> Option<usize> {
+        select_candidate(candidates, adapter_override).map(|candidate| candidate.index)
+    }
+
+    #[test]
+    fn parses_overrides() {
+        assert_eq!(AdapterOverride::parse(" 1 "), Some(AdapterOverride::Index(1)));
+        assert_eq!(AdapterOverride::parse("GeForce"), Some(AdapterOverride::Name("GeForce".to_string())));
+        assert_eq!(AdapterOverride::parse("-1"), Some(AdapterOverride::Name("-1".to_string())));
+        assert_eq!(AdapterOverride::parse("  "), None);
+    }
+
+    #[test]
+    fn matches_overrides_by_index_or_name() {
+        assert!(AdapterOverride::Index(1).matches(1, "AMD Radeon"));
+        assert!(!AdapterOverride::Index(1).matches(0, "AMD Radeon"));
+        assert!(AdapterOverride::Name("radeon".to_string()).matches(0, "AMD Radeon RX 6800"));
+        assert!(AdapterOverride::Name("RX 6800".to_string()).matches(3, "amd radeon rx 6800"));
+        assert!(!AdapterOverride::Name("1".to_string()).matches(1, "llvmpipe"));
+    }
+
+    #[test]
+    fn scores_by_type_then_memory() {
+        let small = memory(&[(2048 * MIB, vk::MemoryHeapFlags::DEVICE_LOCAL)]);
+        let large = memory(&[(512 * MIB, vk::MemoryHeapFlags::empty()), (8192 * MIB, vk::MemoryHeapFlags::DEVICE_LOCAL)]);
+        // Host memory is not counted, however large it is.
+        let host = memory(&[(65536 * MIB, vk::MemoryHeapFlags::empty())]);
+
+        assert!(score(vk::PhysicalDeviceType::DISCRETE_GPU, &large) > score(vk::PhysicalDeviceType::DISCRETE_GPU, &small));
+        assert!(score(vk::PhysicalDeviceType::DISCRETE_GPU, &small) > score(vk::PhysicalDeviceType::INTEGRATED_GPU, &large));
+        assert!(score(vk::PhysicalDeviceType::INTEGRATED_GPU, &host) > score(vk::PhysicalDeviceType::VIRTUAL_GPU, &large));
+        assert!(score(vk::PhysicalDeviceType::VIRTUAL_GPU, &host) > score(vk::PhysicalDeviceType::CPU, &large));
+        assert!(score(vk::PhysicalDeviceType::CPU, &host) > score(vk::PhysicalDeviceType::OTHER, &large));
+        assert_eq!(score(vk::PhysicalDeviceType::OTHER, &host), 0);
+    }
+
+    #[test]
+    fn selects_highest_score_breaking_ties_by_index() {
+        let candidates = [candidate(0, "llvmpipe", 10), candidate(1, "Intel UHD", 30), candidate(2, "Intel Arc", 30)];
+        assert_eq!(selected_index(&candidates, None), Some(1));
+        assert_eq!(selected_index(&[], None), None);
+    }
+
+    #[test]
+    fn prefers_suitable_override() {
+        let candidates = [candidate(0, "llvmpipe", 10), candidate(2, "NVIDIA GeForce", 30)];
+        assert_eq!(selected_index(&candidates, Some(&AdapterOverride::Index(0))), Some(0));
+        assert_eq!(selected_index(&candidates, Some(&AdapterOverride::Name("LLVM".to_string()))), Some(0));
+        // Overrides naming a rejected or missing adapter fall back to the highest score.
+        assert_eq!(selected_index(&candidates, Some(&AdapterOverride::Index(1))), Some(2));
+        assert_eq!(selected_index(&candidates, Some(&AdapterOverride::Name("Radeon".to_string()))), Some(2));
+    }
+}
@@ -1,38 +1,64 @@
-use std::{cell::RefCell, mem::size_of, rc::Rc};
+use std::{cell::RefCell, error, fmt, mem::size_of_val, rc::Rc, slice};
 use ash::vk;
-use super::{Device, Material, util::find_memory_type_index};
+use super::{Device, Vertex, error::Error, upload::{UploadId, Uploader}};
+use super::memory::{Allocation, AllocationError, AllocationStrategy, MemoryUsage, ResourceTiling};
 
+/// Provides a brief overview of why a buffer failed to be created.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BufferCreationError {
     AllocationFailed,
     UnsupportedMemoryType,
+    /// No data was given. Vulkan does not allow buffers of zero size.
+    Empty,
+    /// Creating the buffer or binding its memory failed.
+    Vk(vk::Result),
 }
 
+impl fmt::Display for BufferCreationError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BufferCreationError::AllocationFailed => write!(f, "Failed to allocate memory for a buffer"),
+            BufferCreationError::UnsupportedMemoryType => write!(f, "No memory type is suitable for the buffer"),
+            BufferCreationError::Empty => write!(f, "Cannot create a buffer from empty data"),
+            BufferCreationError::Vk(result) => write!(f, "Failed to create a buffer ({})", result),
+        }
+    }
+}
+
+impl error::Error for BufferCreationError {}
+
 pub struct Buffer {
     device : Rc<RefCell<Device>>,
     buffer : vk::Buffer,
-    buffer_memory : vk::DeviceMemory,
+    allocation : Option<Allocation>,
+    size : vk::DeviceSize,
 }
 
 impl Drop for Buffer {
     fn drop(&mut self) {
         unsafe {
             self.device.borrow().ash_device().destroy_buffer(self.buffer, None);
-            self.device.borrow().ash_device().free_memory(self.buffer_memory, None);
         }
+        self.device.borrow().free_memory(self.allocation.take().unwrap());
         info!("Dropped Buffer")
     }
 }
 
-pub struct VertexBuffer {
-    buffer : Buffer,
-}
-
-impl VertexBuffer {
-    pub fn new(device : Rc<RefCell<Device>>, material : &Material) -> Result<Self,BufferCreationError> {
+impl Buffer {
+    /// Creates an exclusive buffer of `size` bytes, sub-allocated from memory suited to `memory_usage` with the given
+    /// strategy. Fails if `size` is zero.
+    fn new(device : Rc<RefCell<Device>>,
+           size : vk::DeviceSize,
+           usage : vk::BufferUsageFlags,
+           memory_usage : MemoryUsage,
+           strategy : AllocationStrategy) -> Result<Self,BufferCreationError> {
+        if size == 0 {
+            return Err(BufferCreationError::Empty);
+        }
         let buffer_info = vk::BufferCreateInfo::builder()
-            .size(material.vertex_buffer_size())
+            .size(size)
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .usage(vk::BufferUsageFlags::VERTEX_BUFFER)
+            .usage(usage)
             .build();
 
         let (buffer, memory_requirements) = unsafe {
@@ -40,56 +66,315 @@ impl VertexBuffer {
                 .borrow()
                 .ash_device()
                 .create_buffer(&buffer_info, None)
-                .expect("Failed to create buffer");
-            let memory_requirements= device.borrow().ash_device().get_buffer_memory_requirements(buffer);
+                .map_err(BufferCreationError::Vk)?;
+            let memory_requirements = device.borrow().ash_device().get_buffer_memory_requirements(buffer);
             (buffer, memory_requirements)
         };
 
-        let memory_properties = device.borrow().memory_properties();
-        let memory_index = find_memory_type_index(&memory_requirements, &memory_properties, vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE);
-        match memory_index {
-            Some(i) => {
-                let allocate_info = vk::MemoryAllocateInfo::builder()
-                    .memory_type_index(i)
-                    .allocation_size(memory_requirements.size)
-                    .build();
-                let buffer_memory = unsafe {
-                    let buffer_memory = device
-                        .borrow()
-                        .ash_device()
-                        .allocate_memory(&allocate_info, None)
-                        .expect("Failed to allocate memory");
-                    device
-                        .borrow()
-                        .ash_device()
-                        .bind_buffer_memory(buffer, buffer_memory, 0)
-                        .expect("Failed to bind buffer memory");
+        let allocation = device.borrow().allocate_memory(
+            &memory_requirements,
+            memory_usage,
+            strategy,
+            ResourceTiling::Linear);
+        match allocation {
+            Ok(allocation) => {
+                let bound = unsafe {
                     device
                         .borrow()
                         .ash_device()
-                        .map_memory(buffer_memory, 0, buffer_info.size, vk::MemoryMapFlags::empty())
-                        .expect("Failed to map buffer memory");
-                    // TODO: memcpy
-                    device
-                        .borrow()
-                        .ash_device()
-                        .unmap_memory(buffer_memory);
-                    buffer_memory
+                        .bind_buffer_memory(buffer, allocation.memory(), allocation.offset())
                 };
-
-                Ok(Self {
-                    buffer : Buffer { device, buffer, buffer_memory }
+                // Dropping the buffer destroys it and frees its memory, whether or not the memory was bound.
+                let buffer = Self { device, buffer, allocation: Some(allocation), size };
+                bound.map_err(BufferCreationError::Vk)?;
+                Ok(buffer)
+            },
+            Err(error) => {
+                unsafe { device.borrow().ash_device().destroy_buffer(buffer, None); }
+                Err(match error {
+                    AllocationError::NoSuitableMemoryType => BufferCreationError::UnsupportedMemoryType,
+                    AllocationError::OutOfMemory(_) => BufferCreationError::AllocationFailed,
                 })
             },
-            None => Err(BufferCreationError::UnsupportedMemoryType),
         }
     }
+
+    pub fn buffer_raw(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    /// Returns the size of the buffer in bytes, as requested when it was created.
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    /// Names the buffer in validation messages and debuggers. Does nothing unless debug utils are enabled.
+    pub fn set_debug_name(&self, name : &str) {
+        self.device.borrow().set_object_name(self.buffer, name);
+    }
+
+    /// Returns the host mapping of the buffer's memory, if it is host visible.
+    fn mapped_slice(&self) -> Option<&[u8]> {
+        self.allocation.as_ref().unwrap().mapped_slice()
+    }
+
+    /// Creates a host visible buffer and copies `data` into it. `memory_usage` must not be `GpuOnly`.
+    fn with_data<T : Copy>(device : Rc<RefCell<Device>>,
+                           data : &[T],
+                           usage : vk::BufferUsageFlags,
+                           memory_usage : MemoryUsage,
+                           strategy : AllocationStrategy) -> Result<Self,BufferCreationError> {
+        let size = size_of_val(data);
+        let mut buffer = Self::new(device, size as vk::DeviceSize, usage, memory_usage, strategy)?;
+        let bytes = unsafe { slice::from_raw_parts(data.as_ptr() as *const u8, size) };
+        buffer.allocation
+            .as_mut()
+            .unwrap()
+            .mapped_slice_mut()
+            .expect("Only GpuOnly memory may not be host visible")[..size]
+            .copy_from_slice(bytes);
+        Ok(buffer)
+    }
+
+    /// Creates a device local buffer and uploads `data` into it through a staging buffer.
+    fn with_upload<T : Copy>(device : Rc<RefCell<Device>>,
+                             uploader : &mut Uploader,
+                             data : &[T],
+                             usage : vk::BufferUsageFlags) -> Result<(Self, UploadId),Error> {
+        let buffer = Self::new(
+            device,
+            size_of_val(data) as vk::DeviceSize,
+            usage | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryUsage::GpuOnly,
+            AllocationStrategy::FreeList)?;
+        let upload = uploader.upload_buffer(&buffer, data)?;
+        Ok((buffer, upload))
+    }
+}
+
+/// Holds the vertices of a mesh, uploaded to memory which the GPU can read directly.
+pub struct VertexBuffer {
+    buffer : Buffer,
+    vertex_count : u32,
+}
+
+impl VertexBuffer {
+    /// Creates a vertex buffer in host visible memory, which the vertices are written to directly. Fails if there are
+    /// no vertices.
+    pub fn new(device : Rc<RefCell<Device>>, vertices : &[Vertex]) -> Result<Self,BufferCreationError> {
+        if vertices.is_empty() {
+            return Err(BufferCreationError::Empty);
+        }
+        let buffer = Buffer::with_data(
+            device,
+            vertices,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            MemoryUsage::CpuToGpu,
+            AllocationStrategy::FreeList)?;
+        Ok(Self { buffer, vertex_count: vertices.len() as u32 })
+    }
+
+    /// Creates a vertex buffer in device local memory, which the vertices are uploaded to through `uploader`. The
+    /// buffer must not be used until the returned upload has completed, or has been submitted before the first use
+    /// on the graphics queue.
+    pub fn new_device_local(device : Rc<RefCell<Device>>,
+                            uploader : &mut Uploader,
+                            vertices : &[Vertex]) -> Result<(Self, UploadId),Error> {
+        if vertices.is_empty() {
+            return Err(BufferCreationError::Empty.into());
+        }
+        let (buffer, upload) = Buffer::with_upload(device, uploader, vertices, vk::BufferUsageFlags::VERTEX_BUFFER)?;
+        Ok((Self { buffer, vertex_count: vertices.len() as u32 }, upload))
+    }
+
+    pub fn buffer_raw(&self) -> vk::Buffer {
+        self.buffer.buffer_raw()
+    }
+
+    pub fn set_debug_name(&self, name : &str) {
+        self.buffer.set_debug_name(name);
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.vertex_count
+    }
+}
+
+mod sealed {
+    /// Prevents `Index` from being implemented outside this module, as `INDEX_TYPE` must match the size of the type.
+    pub trait Sealed {}
+
+    impl Sealed for u16 {}
+    impl Sealed for u32 {}
+}
+
+/// An integer type which can be used as an index into a vertex buffer. Only `u16` and `u32` are supported.
+pub trait Index : Copy + sealed::Sealed {
+    const INDEX_TYPE : vk::IndexType;
 }
 
+impl Index for u16 {
+    const INDEX_TYPE : vk::IndexType = vk::IndexType::UINT16;
+}
+
+impl Index for u32 {
+    const INDEX_TYPE : vk::IndexType = vk::IndexType::UINT32;
+}
+
+/// Holds the indices of a mesh, allowing triangles to share vertices.
 pub struct IndexBuffer {
     buffer : Buffer,
+    index_count : u32,
+    index_type : vk::IndexType,
+}
+
+impl IndexBuffer {
+    /// Uploads 16 or 32 bit indices. Prefer `u16` indices when the mesh has fewer than 65536 vertices. Fails if there
+    /// are no indices.
+    pub fn new<I : Index>(device : Rc<RefCell<Device>>, indices : &[I]) -> Result<Self,BufferCreationError> {
+        if indices.is_empty() {
+            return Err(BufferCreationError::Empty);
+        }
+        let buffer = Buffer::with_data(
+            device,
+            indices,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            MemoryUsage::CpuToGpu,
+            AllocationStrategy::FreeList)?;
+        Ok(Self { buffer, index_count: indices.len() as u32, index_type: I::INDEX_TYPE })
+    }
+
+    /// Uploads the indices to device local memory through `uploader`, with the same restrictions as
+    /// `VertexBuffer::new_device_local`.
+    pub fn new_device_local<I : Index>(device : Rc<RefCell<Device>>,
+                                       uploader : &mut Uploader,
+                                       indices : &[I]) -> Result<(Self, UploadId),Error> {
+        if indices.is_empty() {
+            return Err(BufferCreationError::Empty.into());
+        }
+        let (buffer, upload) = Buffer::with_upload(device, uploader, indices, vk::BufferUsageFlags::INDEX_BUFFER)?;
+        Ok((Self { buffer, index_count: indices.len() as u32, index_type: I::INDEX_TYPE }, upload))
+    }
+
+    pub fn buffer_raw(&self) -> vk::Buffer {
+        self.buffer.buffer_raw()
+    }
+
+    pub fn set_debug_name(&self, name : &str) {
+        self.buffer.set_debug_name(name);
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.index_count
+    }
+
+    pub fn index_type(&self) -> vk::IndexType {
+        self.index_type
+    }
 }
 
+/// Host visible memory holding data which is copied into device local buffers and images by an `Uploader`.
 pub struct StagingBuffer {
     buffer : Buffer,
-}
\ No newline at end of file
+    size : vk::DeviceSize,
+}
+
+impl StagingBuffer {
+    /// Creates a staging buffer holding `data`. Staging buffers are short-lived and released together once their
+    /// uploads complete, so they are bump allocated from linear blocks, which are reset once they are empty.
+    pub fn new<T : Copy>(device : Rc<RefCell<Device>>, data : &[T]) -> Result<Self,BufferCreationError> {
+        let buffer = Buffer::with_data(
+            device,
+            data,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryUsage::CpuToGpu,
+            AllocationStrategy::Linear)?;
+        Ok(Self { buffer, size: size_of_val(data) as vk::DeviceSize })
+    }
+
+    pub fn buffer_raw(&self) -> vk::Buffer {
+        self.buffer.buffer_raw()
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+}
+
+/// A host visible buffer which the GPU copies into, so that the results can be read on the CPU.
+pub struct ReadbackBuffer {
+    buffer : Buffer,
+    size : vk::DeviceSize,
+}
+
+impl ReadbackBuffer {
+    pub fn new(device : Rc<RefCell<Device>>, size : vk::DeviceSize) -> Result<Self,BufferCreationError> {
+        let buffer = Buffer::new(
+            device,
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryUsage::GpuToCpu,
+            AllocationStrategy::FreeList)?;
+        Ok(Self { buffer, size })
+    }
+
+    /// Copies the contents of the buffer into host memory. Any GPU writes to the buffer must have completed.
+    pub fn read(&self) -> Vec<u8> {
+        let mapped = self.buffer
+            .mapped_slice()
+            .expect("Readback buffers are always host visible");
+        mapped[..self.size as usize].to_vec()
+    }
+
+    pub fn buffer_raw(&self) -> vk::Buffer {
+        self.buffer.buffer_raw()
+    }
+
+    pub fn set_debug_name(&self, name : &str) {
+        self.buffer.set_debug_name(name);
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+}
+
+/// A host visible buffer which shaders can read and write, such as the inputs and outputs of compute work. It can also
+/// hold the arguments of indirect dispatches and draws.
+pub struct StorageBuffer {
+    buffer : Buffer,
+    size : vk::DeviceSize,
+}
+
+impl StorageBuffer {
+    /// Creates a storage buffer holding `data`.
+    pub fn new<T : Copy>(device : Rc<RefCell<Device>>, data : &[T]) -> Result<Self,BufferCreationError> {
+        let usage = vk::BufferUsageFlags::STORAGE_BUFFER
+            | vk::BufferUsageFlags::INDIRECT_BUFFER
+            | vk::BufferUsageFlags::TRANSFER_SRC
+            | vk::BufferUsageFlags::TRANSFER_DST;
+        // Results are read back on the CPU, which is faster from cached memory.
+        let buffer = Buffer::with_data(device, data, usage, MemoryUsage::GpuToCpu, AllocationStrategy::FreeList)?;
+        Ok(Self { buffer, size: size_of_val(data) as vk::DeviceSize })
+    }
+
+    /// Copies the contents of the buffer into host memory. Any GPU writes to the buffer must have completed.
+    pub fn read(&self) -> Vec<u8> {
+        let mapped = self.buffer
+            .mapped_slice()
+            .expect("Storage buffers are always host visible");
+        mapped[..self.size as usize].to_vec()
+    }
+
+    pub fn buffer_raw(&self) -> vk::Buffer {
+        self.buffer.buffer_raw()
+    }
+
+    pub fn set_debug_name(&self, name : &str) {
+        self.buffer.set_debug_name(name);
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+}
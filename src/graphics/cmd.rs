@@ -1,6 +1,9 @@
 use std::{cell::RefCell, rc::Rc};
 use ash::vk;
-use super::{Device, Framebuffer, Pipeline, Queue, RenderPass};
+use super::{Device, Framebuffer, Image, IndexBuffer, Pipeline, Queue, RenderPass, VertexBuffer};
+use super::buffer::{Buffer, ReadbackBuffer, StagingBuffer, StorageBuffer};
+use super::descriptor::DescriptorSet;
+use super::error::{Error, VkResultExt};
 
 /// Specifices the state which will be used for Command Buffers.
 pub struct CmdState {
@@ -13,12 +16,16 @@ pub struct CmdBuffer {
     cmd_pool : Rc<RefCell<CmdPool>>,
     cmd_buffer : vk::CommandBuffer,
     recording : bool,
+    /// Set when the last submission is known to have completed, so the command buffer can be freed without waiting.
+    completed : bool,
 }
 
 impl Drop for CmdBuffer {
     fn drop(&mut self) {
         unsafe {
-            self.device.borrow().ash_device().device_wait_idle().unwrap();
+            if !self.completed {
+                self.device.borrow().ash_device().device_wait_idle().unwrap();
+            }
             self.device
                 .borrow()
                 .ash_device()
@@ -31,7 +38,7 @@ impl Drop for CmdBuffer {
 /// A recorder for graphics, compute, or transfer operations.
 impl CmdBuffer {
     pub fn new(device : Rc<RefCell<Device>>,
-               cmd_pool : Rc<RefCell<CmdPool>>) -> Self {
+               cmd_pool : Rc<RefCell<CmdPool>>) -> Result<Self,Error> {
         let cmd_buffer_info = vk::CommandBufferAllocateInfo::builder()
             .command_pool(cmd_pool.borrow().cmd_pool_raw())
             .command_buffer_count(1)
@@ -41,47 +48,296 @@ impl CmdBuffer {
                 .borrow()
                 .ash_device()
                 .allocate_command_buffers(&cmd_buffer_info)
-                .expect("Failed to create command buffer")
+                .context("Failed to allocate a command buffer")?
                 .remove(0)
         };
 
-        Self { device, cmd_pool, cmd_buffer, recording: false }
+        Ok(Self { device, cmd_pool, cmd_buffer, recording: false, completed: false })
     }
 
-    // Records graphics commands to the command buffer.
-    pub fn record_graphics(&mut self,
-                           state : CmdState,
-                           render_pass : &RenderPass,
-                           framebuffer : &Framebuffer,
-                           pipeline : &Pipeline) {
+    /// Frees the command buffer without waiting for the device to become idle. Its last submission must have
+    /// completed, such as when a fence signalled by the submission has been waited on.
+    pub fn free_completed(mut self) {
+        self.completed = true;
+    }
+
+    /// Resets the command buffer and begins recording a single submission.
+    pub fn begin(&mut self) -> Result<(),Error> {
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
         unsafe {
-            // Wait for any device operations to complete before resetting command buffer.
             self.device
                 .borrow()
                 .ash_device()
-                .device_wait_idle()
-                .unwrap();
+                .reset_command_buffer(
+                    self.cmd_buffer,
+                    vk::CommandBufferResetFlags::RELEASE_RESOURCES)
+                .context("Failed to reset a command buffer")?;
             self.device
                 .borrow()
                 .ash_device()
-                .reset_command_buffer(
+                .begin_command_buffer(
                     self.cmd_buffer,
-                    vk::CommandBufferResetFlags::RELEASE_RESOURCES)
-                .unwrap();
+                    &begin_info)
+                .context("Failed to begin a command buffer")?;
         }
+        self.recording = true;
+        Ok(())
+    }
 
-        let begin_info = vk::CommandBufferBeginInfo::builder()
-            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    /// Finishes recording, after which the command buffer can be submitted to a queue.
+    pub fn end(&mut self) -> Result<(),Error> {
+        // Recording has stopped even if ending fails, as the command buffer is then in the invalid state.
+        self.recording = false;
         unsafe {
             self.device
                 .borrow()
                 .ash_device()
-                .begin_command_buffer(
+                .end_command_buffer(self.cmd_buffer)
+                .context("Failed to end a command buffer")
+        }
+    }
+
+    /// Records a copy of the whole color image into a tightly packed buffer. The image must be in `layout`, which has
+    /// to be either `TRANSFER_SRC_OPTIMAL` or `GENERAL`.
+    pub fn copy_image_to_buffer(&self, image : &Image, layout : vk::ImageLayout, buffer : &ReadbackBuffer) {
+        debug_assert!(self.recording);
+        let extent = image.extent();
+        let region = vk::BufferImageCopy::builder()
+            .image_subresource(vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .layer_count(1)
+                .build())
+            .image_extent(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 })
+            .build();
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .cmd_copy_image_to_buffer(self.cmd_buffer, image.image_raw(), layout, buffer.buffer_raw(), &[region]);
+        }
+    }
+
+    /// Records a copy of the whole staging buffer to the start of `buffer`, which must be at least as large.
+    pub fn copy_buffer(&self, staging_buffer : &StagingBuffer, buffer : &Buffer) {
+        debug_assert!(self.recording);
+        let region = vk::BufferCopy::builder()
+            .size(staging_buffer.size())
+            .build();
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .cmd_copy_buffer(self.cmd_buffer, staging_buffer.buffer_raw(), buffer.buffer_raw(), &[region]);
+        }
+    }
+
+    /// Records a copy of tightly packed texels from the staging buffer into the whole color image. The image must be
+    /// in `layout`, which has to be either `TRANSFER_DST_OPTIMAL` or `GENERAL`.
+    pub fn copy_buffer_to_image(&self, staging_buffer : &StagingBuffer, image : &Image, layout : vk::ImageLayout) {
+        debug_assert!(self.recording);
+        let extent = image.extent();
+        let region = vk::BufferImageCopy::builder()
+            .image_subresource(vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .layer_count(1)
+                .build())
+            .image_extent(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 })
+            .build();
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .cmd_copy_buffer_to_image(self.cmd_buffer, staging_buffer.buffer_raw(), image.image_raw(), layout, &[region]);
+        }
+    }
+
+    /// Records a pipeline barrier, which is also used to transition image layouts and to release or acquire
+    /// ownership of resources between queue families.
+    pub fn pipeline_barrier(&self,
+                            src_stage : vk::PipelineStageFlags,
+                            dst_stage : vk::PipelineStageFlags,
+                            buffer_barriers : &[vk::BufferMemoryBarrier],
+                            image_barriers : &[vk::ImageMemoryBarrier]) {
+        debug_assert!(self.recording);
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .cmd_pipeline_barrier(
                     self.cmd_buffer,
-                    &begin_info)
-                .unwrap();
+                    src_stage,
+                    dst_stage,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    buffer_barriers,
+                    image_barriers);
         }
-        self.recording = true;
+    }
+
+    /// Inserts a global memory barrier. `src` and `dst` each pair a pipeline stage with an access mask, making writes
+    /// of `src.1` in `src.0` visible to accesses of `dst.1` in `dst.0`.
+    pub fn memory_barrier(&self,
+                          src : (vk::PipelineStageFlags, vk::AccessFlags),
+                          dst : (vk::PipelineStageFlags, vk::AccessFlags)) {
+        debug_assert!(self.recording);
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(src.1)
+            .dst_access_mask(dst.1)
+            .build();
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .cmd_pipeline_barrier(self.cmd_buffer, src.0, dst.0, vk::DependencyFlags::empty(), &[barrier], &[], &[]);
+        }
+    }
+
+    /// Binds a graphics or compute pipeline for the commands which follow.
+    pub fn bind_pipeline(&self, pipeline : &Pipeline) {
+        debug_assert!(self.recording);
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .cmd_bind_pipeline(self.cmd_buffer, pipeline.bind_point(), pipeline.pipeline_raw());
+        }
+    }
+
+    /// Binds descriptor sets to consecutive set numbers, starting at `first_set`, of the pipeline's layout.
+    pub fn bind_descriptor_sets(&self, pipeline : &Pipeline, first_set : u32, sets : &[&DescriptorSet]) {
+        debug_assert!(self.recording);
+        let sets : Vec<_> = sets.iter().map(|set| set.set_raw()).collect();
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .cmd_bind_descriptor_sets(
+                    self.cmd_buffer,
+                    pipeline.bind_point(),
+                    pipeline.layout_raw(),
+                    first_set,
+                    &sets,
+                    &[]);
+        }
+    }
+
+    /// Writes `data` to the push constants of the pipeline's layout at `offset` bytes.
+    pub fn push_constants<T : Copy>(&self, pipeline : &Pipeline, stages : vk::ShaderStageFlags, offset : u32, data : &[T]) {
+        debug_assert!(self.recording);
+        let bytes = unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data)) };
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .cmd_push_constants(self.cmd_buffer, pipeline.layout_raw(), stages, offset, bytes);
+        }
+    }
+
+    /// Dispatches the bound compute pipeline over a grid of workgroups.
+    pub fn dispatch(&self, group_count_x : u32, group_count_y : u32, group_count_z : u32) {
+        debug_assert!(self.recording);
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .cmd_dispatch(self.cmd_buffer, group_count_x, group_count_y, group_count_z);
+        }
+    }
+
+    /// Dispatches the bound compute pipeline, reading the workgroup counts as three `u32` values from `buffer` at
+    /// `offset` bytes. The counts can be written by earlier GPU work.
+    pub fn dispatch_indirect(&self, buffer : &StorageBuffer, offset : vk::DeviceSize) {
+        debug_assert!(self.recording);
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .cmd_dispatch_indirect(self.cmd_buffer, buffer.buffer_raw(), offset);
+        }
+    }
+
+    /// Sets the dynamic viewport, which maps normalized device coordinates to the render target.
+    pub fn set_viewport(&self, viewport : vk::Viewport) {
+        debug_assert!(self.recording);
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .cmd_set_viewport(self.cmd_buffer, 0, &[viewport]);
+        }
+    }
+
+    /// Sets the dynamic scissor, outside of which fragments are discarded.
+    pub fn set_scissor(&self, scissor : vk::Rect2D) {
+        debug_assert!(self.recording);
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .cmd_set_scissor(self.cmd_buffer, 0, &[scissor]);
+        }
+    }
+
+    /// Binds the vertex buffer to binding 0 for subsequent draws.
+    pub fn bind_vertex_buffer(&self, vertex_buffer : &VertexBuffer) {
+        debug_assert!(self.recording);
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .cmd_bind_vertex_buffers(self.cmd_buffer, 0, &[vertex_buffer.buffer_raw()], &[0]);
+        }
+    }
+
+    /// Draws non-indexed vertices from the bound vertex buffers.
+    pub fn draw(&self, vertex_count : u32, instance_count : u32, first_vertex : u32, first_instance : u32) {
+        debug_assert!(self.recording);
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .cmd_draw(self.cmd_buffer, vertex_count, instance_count, first_vertex, first_instance);
+        }
+    }
+
+    /// Binds the index buffer for subsequent indexed draws.
+    pub fn bind_index_buffer(&self, index_buffer : &IndexBuffer) {
+        debug_assert!(self.recording);
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .cmd_bind_index_buffer(self.cmd_buffer, index_buffer.buffer_raw(), 0, index_buffer.index_type());
+        }
+    }
+
+    /// Draws vertices using the bound index buffer. `vertex_offset` is added to each index before fetching vertices.
+    pub fn draw_indexed(&self,
+                        index_count : u32,
+                        instance_count : u32,
+                        first_index : u32,
+                        vertex_offset : i32,
+                        first_instance : u32) {
+        debug_assert!(self.recording);
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .cmd_draw_indexed(self.cmd_buffer, index_count, instance_count, first_index, vertex_offset, first_instance);
+        }
+    }
+
+    // Records graphics commands to the command buffer. If an index buffer is provided, the mesh is drawn indexed.
+    // The command buffer must not be executing, which the owning `Frame` guarantees once it has been waited on.
+    pub fn record_graphics(&mut self,
+                           state : CmdState,
+                           render_pass : &RenderPass,
+                           framebuffer : &Framebuffer,
+                           pipeline : &Pipeline,
+                           vertex_buffer : &VertexBuffer,
+                           index_buffer : Option<&IndexBuffer>) -> Result<(),Error> {
+        self.begin()?;
 
         let clear_values = vec![
             vk::ClearValue { color: vk::ClearColorValue { float32: [0.39, 0.58, 0.94, 1.0] } }];
@@ -94,6 +350,7 @@ impl CmdBuffer {
                 .extent(state.extent)
                 .build());
 
+        self.begin_label("Main pass", [0.39, 0.58, 0.94, 1.0]);
         unsafe {
             self.device
                 .borrow()
@@ -105,23 +362,54 @@ impl CmdBuffer {
                 .borrow()
                 .ash_device()
                 .cmd_bind_pipeline(self.cmd_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline_raw());
-            self.device
-                .borrow()
-                .ash_device()
-                .cmd_draw(self.cmd_buffer, 3, 1, 0, 0);
+        }
+        self.set_viewport(vk::Viewport::builder()
+            .width(state.extent.width as _)
+            .height(state.extent.height as _)
+            .max_depth(1.0)
+            .build());
+        self.set_scissor(vk::Rect2D::builder()
+            .extent(state.extent)
+            .build());
+        self.bind_vertex_buffer(vertex_buffer);
+        match index_buffer {
+            Some(index_buffer) => {
+                self.bind_index_buffer(index_buffer);
+                self.draw_indexed(index_buffer.index_count(), 1, 0, 0, 0);
+            },
+            None => self.draw(vertex_buffer.vertex_count(), 1, 0, 0),
+        }
+        unsafe {
             // End of render pass.
             // =====================================================================================
             self.device
                 .borrow()
                 .ash_device()
                 .cmd_end_render_pass(self.cmd_buffer);
-            self.device
-                .borrow()
-                .ash_device()
-                .end_command_buffer(self.cmd_buffer)
-                .unwrap();
         }
-        self.recording = false;
+        self.end_label();
+        self.end()
+    }
+
+    /// Names the command buffer in validation messages and debuggers. Does nothing unless debug utils are enabled.
+    pub fn set_debug_name(&self, name : &str) {
+        self.device.borrow().set_object_name(self.cmd_buffer, name);
+    }
+
+    /// Opens a labelled region of commands, which debuggers such as RenderDoc show as a group. Each region must be
+    /// closed by `end_label` before recording ends. Does nothing unless debug utils are enabled.
+    pub fn begin_label(&self, name : &str, color : [f32; 4]) {
+        self.device.borrow().begin_cmd_label(self.cmd_buffer, name, color);
+    }
+
+    /// Closes the most recently opened label region.
+    pub fn end_label(&self) {
+        self.device.borrow().end_cmd_label(self.cmd_buffer);
+    }
+
+    /// Inserts a single label between commands.
+    pub fn insert_label(&self, name : &str, color : [f32; 4]) {
+        self.device.borrow().insert_cmd_label(self.cmd_buffer, name, color);
     }
 
     pub fn cmd_buffer_raw(&self) -> vk::CommandBuffer {
@@ -147,7 +435,7 @@ impl Drop for CmdPool {
 
 impl CmdPool {
     pub fn new(device : Rc<RefCell<Device>>,
-               queue : &Queue) -> Self {
+               queue : &Queue) -> Result<Self,Error> {
         let cmd_pool_info = vk::CommandPoolCreateInfo::builder()
             .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
             .queue_family_index(queue.family_index());
@@ -156,19 +444,19 @@ impl CmdPool {
                 .borrow()
                 .ash_device()
                 .create_command_pool(&cmd_pool_info, None)
-                .expect("Failed to create command pool")
+                .context("Failed to create a command pool")?
         };
 
-        Self { device, cmd_pool }
+        Ok(Self { device, cmd_pool })
     }
 
-    pub fn reset(&self) {
+    pub fn reset(&self) -> Result<(),Error> {
         unsafe {
             self.device
                 .borrow()
                 .ash_device()
                 .reset_command_pool(self.cmd_pool, vk::CommandPoolResetFlags::RELEASE_RESOURCES)
-                .unwrap();
+                .context("Failed to reset a command pool")
         }
     }
 
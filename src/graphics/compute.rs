@@ -0,0 +1,56 @@
+use std::{cell::RefCell, rc::Rc};
+use ash::vk;
+use super::{CmdBuffer, CmdPool, Device, Queue, error::{Error, VkResultExt}};
+
+/// Submits compute work to a queue and waits for it, so its results can be read on the CPU straight away. This suits
+/// one-off work such as precomputing data, and tests.
+pub struct ComputeContext {
+    device : Rc<RefCell<Device>>,
+    queue : Rc<RefCell<Queue>>,
+    cmd_pool : Rc<RefCell<CmdPool>>,
+}
+
+impl ComputeContext {
+    pub fn new(device : Rc<RefCell<Device>>, queue : Rc<RefCell<Queue>>) -> Result<Self,Error> {
+        let cmd_pool = Rc::new(RefCell::new(CmdPool::new(Rc::clone(&device), &queue.borrow())?));
+        Ok(Self { device, queue, cmd_pool })
+    }
+
+    /// Records commands with `record`, submits them and blocks until they have completed. Shader writes are made
+    /// visible to the host, so host visible buffers such as a `StorageBuffer` can be read once this returns.
+    pub fn run<F>(&self, record : F) -> Result<(),Error>
+        where F : FnOnce(&CmdBuffer) {
+        let mut cmd_buffer = CmdBuffer::new(Rc::clone(&self.device), Rc::clone(&self.cmd_pool))?;
+        cmd_buffer.begin()?;
+        record(&cmd_buffer);
+        cmd_buffer.memory_barrier(
+            (vk::PipelineStageFlags::ALL_COMMANDS, vk::AccessFlags::MEMORY_WRITE),
+            (vk::PipelineStageFlags::HOST, vk::AccessFlags::HOST_READ));
+        cmd_buffer.end()?;
+
+        let device = self.device.borrow();
+        let fence = unsafe {
+            device
+                .ash_device()
+                .create_fence(&vk::FenceCreateInfo::builder(), None)
+                .context("Failed to create a compute fence")?
+        };
+        self.queue
+            .borrow()
+            .submit_offscreen(&cmd_buffer, Some(fence))
+            .inspect_err(|_| unsafe { device.ash_device().destroy_fence(fence, None) })?;
+        let result = unsafe {
+            let result = device
+                .ash_device()
+                .wait_for_fences(&[fence], true, u64::MAX)
+                .context("Failed to wait for compute work");
+            device.ash_device().destroy_fence(fence, None);
+            result
+        };
+        result
+    }
+
+    pub fn queue(&self) -> Rc<RefCell<Queue>> {
+        Rc::clone(&self.queue)
+    }
+}
@@ -1,20 +1,188 @@
-use std::ffi::CStr;
-use std::os::raw::c_void;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_void};
+use std::slice;
+use std::sync::Mutex;
 use ash::vk;
 
+/// Controls the validation layer and which of its messages are reported. Validation is enabled by default on debug
+/// builds, reporting warnings and errors of every type.
+#[derive(Clone, Debug)]
+pub struct ValidationSettings {
+    /// Requests the validation layer and debug messenger. Both are skipped if they are not installed.
+    pub enabled : bool,
+    /// The severities which are reported.
+    pub severities : vk::DebugUtilsMessageSeverityFlagsEXT,
+    /// The message types which are reported.
+    pub message_types : vk::DebugUtilsMessageTypeFlagsEXT,
+    /// Instruments shaders to validate descriptor indexing and buffer accesses on the GPU. This is slow, and
+    /// reserves a descriptor set binding slot.
+    pub gpu_assisted : bool,
+    /// Reports missing or incorrect synchronization between commands, such as read-after-write hazards.
+    pub synchronization : bool,
+    /// Message ID names, such as `VUID-vkCmdDraw-None-02699`, which are never reported.
+    pub suppressed_message_ids : Vec<String>,
+    /// Records every reported message, so they can be retrieved with `Instance::take_validation_messages`. This is
+    /// enabled by default in tests, so they can assert that no validation errors were reported.
+    pub collect_messages : bool,
+}
+
+impl Default for ValidationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: cfg!(debug_assertions),
+            severities: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            message_types: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            gpu_assisted: false,
+            synchronization: false,
+            suppressed_message_ids: Vec::new(),
+            collect_messages: cfg!(test),
+        }
+    }
+}
+
+impl ValidationSettings {
+    /// Settings with validation turned off.
+    pub fn disabled() -> Self {
+        Self { enabled: false, ..Self::default() }
+    }
+
+    /// Returns the validation features to enable through `VkValidationFeaturesEXT`.
+    pub(crate) fn enabled_features(&self) -> Vec<vk::ValidationFeatureEnableEXT> {
+        let mut features = Vec::new();
+        if self.gpu_assisted {
+            features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+            features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT);
+        }
+        if self.synchronization {
+            features.push(vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION);
+        }
+        features
+    }
+}
+
+/// A message reported by the debug messenger.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationMessage {
+    pub severity : vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_types : vk::DebugUtilsMessageTypeFlagsEXT,
+    /// The message ID name, such as `VUID-vkCmdDraw-None-02699`, which may be empty.
+    pub message_id_name : String,
+    pub message_id_number : i32,
+    pub message : String,
+}
+
+impl ValidationMessage {
+    pub fn is_error(&self) -> bool {
+        self.severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+    }
+}
+
+/// State shared with `debug_utils_callback` through its user data pointer. It is boxed by the instance, so its
+/// address remains stable for the lifetime of the messenger.
+pub(crate) struct MessengerData {
+    suppressed_message_ids : Vec<String>,
+    /// The callback may be invoked from any thread which makes Vulkan calls, so collected messages are locked.
+    messages : Option<Mutex<Vec<ValidationMessage>>>,
+}
+
+impl MessengerData {
+    pub(crate) fn new(settings : &ValidationSettings) -> Self {
+        Self {
+            suppressed_message_ids: settings.suppressed_message_ids.clone(),
+            messages: if settings.collect_messages { Some(Mutex::new(Vec::new())) } else { None },
+        }
+    }
+
+    /// Returns the messages collected since the last call, or nothing if messages are not being collected.
+    pub(crate) fn take_messages(&self) -> Vec<ValidationMessage> {
+        match &self.messages {
+            Some(messages) => std::mem::take(&mut *messages.lock().unwrap()),
+            None => Vec::new(),
+        }
+    }
+
+    fn is_suppressed(&self, message_id_name : &str) -> bool {
+        self.suppressed_message_ids.iter().any(|suppressed| suppressed == message_id_name)
+    }
+}
+
+/// Logs messages from the debug messenger, along with their message ID and the objects involved, and collects them
+/// if the messenger was created to do so.
+///
+/// # Safety
+///
+/// Must only be called by the Vulkan loader. `p_user_data` must point to the `MessengerData` the messenger was
+/// created with.
 pub unsafe extern "system" fn debug_utils_callback(
-    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT, 
-    message_types: vk::DebugUtilsMessageTypeFlagsEXT, 
-    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT, 
-    p_user_data: *mut c_void
+    message_severity : vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_types : vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data : *const vk::DebugUtilsMessengerCallbackDataEXT,
+    p_user_data : *mut c_void
 ) -> vk::Bool32 {
-    let message = (*p_callback_data).p_message;
-    match message_severity {
-        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => debug!("{:?}", CStr::from_ptr(message)),
-        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => info!("{:?}", CStr::from_ptr(message)),
-        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => warn!("{:?}", CStr::from_ptr(message)),
-        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => error!("{:?}", CStr::from_ptr(message)),
-        _ => ()
-    }    
+    let callback_data = &*p_callback_data;
+    let message_id_name = string_or_empty(callback_data.p_message_id_name);
+    let message = string_or_empty(callback_data.p_message);
+    if let Some(data) = (p_user_data as *const MessengerData).as_ref() {
+        if data.is_suppressed(&message_id_name) {
+            return vk::FALSE;
+        }
+        if let Some(messages) = &data.messages {
+            messages.lock().unwrap().push(ValidationMessage {
+                severity: message_severity,
+                message_types,
+                message_id_name: message_id_name.clone(),
+                message_id_number: callback_data.message_id_number,
+                message: message.clone(),
+            });
+        }
+    }
+
+    let level = match message_severity {
+        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => log::Level::Debug,
+        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => log::Level::Info,
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => log::Level::Warn,
+        _ => log::Level::Error,
+    };
+    let objects = if callback_data.object_count == 0 {
+        Vec::new()
+    } else {
+        slice::from_raw_parts(callback_data.p_objects, callback_data.object_count as usize)
+            .iter()
+            .map(|object| format_object(object))
+            .collect()
+    };
+    log!(target: "vulkan",
+        level,
+        "[{:?}] {} ({:#x}): {} objects: [{}]",
+        message_types,
+        message_id_name,
+        callback_data.message_id_number,
+        message,
+        objects.join(", "));
     vk::FALSE
-}
\ No newline at end of file
+}
+
+/// Describes an object as its type and handle, along with its debug name if it has one.
+unsafe fn format_object(object : &vk::DebugUtilsObjectNameInfoEXT) -> String {
+    let name = string_or_empty(object.p_object_name);
+    if name.is_empty() {
+        format!("{:?} {:#x}", object.object_type, object.object_handle)
+    } else {
+        format!("{:?} {:#x} \"{}\"", object.object_type, object.object_handle, name)
+    }
+}
+
+unsafe fn string_or_empty(string : *const c_char) -> String {
+    if string.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(string).to_string_lossy().into_owned()
+    }
+}
+
+/// Converts a debug name or label into a C string, dropping any nul bytes.
+pub(crate) fn debug_name(name : &str) -> CString {
+    CString::new(name.replace('\0', "")).unwrap()
+}
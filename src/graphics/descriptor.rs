@@ -0,0 +1,148 @@
+use std::{cell::RefCell, rc::Rc};
+use ash::vk;
+use super::{Device, error::{Error, VkResultExt}};
+
+/// Describes the resources bound to one descriptor set of a pipeline.
+pub struct DescriptorSetLayout {
+    device : Rc<RefCell<Device>>,
+    layout : vk::DescriptorSetLayout,
+}
+
+impl Drop for DescriptorSetLayout {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.borrow().ash_device().destroy_descriptor_set_layout(self.layout, None);
+        }
+        info!("Dropped DescriptorSetLayout")
+    }
+}
+
+impl DescriptorSetLayout {
+    pub fn layout_raw(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+}
+
+pub struct DescriptorSetLayoutBuilder {
+    device : Rc<RefCell<Device>>,
+    bindings : Vec<vk::DescriptorSetLayoutBinding>,
+}
+
+impl DescriptorSetLayoutBuilder {
+    pub fn new(device : Rc<RefCell<Device>>) -> Self {
+        Self { device, bindings: Vec::new() }
+    }
+
+    /// Adds a binding of `count` descriptors, which can be accessed by the given shader stages.
+    pub fn add_binding(mut self,
+                       binding : u32,
+                       descriptor_type : vk::DescriptorType,
+                       count : u32,
+                       stages : vk::ShaderStageFlags) -> Self {
+        self.bindings.push(vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(descriptor_type)
+            .descriptor_count(count)
+            .stage_flags(stages)
+            .build());
+        self
+    }
+
+    pub fn build(self) -> Result<DescriptorSetLayout,Error> {
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(self.bindings.as_slice());
+        let layout = unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .create_descriptor_set_layout(&layout_info, None)
+                .context("Failed to create a descriptor set layout")?
+        };
+        Ok(DescriptorSetLayout { device: self.device, layout })
+    }
+}
+
+/// Allocates descriptor sets. Sets are freed together when the pool is dropped.
+pub struct DescriptorPool {
+    device : Rc<RefCell<Device>>,
+    pool : vk::DescriptorPool,
+}
+
+impl Drop for DescriptorPool {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.borrow().ash_device().destroy_descriptor_pool(self.pool, None);
+        }
+        info!("Dropped DescriptorPool")
+    }
+}
+
+impl DescriptorPool {
+    /// Creates a pool which can allocate up to `max_sets` sets, holding at most the given number of descriptors of
+    /// each type in total.
+    pub fn new(device : Rc<RefCell<Device>>,
+               max_sets : u32,
+               pool_sizes : &[vk::DescriptorPoolSize]) -> Result<Self,Error> {
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(max_sets)
+            .pool_sizes(pool_sizes);
+        let pool = unsafe {
+            device
+                .borrow()
+                .ash_device()
+                .create_descriptor_pool(&pool_info, None)
+                .context("Failed to create a descriptor pool")?
+        };
+        Ok(Self { device, pool })
+    }
+
+    /// Allocates a set with the given layout. Its descriptors must be written before it is bound.
+    pub fn allocate(&self, layout : &DescriptorSetLayout) -> Result<DescriptorSet,Error> {
+        let layouts = [layout.layout_raw()];
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(&layouts);
+        let set = unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .allocate_descriptor_sets(&allocate_info)
+                .context("Failed to allocate a descriptor set")?
+                .remove(0)
+        };
+        Ok(DescriptorSet { device: Rc::clone(&self.device), set })
+    }
+
+    pub fn pool_raw(&self) -> vk::DescriptorPool {
+        self.pool
+    }
+}
+
+/// A set of descriptors allocated from a `DescriptorPool`, which must outlive it.
+pub struct DescriptorSet {
+    device : Rc<RefCell<Device>>,
+    set : vk::DescriptorSet,
+}
+
+impl DescriptorSet {
+    /// Points the descriptor at `binding` to the whole of `buffer`. The set must not be in use by the GPU.
+    pub fn write_buffer(&self, binding : u32, descriptor_type : vk::DescriptorType, buffer : vk::Buffer) {
+        let buffer_infos = [vk::DescriptorBufferInfo::builder()
+            .buffer(buffer)
+            .range(vk::WHOLE_SIZE)
+            .build()];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(binding)
+            .descriptor_type(descriptor_type)
+            .buffer_info(&buffer_infos)
+            .build();
+        unsafe {
+            self.device.borrow().ash_device().update_descriptor_sets(&[write], &[]);
+        }
+    }
+
+    pub fn set_raw(&self) -> vk::DescriptorSet {
+        self.set
+    }
+}
@@ -1,27 +1,52 @@
-use std::{cell::RefCell, rc::Rc};
-use ash::extensions::khr::Swapchain;
+use std::{cell::RefCell, error, ffi::{CStr, CString}, fmt, path::Path};
+use ash::extensions::{ext::DebugUtils, khr::Swapchain};
 use ash::vk;
-use super::{Instance, Queue};
+use super::{Instance, Surface, adapter::AdapterSelector, debug::debug_name, error::{Error, VkResultExt}};
+use super::pipeline_cache::PipelineCache;
+use super::memory::{Allocation, AllocationError, AllocationStrategy, Allocator, MemoryStats, MemoryUsage, ResourceTiling};
 
+/// Provides a brief overview of why a device failed to be created.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum DeviceCreationError {
-    MissingExtensions
+    MissingExtensions,
+    /// No adapter meets the requirements of the `AdapterSelector`.
+    NoSuitableAdapter,
 }
 
+impl fmt::Display for DeviceCreationError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceCreationError::MissingExtensions => write!(f, "Required device extensions are not supported"),
+            DeviceCreationError::NoSuitableAdapter => write!(f, "No adapter meets the renderer's requirements"),
+        }
+    }
+}
+
+impl error::Error for DeviceCreationError {}
+
 pub struct Device {
     physical_device : vk::PhysicalDevice,
     properties : vk::PhysicalDeviceProperties,
     limits : vk::PhysicalDeviceLimits,
     memory_properties : vk::PhysicalDeviceMemoryProperties,
     device : ash::Device,
-    compute_index : u32,
-    graphics_index : u32,
-    transfer_index : u32,
+    queue_plan : QueuePlan,
+    enabled_extensions : Vec<CString>,
+    allocator : RefCell<Allocator>,
+    pipeline_cache : PipelineCache,
+    /// Only loaded if the instance enabled `VK_EXT_debug_utils`, otherwise naming and labelling do nothing.
+    debug_utils : Option<DebugUtils>,
 }
 
 impl Drop for Device {
     fn drop(&mut self) {
         unsafe {
             self.device.device_wait_idle().unwrap();
+            if let Err(error) = self.pipeline_cache.save(&self.device) {
+                warn!("Failed to save the pipeline cache ({})", error);
+            }
+            self.pipeline_cache.destroy(&self.device);
+            self.allocator.get_mut().destroy(&self.device);
             self.device.destroy_device(None);
         }
         info!("Dropped Device")
@@ -29,15 +54,28 @@ impl Drop for Device {
 }
 
 impl Device {
-    pub fn new(instance: &Instance) -> Result<Self,DeviceCreationError> {
-        let physical_device = instance.select_primary_physical_device();
-        let (properties, features, limits, memory_properties, queue_families) = unsafe {
+    /// Creates a device which is capable of presenting to the surface.
+    pub fn new(instance: &Instance, surface : &Surface) -> Result<Self,Error> {
+        Self::with_selector(instance, &AdapterSelector::new().require_extension(Swapchain::name()), Some(surface))
+    }
+
+    /// Creates a device without the swapchain extension, for use with an instance created by `Instance::new_headless`.
+    pub fn new_headless(instance: &Instance) -> Result<Self,Error> {
+        Self::with_selector(instance, &AdapterSelector::new(), None)
+    }
+
+    /// Creates a device on the adapter chosen by `selector`, enabling its required extensions and features. When a
+    /// surface is provided, the adapter and queues are chosen so that the surface can be presented to.
+    pub fn with_selector(instance: &Instance,
+                         selector : &AdapterSelector,
+                         surface : Option<&Surface>) -> Result<Self,Error> {
+        let physical_device = selector
+            .select(instance, surface)
+            .ok_or(DeviceCreationError::NoSuitableAdapter)?;
+        let (properties, limits, memory_properties, queue_families) = unsafe {
             let properties = instance
                 .ash_instance()
                 .get_physical_device_properties(physical_device);
-            let features = instance
-                .ash_instance()
-                .get_physical_device_features(physical_device);
             let limits = properties.limits;
             let memory_properties = instance
                 .ash_instance()
@@ -45,48 +83,36 @@ impl Device {
             let queue_families = instance
                 .ash_instance()
                 .get_physical_device_queue_family_properties(physical_device);
-            (properties, features, limits, memory_properties, queue_families)
+            (properties, limits, memory_properties, queue_families)
         };
 
-        let mut compute_index = 0;
-        let mut graphics_index = 0;
-        let mut transfer_index = 0;
-        for index in 0..queue_families.len() {
-            let queue_family = queue_families.get(index).unwrap();
-            if queue_family.queue_flags & vk::QueueFlags::GRAPHICS
-                == vk::QueueFlags::GRAPHICS {
-                graphics_index = index as u32;
-            }
-            if queue_family.queue_flags & vk::QueueFlags::COMPUTE
-                == vk::QueueFlags::COMPUTE {
-                compute_index = index as u32;
-            }
-            if queue_family.queue_flags & vk::QueueFlags::TRANSFER
-                == vk::QueueFlags::TRANSFER {
-                transfer_index = index as u32;
-            }
-        }
+        let supports_present = surface
+            .map(|surface| move |family_index| surface.supports_present(physical_device, family_index));
+        let queue_plan = QueuePlan::new(&queue_families, supports_present.as_ref().map(|f| f as &dyn Fn(u32) -> bool))
+            .ok_or(DeviceCreationError::NoSuitableAdapter)?;
+        info!("Using queue families {:?}, async compute: {}, async transfer: {}",
+            queue_plan, queue_plan.async_compute(), queue_plan.async_transfer());
 
+        // Each family may only be named once, with a single queue shared by all work submitted to that family.
         let priorities = [1.0];
+        let queue_infos : Vec<_> = queue_plan
+            .unique_families()
+            .into_iter()
+            .map(|family_index| vk::DeviceQueueCreateInfo::builder()
+                .queue_family_index(family_index)
+                .queue_priorities(&priorities)
+                .build())
+            .collect();
+        let enabled_features = selector.required_features();
+        let device_extensions : Vec<_> = selector
+            .required_extensions()
+            .iter()
+            .map(|name| name.as_ptr())
+            .collect();
 
-        let compute_info = vk::DeviceQueueCreateInfo::builder()
-            .queue_family_index(compute_index)
-            .queue_priorities(&priorities);
-        let graphics_info = vk::DeviceQueueCreateInfo::builder()
-            .queue_family_index(graphics_index)
-            .queue_priorities(&priorities);
-        let transfer_info = vk::DeviceQueueCreateInfo::builder()
-            .queue_family_index(transfer_index)
-            .queue_priorities(&priorities);
-        let enabled_features = vk::PhysicalDeviceFeatures::builder()
-            .sampler_anisotropy(true)
-            .fill_mode_non_solid(true);
-
-        let queue_infos = vec![compute_info.build(), graphics_info.build(), transfer_info.build()];
-        let device_extensions = [Swapchain::name().as_ptr()];
         let device_info = vk::DeviceCreateInfo::builder()
             .queue_create_infos(queue_infos.as_slice())
-            .enabled_extension_names(device_extensions.as_ref())
+            .enabled_extension_names(device_extensions.as_slice())
             .enabled_features(&enabled_features)
             .build();
 
@@ -94,7 +120,16 @@ impl Device {
             instance
                 .ash_instance()
                 .create_device(physical_device, &device_info, None)
-                .unwrap()
+                .context("Failed to create the device")?
+        };
+
+        let pipeline_cache = PipelineCache::new(&device, &properties, None)
+            .inspect_err(|_| unsafe { device.destroy_device(None) })?;
+
+        let debug_utils = if instance.is_extension_enabled(DebugUtils::name()) {
+            Some(DebugUtils::new(instance.ash_entry(), instance.ash_instance()))
+        } else {
+            None
         };
 
         Ok(Self {
@@ -103,9 +138,11 @@ impl Device {
             limits,
             memory_properties,
             device,
-            compute_index,
-            graphics_index,
-            transfer_index,
+            queue_plan,
+            enabled_extensions: selector.required_extensions().to_vec(),
+            allocator: RefCell::new(Allocator::new(memory_properties, limits)),
+            pipeline_cache,
+            debug_utils,
         })
     }
 
@@ -113,20 +150,141 @@ impl Device {
         &self.device
     }
 
+    /// Returns the cache used when creating pipelines.
+    pub fn pipeline_cache(&self) -> vk::PipelineCache {
+        self.pipeline_cache.cache_raw()
+    }
+
+    /// Replaces the pipeline cache with one which is saved to `path` when the device is dropped, and seeded with the
+    /// data already there if it was saved by the same driver and device. Pipelines should be created afterwards, so
+    /// they benefit from the cache.
+    pub fn load_pipeline_cache(&mut self, path : &Path) -> Result<(),Error> {
+        let pipeline_cache = PipelineCache::new(&self.device, &self.properties, Some(path))?;
+        self.pipeline_cache.destroy(&self.device);
+        self.pipeline_cache = pipeline_cache;
+        Ok(())
+    }
+
+    /// Saves the pipeline cache now, rather than waiting for the device to be dropped. This does nothing unless a
+    /// cache was loaded with `load_pipeline_cache`.
+    pub fn save_pipeline_cache(&self) -> Result<(),Error> {
+        self.pipeline_cache.save(&self.device)
+    }
+
+    /// Names an object, so validation messages and debuggers such as RenderDoc show the name instead of its handle.
+    pub fn set_object_name<H : vk::Handle>(&self, handle : H, name : &str) {
+        if let Some(debug_utils) = &self.debug_utils {
+            let name = debug_name(name);
+            let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+                .object_type(H::TYPE)
+                .object_handle(handle.as_raw())
+                .object_name(&name);
+            unsafe {
+                // Naming only aids debugging, so a failure is not worth reporting to the caller.
+                if let Err(result) = debug_utils.set_debug_utils_object_name(self.device.handle(), &name_info) {
+                    warn!("Failed to name object {:?} ({})", name, result);
+                }
+            }
+        }
+    }
+
+    /// Opens a label region in the command buffer, which must be closed by `end_cmd_label`.
+    pub fn begin_cmd_label(&self, cmd_buffer : vk::CommandBuffer, name : &str, color : [f32; 4]) {
+        if let Some(debug_utils) = &self.debug_utils {
+            let name = debug_name(name);
+            let label = vk::DebugUtilsLabelEXT::builder().label_name(&name).color(color);
+            unsafe { debug_utils.cmd_begin_debug_utils_label(cmd_buffer, &label) };
+        }
+    }
+
+    pub fn end_cmd_label(&self, cmd_buffer : vk::CommandBuffer) {
+        if let Some(debug_utils) = &self.debug_utils {
+            unsafe { debug_utils.cmd_end_debug_utils_label(cmd_buffer) };
+        }
+    }
+
+    /// Inserts a single label into the command buffer.
+    pub fn insert_cmd_label(&self, cmd_buffer : vk::CommandBuffer, name : &str, color : [f32; 4]) {
+        if let Some(debug_utils) = &self.debug_utils {
+            let name = debug_name(name);
+            let label = vk::DebugUtilsLabelEXT::builder().label_name(&name).color(color);
+            unsafe { debug_utils.cmd_insert_debug_utils_label(cmd_buffer, &label) };
+        }
+    }
+
+    /// Opens a label region on the queue, which must be closed by `end_queue_label`.
+    pub fn begin_queue_label(&self, queue : vk::Queue, name : &str, color : [f32; 4]) {
+        if let Some(debug_utils) = &self.debug_utils {
+            let name = debug_name(name);
+            let label = vk::DebugUtilsLabelEXT::builder().label_name(&name).color(color);
+            unsafe { debug_utils.queue_begin_debug_utils_label(queue, &label) };
+        }
+    }
+
+    pub fn end_queue_label(&self, queue : vk::Queue) {
+        if let Some(debug_utils) = &self.debug_utils {
+            unsafe { debug_utils.queue_end_debug_utils_label(queue) };
+        }
+    }
+
+    /// Inserts a single label into the queue.
+    pub fn insert_queue_label(&self, queue : vk::Queue, name : &str, color : [f32; 4]) {
+        if let Some(debug_utils) = &self.debug_utils {
+            let name = debug_name(name);
+            let label = vk::DebugUtilsLabelEXT::builder().label_name(&name).color(color);
+            unsafe { debug_utils.queue_insert_debug_utils_label(queue, &label) };
+        }
+    }
+
     pub fn physical_device(&self) -> vk::PhysicalDevice {
         self.physical_device
     }
 
     pub fn compute_queue_index(&self) -> u32 {
-        self.compute_index
+        self.queue_plan.compute_family
     }
 
     pub fn graphics_queue_index(&self) -> u32 {
-        self.graphics_index
+        self.queue_plan.graphics_family
     }
 
     pub fn transfer_queue_index(&self) -> u32 {
-        self.transfer_index
+        self.queue_plan.transfer_family
+    }
+
+    /// Returns the family used for presentation, or `None` if the device was created without a surface.
+    pub fn present_queue_index(&self) -> Option<u32> {
+        self.queue_plan.present_family
+    }
+
+    /// Returns true if the extension was enabled when the device was created.
+    pub fn is_extension_enabled(&self, name : &CStr) -> bool {
+        self.enabled_extensions.iter().any(|enabled| enabled.as_c_str() == name)
+    }
+
+    pub fn queue_plan(&self) -> QueuePlan {
+        self.queue_plan
+    }
+
+    /// Sub-allocates memory for a resource with the given requirements. The allocation must be returned with
+    /// `free_memory` before the resource is destroyed.
+    pub fn allocate_memory(&self,
+                           requirements : &vk::MemoryRequirements,
+                           usage : MemoryUsage,
+                           strategy : AllocationStrategy,
+                           tiling : ResourceTiling) -> Result<Allocation,AllocationError> {
+        self.allocator
+            .borrow_mut()
+            .allocate(&self.device, requirements, usage, strategy, tiling)
+    }
+
+    pub fn free_memory(&self, allocation : Allocation) {
+        self.allocator.borrow_mut().free(&self.device, allocation);
+    }
+
+    /// Returns usage and fragmentation statistics for all memory allocated through `allocate_memory`.
+    pub fn memory_stats(&self) -> MemoryStats {
+        self.allocator.borrow().stats()
     }
 
     pub fn properties(&self) -> vk::PhysicalDeviceProperties {
@@ -140,4 +298,78 @@ impl Device {
     pub fn memory_properties(&self) -> vk::PhysicalDeviceMemoryProperties {
         self.memory_properties
     }
-}
\ No newline at end of file
+}
+
+/// Describes which queue family each kind of work is submitted to. Dedicated compute and transfer families are
+/// preferred, so that this work can run asynchronously to graphics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueuePlan {
+    pub graphics_family : u32,
+    pub compute_family : u32,
+    pub transfer_family : u32,
+    pub present_family : Option<u32>,
+}
+
+impl QueuePlan {
+    /// Plans the queue families, where `supports_present` returns whether a family can present to the surface. When
+    /// no surface is used, pass `None` and `present_family` will be `None`. Returns `None` if there is no graphics
+    /// family, or if a surface is used and no family can present to it.
+    pub fn new(queue_families : &[vk::QueueFamilyProperties],
+               supports_present : Option<&dyn Fn(u32) -> bool>) -> Option<Self> {
+        let presents = |index : u32| supports_present.is_none_or(|supports_present| supports_present(index));
+        let families : Vec<(u32, vk::QueueFlags)> = queue_families
+            .iter()
+            .enumerate()
+            .filter(|(_, family)| family.queue_count > 0)
+            .map(|(index, family)| (index as u32, family.queue_flags))
+            .collect();
+        let find = |required : vk::QueueFlags, excluded : vk::QueueFlags| families
+            .iter()
+            .find(|(_, flags)| flags.contains(required) && !flags.intersects(excluded))
+            .map(|(index, _)| *index);
+
+        // Presenting from the graphics family avoids transferring swapchain images between families.
+        let graphics_family = families
+            .iter()
+            .find(|(index, flags)| flags.contains(vk::QueueFlags::GRAPHICS) && presents(*index))
+            .map(|(index, _)| *index)
+            .or_else(|| find(vk::QueueFlags::GRAPHICS, vk::QueueFlags::empty()))?;
+        let present_family = match supports_present {
+            Some(_) if presents(graphics_family) => Some(graphics_family),
+            Some(_) => Some(families
+                .iter()
+                .map(|(index, _)| *index)
+                .find(|index| presents(*index))?),
+            None => None,
+        };
+
+        let compute_family = find(vk::QueueFlags::COMPUTE, vk::QueueFlags::GRAPHICS)
+            .or_else(|| find(vk::QueueFlags::COMPUTE, vk::QueueFlags::empty()))
+            .unwrap_or(graphics_family);
+        // Graphics and compute families always support transfers, even if they do not report it.
+        let transfer_family = find(vk::QueueFlags::TRANSFER, vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+            .or_else(|| find(vk::QueueFlags::TRANSFER, vk::QueueFlags::GRAPHICS))
+            .unwrap_or(graphics_family);
+
+        Some(Self { graphics_family, compute_family, transfer_family, present_family })
+    }
+
+    /// Returns true if compute work is submitted to a different family than graphics work.
+    pub fn async_compute(&self) -> bool {
+        self.compute_family != self.graphics_family
+    }
+
+    /// Returns true if transfer work is submitted to a different family than graphics work.
+    pub fn async_transfer(&self) -> bool {
+        self.transfer_family != self.graphics_family
+    }
+
+    /// Returns each family in the plan once, in ascending order.
+    pub fn unique_families(&self) -> Vec<u32> {
+        let mut families = vec![self.graphics_family, self.compute_family, self.transfer_family];
+        families.extend(self.present_family);
+        families.sort_unstable();
+        families.dedup();
+        families
+    }
+}
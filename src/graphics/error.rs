@@ -0,0 +1,127 @@
+use std::{error, fmt, io};
+use ash::vk;
+use super::buffer::BufferCreationError;
+use super::device::DeviceCreationError;
+use super::instance::InstanceCreationError;
+use super::memory::AllocationError;
+use super::reflect::ReflectionError;
+use super::swapchain::SwapchainCreationError;
+use super::upload::UploadError;
+
+/// Any error produced while creating or using graphics resources.
+#[derive(Debug)]
+pub enum Error {
+    /// A Vulkan call failed. `context` describes what was being attempted.
+    Vulkan { context : &'static str, result : vk::Result },
+    Instance(InstanceCreationError),
+    Device(DeviceCreationError),
+    Swapchain(SwapchainCreationError),
+    Buffer(BufferCreationError),
+    Allocation(AllocationError),
+    Upload(UploadError),
+    /// Shader code is not a valid SPIR-V module.
+    InvalidSpirv,
+    /// The renderer was asked to have no frames in flight.
+    NoFramesInFlight,
+    /// A pipeline uses dynamic state which the device was not created with the extensions or features for.
+    UnsupportedDynamicState(vk::DynamicState),
+    /// A shader could not be reflected, or does not match the pipeline it is used in.
+    Reflection(ReflectionError),
+    /// Reading or writing a file, such as the pipeline cache, failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Vulkan { context, result } => write!(f, "{} ({})", context, result),
+            Error::Instance(error) => error.fmt(f),
+            Error::Device(error) => error.fmt(f),
+            Error::Swapchain(error) => error.fmt(f),
+            Error::Buffer(error) => error.fmt(f),
+            Error::Allocation(error) => error.fmt(f),
+            Error::Upload(error) => error.fmt(f),
+            Error::InvalidSpirv => write!(f, "The shader is not a valid SPIR-V module"),
+            Error::NoFramesInFlight => write!(f, "At least one frame must be in flight"),
+            Error::UnsupportedDynamicState(state) => write!(f, "The device does not support the {:?} dynamic state", state),
+            Error::Reflection(error) => error.fmt(f),
+            Error::Io(error) => error.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Vulkan { result, .. } => Some(result),
+            Error::Instance(error) => Some(error),
+            Error::Device(error) => Some(error),
+            Error::Swapchain(error) => Some(error),
+            Error::Buffer(error) => Some(error),
+            Error::Allocation(error) => Some(error),
+            Error::Upload(error) => Some(error),
+            Error::InvalidSpirv | Error::NoFramesInFlight | Error::UnsupportedDynamicState(_) => None,
+            Error::Reflection(error) => Some(error),
+            Error::Io(error) => Some(error),
+        }
+    }
+}
+
+impl From<InstanceCreationError> for Error {
+    fn from(error : InstanceCreationError) -> Self {
+        Error::Instance(error)
+    }
+}
+
+impl From<DeviceCreationError> for Error {
+    fn from(error : DeviceCreationError) -> Self {
+        Error::Device(error)
+    }
+}
+
+impl From<SwapchainCreationError> for Error {
+    fn from(error : SwapchainCreationError) -> Self {
+        Error::Swapchain(error)
+    }
+}
+
+impl From<BufferCreationError> for Error {
+    fn from(error : BufferCreationError) -> Self {
+        Error::Buffer(error)
+    }
+}
+
+impl From<UploadError> for Error {
+    fn from(error : UploadError) -> Self {
+        Error::Upload(error)
+    }
+}
+
+impl From<ReflectionError> for Error {
+    fn from(error : ReflectionError) -> Self {
+        Error::Reflection(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error : io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<AllocationError> for Error {
+    fn from(error : AllocationError) -> Self {
+        Error::Allocation(error)
+    }
+}
+
+/// Attaches context to the result of a Vulkan call, so failures can be reported as an `Error`.
+pub trait VkResultExt<T> {
+    fn context(self, context : &'static str) -> Result<T,Error>;
+}
+
+impl<T> VkResultExt<T> for Result<T,vk::Result> {
+    fn context(self, context : &'static str) -> Result<T,Error> {
+        self.map_err(|result| Error::Vulkan { context, result })
+    }
+}
@@ -0,0 +1,102 @@
+use std::{cell::RefCell, rc::Rc};
+use ash::vk;
+use super::{CmdBuffer, CmdPool, Device, Queue, error::{Error, VkResultExt}};
+
+/// The resources used to record and submit a single frame. The renderer cycles through several frames, so the CPU
+/// can record one frame while the GPU is still executing the previous ones. A frame's resources may only be reused
+/// once `wait` has returned.
+pub struct Frame {
+    device : Rc<RefCell<Device>>,
+    cmd_buffer : CmdBuffer,
+    _cmd_pool : Rc<RefCell<CmdPool>>,
+    /// Signalled when the swapchain image this frame renders to has been acquired.
+    acquire_semaphore : vk::Semaphore,
+    /// Signalled when the frame's submission has completed on the GPU.
+    in_flight_fence : vk::Fence,
+}
+
+impl Drop for Frame {
+    fn drop(&mut self) {
+        if let Err(error) = self.wait() {
+            error!("Failed to wait for a frame before dropping it: {}", error);
+        }
+        unsafe {
+            let device = self.device.borrow();
+            device.ash_device().destroy_semaphore(self.acquire_semaphore, None);
+            device.ash_device().destroy_fence(self.in_flight_fence, None);
+        }
+        info!("Dropped Frame")
+    }
+}
+
+impl Frame {
+    /// Creates a frame whose command buffer is submitted to `queue`. The frame starts out signalled, so the first
+    /// `wait` returns immediately.
+    pub fn new(device : Rc<RefCell<Device>>, queue : &Queue) -> Result<Self,Error> {
+        let cmd_pool = Rc::new(RefCell::new(CmdPool::new(Rc::clone(&device), queue)?));
+        let cmd_buffer = CmdBuffer::new(Rc::clone(&device), Rc::clone(&cmd_pool))?;
+
+        let semaphore_info = vk::SemaphoreCreateInfo::builder();
+        let fence_info = vk::FenceCreateInfo::builder()
+            .flags(vk::FenceCreateFlags::SIGNALED);
+        let (acquire_semaphore, in_flight_fence) = unsafe {
+            let device = device.borrow();
+            let acquire_semaphore = device
+                .ash_device()
+                .create_semaphore(&semaphore_info, None)
+                .context("Failed to create a frame semaphore")?;
+            let in_flight_fence = device
+                .ash_device()
+                .create_fence(&fence_info, None)
+                .context("Failed to create a frame fence")
+                .inspect_err(|_| device.ash_device().destroy_semaphore(acquire_semaphore, None))?;
+            (acquire_semaphore, in_flight_fence)
+        };
+
+        Ok(Self {
+            device,
+            cmd_buffer,
+            _cmd_pool: cmd_pool,
+            acquire_semaphore,
+            in_flight_fence,
+        })
+    }
+
+    /// Blocks until the GPU has finished executing the last submission of this frame.
+    pub fn wait(&self) -> Result<(),Error> {
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .wait_for_fences(&[self.in_flight_fence], true, u64::MAX)
+                .context("Failed to wait for a frame fence")
+        }
+    }
+
+    /// Resets the in flight fence, which must be done right before the frame is submitted with it.
+    pub fn reset_fence(&self) -> Result<(),Error> {
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .reset_fences(&[self.in_flight_fence])
+                .context("Failed to reset a frame fence")
+        }
+    }
+
+    pub fn cmd_buffer(&self) -> &CmdBuffer {
+        &self.cmd_buffer
+    }
+
+    pub fn cmd_buffer_mut(&mut self) -> &mut CmdBuffer {
+        &mut self.cmd_buffer
+    }
+
+    pub fn acquire_semaphore(&self) -> vk::Semaphore {
+        self.acquire_semaphore
+    }
+
+    pub fn in_flight_fence(&self) -> vk::Fence {
+        self.in_flight_fence
+    }
+}
@@ -1,8 +1,8 @@
 use std::{cell::RefCell, rc::Rc};
 use ash::vk;
-use super::{Device, RenderPass};
+use super::{Device, RenderPass, error::{Error, VkResultExt}, image::OffscreenTarget};
 
-/// A framebuffer manages an image created by the swapchain.
+/// A framebuffer manages an image created by the swapchain, or by an `OffscreenTarget`.
 pub struct Framebuffer {
     device : Rc<RefCell<Device>>,
     framebuffer : vk::Framebuffer,
@@ -24,13 +24,22 @@ impl Framebuffer {
     pub fn framebuffer_raw(&self) -> vk::Framebuffer {
         self.framebuffer
     }
+
+    /// Names the framebuffer and its image view in validation messages and debuggers. Does nothing unless debug
+    /// utils are enabled.
+    pub fn set_debug_name(&self, name : &str) {
+        let device = self.device.borrow();
+        device.set_object_name(self.framebuffer, name);
+        device.set_object_name(self.color_view, &format!("{} view", name));
+    }
 }
 
 pub struct FramebufferBuilder {
     device : Rc<RefCell<Device>>,
     render_pass : Rc<RefCell<RenderPass>>,
     extent : vk::Extent2D,
-    color_view : vk::ImageView,
+    color_image : vk::Image,
+    color_format : vk::Format,
 }
 
 impl FramebufferBuilder {
@@ -39,49 +48,58 @@ impl FramebufferBuilder {
                color_image : vk::Image,
                color_format : vk::Format,
                extent : vk::Extent2D) -> Self {
+        Self { device,
+            render_pass,
+            extent,
+            color_image,
+            color_format,
+        }
+    }
+
+    /// Creates a builder which renders into an offscreen target instead of a swapchain image.
+    pub fn from_target(device : Rc<RefCell<Device>>,
+                       render_pass : Rc<RefCell<RenderPass>>,
+                       target : &OffscreenTarget) -> Self {
+        Self::new(device, render_pass, target.image_raw(), target.format(), target.extent())
+    }
+
+    pub fn add_depth_stencil(self) -> Self {
+        self
+    }
+
+    pub fn build(self) -> Result<Framebuffer,Error> {
         let color_subresource_range = vk::ImageSubresourceRange::builder()
             .aspect_mask(vk::ImageAspectFlags::COLOR)
             .level_count(1)
             .layer_count(1);
 
         let color_view_info = vk::ImageViewCreateInfo::builder()
-            .format(color_format)
-            .image(color_image)
+            .format(self.color_format)
+            .image(self.color_image)
             .view_type(vk::ImageViewType::TYPE_2D)
             .subresource_range(color_subresource_range.build());
+        let device = self.device.borrow();
         let color_view = unsafe {
             device
-                .borrow()
                 .ash_device()
                 .create_image_view(&color_view_info, None)
-                .unwrap()
+                .context("Failed to create a framebuffer image view")?
         };
-        Self { device,
-            render_pass,
-            extent,
-            color_view
-        }
-    }
-
-    pub fn add_depth_stencil(self) -> Self {
-        self
-    }
 
-    pub fn build(self) -> Framebuffer {
+        let attachments = [color_view];
         let framebuffer_info = vk::FramebufferCreateInfo::builder()
             .layers(1)
             .width(self.extent.width)
             .height(self.extent.height)
             .render_pass(self.render_pass.borrow().render_pass_raw())
-            .attachments(&[self.color_view])
-            .build();
+            .attachments(&attachments);
         let framebuffer = unsafe {
-            self.device
-                .borrow()
+            device
                 .ash_device()
                 .create_framebuffer(&framebuffer_info, None)
-                .expect("Failed to create framebuffer")
+                .context("Failed to create a framebuffer")
+                .inspect_err(|_| device.ash_device().destroy_image_view(color_view, None))?
         };
-        Framebuffer { device: Rc::clone(&self.device), framebuffer, color_view: self.color_view }
+        Ok(Framebuffer { device: Rc::clone(&self.device), framebuffer, color_view })
     }
 }
\ No newline at end of file
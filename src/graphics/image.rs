@@ -0,0 +1,172 @@
+use std::{cell::RefCell, rc::Rc};
+use ash::vk;
+use super::{CmdBuffer, CmdPool, Device, Queue, buffer::ReadbackBuffer, error::{Error, VkResultExt}};
+use super::memory::{Allocation, AllocationStrategy, MemoryUsage, ResourceTiling};
+
+/// A 2D image which owns its memory, unlike the images handed out by the swapchain.
+pub struct Image {
+    device : Rc<RefCell<Device>>,
+    image : vk::Image,
+    allocation : Option<Allocation>,
+    format : vk::Format,
+    extent : vk::Extent2D,
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.borrow().ash_device().device_wait_idle().unwrap();
+            self.device.borrow().ash_device().destroy_image(self.image, None);
+        }
+        self.device.borrow().free_memory(self.allocation.take().unwrap());
+        info!("Dropped Image")
+    }
+}
+
+impl Image {
+    /// Creates a single sampled, optimally tiled image, preferring device local memory.
+    pub fn new(device : Rc<RefCell<Device>>,
+               format : vk::Format,
+               extent : vk::Extent2D,
+               usage : vk::ImageUsageFlags) -> Result<Self,Error> {
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+        let (image, memory_requirements) = unsafe {
+            let image = device
+                .borrow()
+                .ash_device()
+                .create_image(&image_info, None)
+                .context("Failed to create an image")?;
+            let memory_requirements = device.borrow().ash_device().get_image_memory_requirements(image);
+            (image, memory_requirements)
+        };
+
+        let allocation = device
+            .borrow()
+            .allocate_memory(
+                &memory_requirements,
+                MemoryUsage::GpuOnly,
+                AllocationStrategy::FreeList,
+                ResourceTiling::Optimal)
+            .inspect_err(|_| unsafe { device.borrow().ash_device().destroy_image(image, None) })?;
+        let bound = unsafe {
+            device
+                .borrow()
+                .ash_device()
+                .bind_image_memory(image, allocation.memory(), allocation.offset())
+        };
+        // Dropping the image destroys it and frees its memory, whether or not the memory was bound.
+        let image = Self { device, image, allocation: Some(allocation), format, extent };
+        bound.context("Failed to bind image memory")?;
+        Ok(image)
+    }
+
+    pub fn image_raw(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    /// Names the image in validation messages and debuggers. Does nothing unless debug utils are enabled.
+    pub fn set_debug_name(&self, name : &str) {
+        self.device.borrow().set_object_name(self.image, name);
+    }
+}
+
+/// A color image which is rendered into in place of a swapchain image, and whose contents can be read back to
+/// host memory for screenshots, thumbnails, or image comparisons.
+pub struct OffscreenTarget {
+    image : Image,
+    readback_buffer : ReadbackBuffer,
+}
+
+impl OffscreenTarget {
+    /// Creates a target with the given color format, which must be a 4 component, 8 bit per channel format.
+    pub fn new(device : Rc<RefCell<Device>>, format : vk::Format, extent : vk::Extent2D) -> Result<Self,Error> {
+        debug_assert!(is_rgba8_format(format) || is_bgra8_format(format),
+            "Offscreen targets only support 8 bit RGBA or BGRA formats");
+        let image = Image::new(
+            Rc::clone(&device),
+            format,
+            extent,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC)?;
+        let readback_buffer = ReadbackBuffer::new(
+            device,
+            extent.width as vk::DeviceSize * extent.height as vk::DeviceSize * 4)?;
+        Ok(Self { image, readback_buffer })
+    }
+
+    /// Copies the target into host memory, returning tightly packed RGBA8 pixels in row-major order. The target must
+    /// be in the `TRANSFER_SRC_OPTIMAL` layout, which is the final layout render passes use for offscreen attachments.
+    /// Render passes make their color writes visible to transfers when they end. This blocks until `queue` is idle.
+    pub fn read_pixels(&self, queue : &Queue, cmd_pool : Rc<RefCell<CmdPool>>) -> Result<Vec<u8>,Error> {
+        let device = Rc::clone(&self.image.device);
+        let mut cmd_buffer = CmdBuffer::new(device, cmd_pool)?;
+        cmd_buffer.begin()?;
+        cmd_buffer.copy_image_to_buffer(&self.image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, &self.readback_buffer);
+        cmd_buffer.memory_barrier(
+            (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+            (vk::PipelineStageFlags::HOST, vk::AccessFlags::HOST_READ));
+        cmd_buffer.end()?;
+        queue.submit_offscreen(&cmd_buffer, None)?;
+        queue.wait_idle()?;
+
+        let mut pixels = self.readback_buffer.read();
+        if is_bgra8_format(self.image.format()) {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        Ok(pixels)
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn image_raw(&self) -> vk::Image {
+        self.image.image_raw()
+    }
+
+    /// Names the image and its readback buffer in validation messages and debuggers.
+    pub fn set_debug_name(&self, name : &str) {
+        self.image.set_debug_name(name);
+        self.readback_buffer.set_debug_name(&format!("{} readback", name));
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.image.format()
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.image.extent()
+    }
+}
+
+fn is_rgba8_format(format : vk::Format) -> bool {
+    matches!(format,
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_SNORM |
+        vk::Format::R8G8B8A8_UINT | vk::Format::R8G8B8A8_SINT)
+}
+
+fn is_bgra8_format(format : vk::Format) -> bool {
+    matches!(format,
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_SNORM |
+        vk::Format::B8G8R8A8_UINT | vk::Format::B8G8R8A8_SINT)
+}
@@ -1,29 +1,50 @@
-use std::ffi::CString;
-use ash::vk::{self, Result as VkResult};
+use std::{error, ffi::{CStr, CString}, fmt, os::raw::c_void};
+use ash::vk;
 use ash::extensions::ext::DebugUtils;
 
-use super::platform::get_required_instance_extensions;
-use super::debug::debug_utils_callback;
+use super::platform::{get_headless_instance_extensions, get_optional_instance_extensions, get_required_instance_extensions};
+use super::debug::{debug_utils_callback, MessengerData, ValidationMessage, ValidationSettings};
+use super::error::{Error, VkResultExt};
+
+/// Name of the Khronos validation layer, which is only present on machines with the Vulkan SDK installed.
+const VALIDATION_LAYER_NAME : &str = "VK_LAYER_KHRONOS_validation";
 
 /// Provides a brief overview of why an instance failed to be created.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum InstanceCreationError {
-    /// Unknown or uncaptured error.
-    Unknown,
     /// Triggered if there is no [Vulkan ICD](https://github.com/KhronosGroup/Vulkan-LoaderAndValidationLayers/blob/master/loader/LoaderAndLayerInterface.md#installable-client-drivers).
     MissingDriver,
-    /// There are required extensions which were not found.
-    MissingExtensions,
-    /// There are required layers which were not found. This would only be triggered in debug mode.
-    MissingLayers,
+    /// There are required extensions which were not found. Contains the name of each missing extension.
+    MissingExtensions(Vec<String>),
+    /// There are required layers which were not found. Contains the name of each missing layer.
+    MissingLayers(Vec<String>),
+}
+
+impl fmt::Display for InstanceCreationError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstanceCreationError::MissingDriver => write!(f, "No Vulkan driver was found"),
+            InstanceCreationError::MissingExtensions(names) =>
+                write!(f, "Required instance extensions are not supported: {}", names.join(", ")),
+            InstanceCreationError::MissingLayers(names) =>
+                write!(f, "Required instance layers are not available: {}", names.join(", ")),
+        }
+    }
 }
 
+impl error::Error for InstanceCreationError {}
+
 /// Contains vulkan instance-level loaders and handles.
 pub struct Instance {
     entry : ash::Entry,
     instance : ash::Instance,
     debug_utils_loader : Option<DebugUtils>,
     debug_utils : Option<vk::DebugUtilsMessengerEXT>,
+    /// Read by the debug messenger, so it must outlive it.
+    debug_messenger_data : Option<Box<MessengerData>>,
     physical_devices : Vec<vk::PhysicalDevice>,
+    enabled_extensions : Vec<CString>,
+    enabled_layers : Vec<CString>,
 }
 
 impl Drop for Instance {
@@ -41,103 +62,284 @@ impl Drop for Instance {
 }
 
 impl Instance {
-    pub fn new() -> Result<Self,InstanceCreationError> {
-        let entry = unsafe { 
-            let entry_result = ash::Entry::load();
-            match entry_result {
-                Ok(entry) => (entry),
-                Err(_error) => return Err(InstanceCreationError::MissingDriver)
-            } 
-        };
+    /// Creates an instance with the extensions required to present to a window on this platform.
+    pub fn new() -> Result<Self,Error> {
+        InstanceBuilder::new()
+            .require_extensions(&get_required_instance_extensions())
+            .request_extensions(&get_optional_instance_extensions())
+            .build()
+    }
 
-        let layer_names = [CString::new("VK_LAYER_KHRONOS_validation").unwrap()];
-        let layer_names_raw: Vec<*const i8> = layer_names
-            .iter()
-            .map(|raw_name| raw_name.as_ptr())
-            .collect();
+    /// Creates an instance without any surface extensions. This is used when rendering offscreen, and works on
+    /// machines without a display such as CI runners using a software driver like lavapipe.
+    pub fn new_headless() -> Result<Self,Error> {
+        InstanceBuilder::new().build()
+    }
 
-        let extension_names = get_required_instance_extensions();
+    /// Creates an instance which can present to headless surfaces through `VK_EXT_headless_surface`, so that the
+    /// swapchain can be used without a display. Fails with `MissingExtensions` if the driver does not support it.
+    pub fn new_headless_surface() -> Result<Self,Error> {
+        InstanceBuilder::new()
+            .require_extensions(&get_headless_instance_extensions())
+            .build()
+    }
 
-        // Enable validation layer only on debug builds.
-        let instance_info = if cfg!(debug_assertions) {
-            vk::InstanceCreateInfo::builder()
-                .enabled_extension_names(&extension_names)
-                .enabled_layer_names(&layer_names_raw)
-                .build()
-        } else {
-            vk::InstanceCreateInfo::builder()
-                .enabled_extension_names(&extension_names)
-                .build()
+    /// Returns true if the extension was enabled when the instance was created.
+    pub fn is_extension_enabled(&self, name : &CStr) -> bool {
+        is_enabled(&self.enabled_extensions, name)
+    }
+
+    /// Returns true if the layer was enabled when the instance was created.
+    pub fn is_layer_enabled(&self, name : &CStr) -> bool {
+        is_enabled(&self.enabled_layers, name)
+    }
+
+    /// Returns the validation messages reported since the last call. This is empty unless validation is enabled
+    /// with `ValidationSettings::collect_messages`.
+    pub fn take_validation_messages(&self) -> Vec<ValidationMessage> {
+        match &self.debug_messenger_data {
+            Some(data) => data.take_messages(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns true if the validation layer is enabled, and its messages are being logged.
+    pub fn is_validation_enabled(&self) -> bool {
+        self.debug_utils.is_some()
+            && self.enabled_layers.iter().any(|enabled| enabled.to_bytes() == VALIDATION_LAYER_NAME.as_bytes())
+    }
+
+    /// Returns the ash entrypoint.
+    pub fn ash_entry(&self) -> &ash::Entry {
+        &self.entry
+    }
+
+    /// Returns the ash instance.
+    pub fn ash_instance(&self) -> &ash::Instance {
+        &self.instance
+    }
+
+    /// Returns all physical devices.
+    pub fn physical_devices(&self) -> Vec<vk::PhysicalDevice> {
+        self.physical_devices.clone()
+    }
+}
+
+/// Configures the layers and extensions of an instance. Required layers and extensions fail creation if they are
+/// missing, while optional ones are only enabled if they are available. By default, debug builds request the
+/// validation layer and `VK_EXT_debug_utils`, and run without them on machines which lack the Vulkan SDK.
+pub struct InstanceBuilder {
+    required_extensions : Vec<CString>,
+    optional_extensions : Vec<CString>,
+    required_layers : Vec<CString>,
+    optional_layers : Vec<CString>,
+    validation : ValidationSettings,
+}
+
+impl Default for InstanceBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InstanceBuilder {
+    pub fn new() -> Self {
+        Self {
+            required_extensions: Vec::new(),
+            optional_extensions: Vec::new(),
+            required_layers: Vec::new(),
+            optional_layers: Vec::new(),
+            validation: ValidationSettings::default(),
+        }
+    }
+
+    /// Adds extensions which must be available for the instance to be created.
+    pub fn require_extensions(mut self, names : &[&CStr]) -> Self {
+        self.required_extensions.extend(names.iter().map(|name| CString::from(*name)));
+        self
+    }
+
+    /// Adds extensions which are enabled if the loader or an enabled layer provides them.
+    pub fn request_extensions(mut self, names : &[&CStr]) -> Self {
+        self.optional_extensions.extend(names.iter().map(|name| CString::from(*name)));
+        self
+    }
+
+    /// Adds layers which must be available for the instance to be created.
+    pub fn require_layers(mut self, names : &[&CStr]) -> Self {
+        self.required_layers.extend(names.iter().map(|name| CString::from(*name)));
+        self
+    }
+
+    /// Adds layers which are enabled if they are installed.
+    pub fn request_layers(mut self, names : &[&CStr]) -> Self {
+        self.optional_layers.extend(names.iter().map(|name| CString::from(*name)));
+        self
+    }
+
+    /// Sets whether the validation layer and debug messenger are requested. Both are optional, so validation is
+    /// skipped when they are unavailable.
+    pub fn validation(mut self, enabled : bool) -> Self {
+        self.validation.enabled = enabled;
+        self
+    }
+
+    /// Replaces the validation settings, including which messages are reported and which validation features are
+    /// enabled.
+    pub fn validation_settings(mut self, settings : ValidationSettings) -> Self {
+        self.validation = settings;
+        self
+    }
+
+    /// Creates the instance. Fails with `MissingLayers` or `MissingExtensions`, naming everything which is missing,
+    /// if any required layer or extension is unavailable.
+    pub fn build(mut self) -> Result<Instance,Error> {
+        let entry = unsafe {
+            match ash::Entry::load() {
+                Ok(entry) => entry,
+                Err(_error) => return Err(InstanceCreationError::MissingDriver.into())
+            }
         };
 
+        let validation_features = self.validation.enabled_features();
+        if self.validation.enabled {
+            self.optional_layers.push(CString::new(VALIDATION_LAYER_NAME).unwrap());
+            self.optional_extensions.push(CString::from(DebugUtils::name()));
+            if !validation_features.is_empty() {
+                self.optional_extensions.push(CString::from(vk::ExtValidationFeaturesFn::name()));
+            }
+        }
+
+        let available_layers = available_layers(&entry)?;
+        let enabled_layers = select_names(&self.required_layers, &self.optional_layers, &available_layers, "layer")
+            .map_err(InstanceCreationError::MissingLayers)?;
+        // Layers can provide extensions of their own, such as the validation layer providing debug utils.
+        let available_extensions = available_extensions(&entry, &enabled_layers)?;
+        let enabled_extensions = select_names(
+            &self.required_extensions,
+            &self.optional_extensions,
+            &available_extensions,
+            "extension")
+            .map_err(InstanceCreationError::MissingExtensions)?;
+
+        let layer_names_raw : Vec<*const i8> = enabled_layers.iter().map(|name| name.as_ptr()).collect();
+        let extension_names_raw : Vec<*const i8> = enabled_extensions.iter().map(|name| name.as_ptr()).collect();
+        let mut validation_features_info = vk::ValidationFeaturesEXT::builder()
+            .enabled_validation_features(&validation_features);
+        let mut instance_info = vk::InstanceCreateInfo::builder()
+            .enabled_extension_names(&extension_names_raw)
+            .enabled_layer_names(&layer_names_raw);
+        // The validation layer provides the extension, so it is only available if the layer is enabled.
+        if is_enabled(&enabled_extensions, vk::ExtValidationFeaturesFn::name()) {
+            instance_info = instance_info.push_next(&mut validation_features_info);
+        } else if self.validation.enabled && !validation_features.is_empty() {
+            warn!("Validation features {:?} are not available", validation_features);
+        }
+
         let instance = unsafe {
-            let instance_result = entry.create_instance(&instance_info, None);
-            match instance_result {
-                Ok(instance) => (instance),
-                Err(error) => match error {
-                    VkResult::ERROR_INCOMPATIBLE_DRIVER => return Err(InstanceCreationError::MissingDriver),
-                    VkResult::ERROR_EXTENSION_NOT_PRESENT => return Err(InstanceCreationError::MissingExtensions),
-                    VkResult::ERROR_LAYER_NOT_PRESENT => return Err(InstanceCreationError::MissingLayers),
-                    _ => return Err(InstanceCreationError::Unknown),
-                }
+            match entry.create_instance(&instance_info, None) {
+                Ok(instance) => instance,
+                Err(vk::Result::ERROR_INCOMPATIBLE_DRIVER) => return Err(InstanceCreationError::MissingDriver.into()),
+                Err(result) => return Err(Error::Vulkan { context: "Failed to create the instance", result }),
             }
         };
 
-        // Only enable the debug utils callback on debug builds.
-        let (debug_utils_loader, debug_utils) = if cfg!(debug_assertions) {
+        // Validation messages are only reported when debug utils could be enabled.
+        let (debug_utils_loader, debug_utils, debug_messenger_data) = if self.validation.enabled
+            && is_enabled(&enabled_extensions, DebugUtils::name()) {
+            let mut debug_messenger_data = Box::new(MessengerData::new(&self.validation));
             let debug_utils_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
-                .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                    | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                    | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-                    | vk::DebugUtilsMessageSeverityFlagsEXT::INFO)
-                .message_type(vk::DebugUtilsMessageTypeFlagsEXT::GENERAL 
-                    | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE 
-                    | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION)
-                .pfn_user_callback(Some(debug_utils_callback));
-
-                let debug_utils_loader = DebugUtils::new(&entry, &instance);
-                let debug_utils = unsafe {
-                    debug_utils_loader
+                .message_severity(self.validation.severities)
+                .message_type(self.validation.message_types)
+                .pfn_user_callback(Some(debug_utils_callback))
+                .user_data(debug_messenger_data.as_mut() as *mut MessengerData as *mut c_void);
+
+            let debug_utils_loader = DebugUtils::new(&entry, &instance);
+            let debug_utils = unsafe {
+                debug_utils_loader
                     .create_debug_utils_messenger(&debug_utils_info, None)
-                    .unwrap()
-                };
+                    .context("Failed to create the debug messenger")?
+            };
 
-                (Some(debug_utils_loader), Some(debug_utils))
+            (Some(debug_utils_loader), Some(debug_utils), Some(debug_messenger_data))
         } else {
-            (None, None)
+            (None, None, None)
         };
 
         let physical_devices = unsafe {
             instance
                 .enumerate_physical_devices()
-                .expect("Failed to retrieve physical devices.")
+                .context("Failed to retrieve physical devices")?
         };
 
-        Ok(Self { entry,
+        Ok(Instance { entry,
             instance,
             debug_utils_loader,
             debug_utils,
-            physical_devices
+            debug_messenger_data,
+            physical_devices,
+            enabled_extensions,
+            enabled_layers,
         })
     }
+}
 
-    /// Returns the ash entrypoint.
-    pub fn ash_entry(&self) -> &ash::Entry {
-        &self.entry
-    }
+fn is_enabled(enabled : &[CString], name : &CStr) -> bool {
+    enabled.iter().any(|enabled| enabled.as_c_str() == name)
+}
 
-    /// Returns the ash instance.
-    pub fn ash_instance(&self) -> &ash::Instance {
-        &self.instance
+/// Returns the names of the installed instance layers.
+fn available_layers(entry : &ash::Entry) -> Result<Vec<CString>,Error> {
+    let properties = entry
+        .enumerate_instance_layer_properties()
+        .context("Failed to enumerate instance layers")?;
+    Ok(properties
+        .iter()
+        .map(|layer| unsafe { CStr::from_ptr(layer.layer_name.as_ptr()) }.to_owned())
+        .collect())
+}
+
+/// Returns the names of the instance extensions provided by the loader and by `layers`.
+fn available_extensions(entry : &ash::Entry, layers : &[CString]) -> Result<Vec<CString>,Error> {
+    let mut properties = entry
+        .enumerate_instance_extension_properties(None)
+        .context("Failed to enumerate instance extensions")?;
+    for layer in layers {
+        properties.extend(entry
+            .enumerate_instance_extension_properties(Some(layer))
+            .context("Failed to enumerate instance layer extensions")?);
     }
+    Ok(properties
+        .iter()
+        .map(|extension| unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) }.to_owned())
+        .collect())
+}
 
-    /// Returns all physical devices.
-    pub fn physical_devices(&self) -> Vec<vk::PhysicalDevice> {
-        self.physical_devices.clone()
+/// Selects every required name, and the optional names which are available, without duplicates. Returns the
+/// required names which are unavailable as the error. `kind` is only used for logging.
+fn select_names(required : &[CString],
+                optional : &[CString],
+                available : &[CString],
+                kind : &str) -> Result<Vec<CString>,Vec<String>> {
+    let missing : Vec<String> = required
+        .iter()
+        .filter(|name| !available.contains(name))
+        .map(|name| name.to_string_lossy().into_owned())
+        .collect();
+    if !missing.is_empty() {
+        return Err(missing);
     }
 
-    /// Returns the first adapter in the sequence.
-    pub fn select_primary_physical_device(&self) -> vk::PhysicalDevice {
-        self.physical_devices.clone().remove(0)
+    let mut selected = Vec::new();
+    for name in required.iter().chain(optional.iter()) {
+        if selected.contains(name) {
+            continue;
+        }
+        if available.contains(name) {
+            selected.push(name.clone());
+        } else {
+            debug!("Optional instance {} {:?} is not available", kind, name);
+        }
     }
+    Ok(selected)
 }
@@ -1,40 +1,76 @@
-use std::{cell::RefCell, ffi::CString, fs::File, io::Read, mem::size_of, rc::Rc};
+use std::{cell::RefCell, ffi::CString, io::Cursor, mem::{size_of, offset_of}, rc::Rc};
 use ash::vk;
 use nalgebra::{Vector2, Vector3, Vector4};
-use super::Device;
+use super::{Device, error::{Error, VkResultExt}, reflect::{self, PipelineInterface, ShaderReflection}};
 
-/// Creates a shader module with the provided device and bytes.
-fn create_shader_module(device : &Rc<RefCell<Device>>, bytes : Vec<u8>) -> vk::ShaderModule {
-    let module_create_info = vk::ShaderModuleCreateInfo {
-        p_code: bytes.as_ptr() as *const u32,
-        code_size: bytes.len(),
-        ..Default::default()
-    };
+/// Creates a shader module with the provided device and bytes. The bytes are copied into words, as they may not be
+/// aligned for `u32`.
+fn create_shader_module(device : &Rc<RefCell<Device>>, bytes : &[u8]) -> Result<vk::ShaderModule,Error> {
+    let code = ash::util::read_spv(&mut Cursor::new(bytes)).map_err(|_| Error::InvalidSpirv)?;
+    let module_create_info = vk::ShaderModuleCreateInfo::builder().code(&code);
 
     unsafe {
         device
             .borrow()
             .ash_device()
             .create_shader_module(&module_create_info, None)
-            .unwrap()
+            .context("Failed to create a shader module")
     }
 }
 
 /// Stores the vertex information associated.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vertex {
-    position : Vector3<f32>,
-    color : Vector4<f32>,
-    texture_coord : Vector2<f32>,
+    pub position : Vector3<f32>,
+    pub color : Vector4<f32>,
+    pub texture_coord : Vector2<f32>,
+}
+
+impl Vertex {
+    pub fn new(position : Vector3<f32>, color : Vector4<f32>, texture_coord : Vector2<f32>) -> Self {
+        Self { position, color, texture_coord }
+    }
+
+    /// Vertices are read from a single interleaved buffer bound at binding 0.
+    pub fn binding_descriptions() -> Vec<vk::VertexInputBindingDescription> {
+        vec![vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<Vertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()]
+    }
+
+    /// The position, color, and texture coordinate are bound to locations 0, 1, and 2 respectively. Shaders must read
+    /// each location they use with the same numeric type, which `Material` checks when it is created.
+    pub fn attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+        let attribute = |location, format, offset : usize| vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(location)
+            .format(format)
+            .offset(offset as u32)
+            .build();
+        vec![
+            attribute(0, vk::Format::R32G32B32_SFLOAT, offset_of!(Vertex, position)),
+            attribute(1, vk::Format::R32G32B32A32_SFLOAT, offset_of!(Vertex, color)),
+            attribute(2, vk::Format::R32G32_SFLOAT, offset_of!(Vertex, texture_coord)),
+        ]
+    }
 }
 
 /// A material describes the appearance of an object in a rendered space.
 pub struct Material {
     device : Rc<RefCell<Device>>,
-    entry_point : CString,
+    // Referenced by `pipeline_shader_stages`.
+    _entry_point : CString,
     vertex_module : vk::ShaderModule,
     fragment_module : vk::ShaderModule,
     pipeline_shader_stages : Vec<vk::PipelineShaderStageCreateInfo>,
+    // Referenced by `pipeline_vertex_input_state`.
+    _vertex_bindings : Vec<vk::VertexInputBindingDescription>,
+    _vertex_attributes : Vec<vk::VertexInputAttributeDescription>,
     pipeline_vertex_input_state : vk::PipelineVertexInputStateCreateInfo,
+    interface : PipelineInterface,
 }
 
 impl Drop for Material {
@@ -48,31 +84,59 @@ impl Drop for Material {
 }
 
 impl Material {
-    pub fn new(device : Rc<RefCell<Device>>) -> Self {
+    /// Creates the default material. The shaders are reflected first, failing if they do not read the `Vertex`
+    /// layout, or if the fragment shader reads anything the vertex shader does not write.
+    pub fn new(device : Rc<RefCell<Device>>) -> Result<Self,Error> {
+        let vertex_code = include_bytes!("../assets/shaders/vert.spv");
+        let fragment_code = include_bytes!("../assets/shaders/frag.spv");
+        let vertex_reflection = ShaderReflection::new(vertex_code)?;
+        let fragment_reflection = ShaderReflection::new(fragment_code)?;
+        let vertex_entry_point = vertex_reflection.entry_point("main", vk::ShaderStageFlags::VERTEX)?;
+        let fragment_entry_point = fragment_reflection.entry_point("main", vk::ShaderStageFlags::FRAGMENT)?;
+        reflect::check_stage_interface(&vertex_entry_point.outputs, fragment_entry_point)?;
+        // Only the attributes the vertex shader reads are passed to the pipeline.
+        let vertex_attributes = reflect::vertex_attributes(&vertex_entry_point.inputs, &Vertex::attribute_descriptions())?;
+        let interface = PipelineInterface::new(&[&vertex_reflection, &fragment_reflection])?;
+
         // Have to keep this pointer alive.
         let entry_point = CString::new("main").unwrap();
 
-        let vertex_module = create_shader_module(&device, include_bytes!("../assets/shaders/vert.spv").to_vec());
+        let vertex_module = create_shader_module(&device, vertex_code)?;
         let vertex_pipeline_stage = vk::PipelineShaderStageCreateInfo::builder()
             .stage(vk::ShaderStageFlags::VERTEX)
             .module(vertex_module)
             .name(entry_point.as_c_str());
 
-        let fragment_module = create_shader_module(&device, include_bytes!("../assets/shaders/frag.spv").to_vec());
+        let fragment_module = create_shader_module(&device, fragment_code)
+            .inspect_err(|_| unsafe { device.borrow().ash_device().destroy_shader_module(vertex_module, None) })?;
         let fragment_pipeline_stage = vk::PipelineShaderStageCreateInfo::builder()
             .stage(vk::ShaderStageFlags::FRAGMENT)
             .module(fragment_module)
             .name(entry_point.as_c_str());
 
         let pipeline_shader_stages = vec![vertex_pipeline_stage.build(), fragment_pipeline_stage.build()];
+        let vertex_bindings = Vertex::binding_descriptions();
         let pipeline_vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(vertex_bindings.as_slice())
+            .vertex_attribute_descriptions(vertex_attributes.as_slice())
             .build();
-        Self { device, entry_point, vertex_module, fragment_module, pipeline_shader_stages, pipeline_vertex_input_state }
+        Ok(Self { device,
+            _entry_point: entry_point,
+            vertex_module,
+            fragment_module,
+            pipeline_shader_stages,
+            _vertex_bindings: vertex_bindings,
+            _vertex_attributes: vertex_attributes,
+            pipeline_vertex_input_state,
+            interface,
+        })
     }
 
-    pub fn vertex_buffer_size(&self) -> vk::DeviceSize { size_of::<Vertex>() as vk::DeviceSize }
-
     pub fn pipeline_shader_stages(&self) -> Vec<vk::PipelineShaderStageCreateInfo> { self.pipeline_shader_stages.clone() }
 
     pub fn pipeline_vertex_input_state(&self) -> vk::PipelineVertexInputStateCreateInfo { self.pipeline_vertex_input_state }
+
+    /// Returns the descriptor bindings and push constants used by the shaders, from which the pipeline layout is
+    /// created.
+    pub fn interface(&self) -> &PipelineInterface { &self.interface }
 }
\ No newline at end of file
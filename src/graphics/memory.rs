@@ -0,0 +1,566 @@
+use std::{collections::BTreeMap, error, fmt, ptr, slice};
+use ash::vk;
+use super::util::find_memory_type_index;
+
+/// Size of the blocks which allocations are carved out of. Larger requests receive a block of their own.
+const DEFAULT_BLOCK_SIZE : vk::DeviceSize = 64 * 1024 * 1024;
+
+/// Describes how the CPU and GPU will access an allocation, which determines the memory type it is placed in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryUsage {
+    /// Only accessed by the GPU. Prefers device local memory.
+    GpuOnly,
+    /// Written by the CPU and read by the GPU, such as staging or per-frame uniform data. Requires host visible,
+    /// coherent memory and prefers memory which is also device local.
+    CpuToGpu,
+    /// Written by the GPU and read by the CPU, such as readbacks. Requires host visible, coherent memory and prefers
+    /// memory which is also cached.
+    GpuToCpu,
+}
+
+impl MemoryUsage {
+    /// Returns the property flags a memory type must have, and the flags it should have if possible.
+    pub fn property_flags(self) -> (vk::MemoryPropertyFlags, vk::MemoryPropertyFlags) {
+        let host = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        match self {
+            MemoryUsage::GpuOnly => (vk::MemoryPropertyFlags::empty(), vk::MemoryPropertyFlags::DEVICE_LOCAL),
+            MemoryUsage::CpuToGpu => (host, vk::MemoryPropertyFlags::DEVICE_LOCAL),
+            MemoryUsage::GpuToCpu => (host, vk::MemoryPropertyFlags::HOST_CACHED),
+        }
+    }
+}
+
+/// How allocations are placed within a block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocationStrategy {
+    /// Reuses freed ranges, coalescing them with their neighbours. Suited to long-lived resources.
+    FreeList,
+    /// Allocates by bumping an offset, and only reclaims space once every allocation in the block is freed.
+    /// Suited to transient resources which are freed together.
+    Linear,
+}
+
+/// Whether a resource is laid out linearly, such as buffers and linear images, or optimally, such as optimal tiling
+/// images. Linear and optimal resources must not share a page of `bufferImageGranularity` bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceTiling {
+    Linear,
+    Optimal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocationError {
+    /// No memory type satisfies both the resource's requirements and the required property flags.
+    NoSuitableMemoryType,
+    /// The driver failed to allocate a new block.
+    OutOfMemory(vk::Result),
+}
+
+impl fmt::Display for AllocationError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllocationError::NoSuitableMemoryType => write!(f, "No memory type is suitable for the resource"),
+            AllocationError::OutOfMemory(result) => write!(f, "Failed to allocate a memory block ({})", result),
+        }
+    }
+}
+
+impl error::Error for AllocationError {}
+
+/// A range of device memory sub-allocated from a larger block. It must be returned with `Device::free_memory`.
+pub struct Allocation {
+    block_id : u64,
+    memory : vk::DeviceMemory,
+    memory_type_index : u32,
+    offset : vk::DeviceSize,
+    size : vk::DeviceSize,
+    mapped : *mut u8,
+}
+
+impl Allocation {
+    pub fn memory(&self) -> vk::DeviceMemory {
+        self.memory
+    }
+
+    pub fn memory_type_index(&self) -> u32 {
+        self.memory_type_index
+    }
+
+    /// Returns the offset of the allocation within `memory`, which resources should be bound at.
+    pub fn offset(&self) -> vk::DeviceSize {
+        self.offset
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    /// Returns the host mapping of the allocation, if it was placed in host visible memory. Blocks are mapped
+    /// persistently, so the mapping stays valid until the allocation is freed.
+    pub fn mapped_slice(&self) -> Option<&[u8]> {
+        if self.mapped.is_null() {
+            return None;
+        }
+        Some(unsafe { slice::from_raw_parts(self.mapped, self.size as usize) })
+    }
+
+    /// Returns the host mapping of the allocation mutably, if it was placed in host visible memory.
+    pub fn mapped_slice_mut(&mut self) -> Option<&mut [u8]> {
+        if self.mapped.is_null() {
+            return None;
+        }
+        Some(unsafe { slice::from_raw_parts_mut(self.mapped, self.size as usize) })
+    }
+}
+
+/// Usage statistics over every block owned by an allocator.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemoryStats {
+    /// Number of `vkAllocateMemory` calls currently alive.
+    pub block_count : usize,
+    pub allocation_count : usize,
+    /// Bytes of device memory allocated for blocks.
+    pub reserved_bytes : vk::DeviceSize,
+    /// Bytes handed out to allocations, excluding alignment padding.
+    pub used_bytes : vk::DeviceSize,
+    /// Bytes within blocks which are not handed out to allocations.
+    pub free_bytes : vk::DeviceSize,
+    pub largest_free_range : vk::DeviceSize,
+    /// Ranges from 0, where all free memory is contiguous, towards 1, where it is split into many small ranges.
+    pub fragmentation : f32,
+}
+
+fn align_up(value : vk::DeviceSize, alignment : vk::DeviceSize) -> vk::DeviceSize {
+    if alignment <= 1 {
+        value
+    } else {
+        value.div_ceil(alignment) * alignment
+    }
+}
+
+/// Returns true if the last byte of a resource ending at `end` shares a page with a resource starting at `start`.
+fn on_same_page(end : vk::DeviceSize, start : vk::DeviceSize, page_size : vk::DeviceSize) -> bool {
+    page_size > 1 && (end - 1) / page_size == start / page_size
+}
+
+/// A single `vkAllocateMemory` call which allocations are carved out of.
+struct Block {
+    id : u64,
+    memory : vk::DeviceMemory,
+    memory_type_index : u32,
+    size : vk::DeviceSize,
+    strategy : AllocationStrategy,
+    mapped : *mut u8,
+    /// Live allocations keyed by their offset.
+    allocations : BTreeMap<vk::DeviceSize, (vk::DeviceSize, ResourceTiling)>,
+    /// Free ranges of `(offset, size)` sorted by offset. Only used by the free-list strategy.
+    free_ranges : Vec<(vk::DeviceSize, vk::DeviceSize)>,
+    /// End of the last allocation. Only used by the linear strategy.
+    linear_offset : vk::DeviceSize,
+}
+
+impl Block {
+    fn new(id : u64,
+           memory : vk::DeviceMemory,
+           memory_type_index : u32,
+           size : vk::DeviceSize,
+           strategy : AllocationStrategy,
+           mapped : *mut u8) -> Self {
+        Self {
+            id,
+            memory,
+            memory_type_index,
+            size,
+            strategy,
+            mapped,
+            allocations: BTreeMap::new(),
+            free_ranges: vec![(0, size)],
+            linear_offset: 0,
+        }
+    }
+
+    /// Returns true if a resource with `tiling` must be kept off the pages of the neighbouring allocation.
+    fn conflicts(neighbour : Option<(&vk::DeviceSize, &(vk::DeviceSize, ResourceTiling))>, tiling : ResourceTiling) -> bool {
+        neighbour.is_some_and(|(_, (_, neighbour_tiling))| *neighbour_tiling != tiling)
+    }
+
+    /// Finds space for the allocation, returning its offset.
+    fn allocate(&mut self,
+                size : vk::DeviceSize,
+                alignment : vk::DeviceSize,
+                tiling : ResourceTiling,
+                granularity : vk::DeviceSize) -> Option<vk::DeviceSize> {
+        let offset = match self.strategy {
+            AllocationStrategy::Linear => {
+                let mut offset = align_up(self.linear_offset, alignment);
+                let previous = self.allocations.iter().next_back();
+                if Self::conflicts(previous, tiling) && on_same_page(self.linear_offset, offset, granularity) {
+                    offset = align_up(offset, granularity);
+                }
+                if offset + size > self.size {
+                    return None;
+                }
+                self.linear_offset = offset + size;
+                offset
+            },
+            AllocationStrategy::FreeList => {
+                let (index, offset) = self.free_ranges
+                    .iter()
+                    .enumerate()
+                    .find_map(|(index, &(start, length))| {
+                        let mut offset = align_up(start, alignment);
+                        let previous = self.allocations.range(..start).next_back();
+                        if Self::conflicts(previous, tiling) && on_same_page(start, offset, granularity) {
+                            offset = align_up(offset, granularity);
+                        }
+                        let end = offset + size;
+                        if end > start + length {
+                            return None;
+                        }
+                        let next = self.allocations.range(start + length..).next();
+                        if Self::conflicts(next, tiling) && on_same_page(end, start + length, granularity) {
+                            return None;
+                        }
+                        Some((index, offset))
+                    })?;
+
+                // Split the free range around the allocation, keeping any padding free.
+                let (start, length) = self.free_ranges.remove(index);
+                let end = offset + size;
+                if end < start + length {
+                    self.free_ranges.insert(index, (end, start + length - end));
+                }
+                if offset > start {
+                    self.free_ranges.insert(index, (start, offset - start));
+                }
+                offset
+            },
+        };
+        self.allocations.insert(offset, (size, tiling));
+        Some(offset)
+    }
+
+    fn free(&mut self, offset : vk::DeviceSize) {
+        let (size, _) = self.allocations
+            .remove(&offset)
+            .expect("Freed an allocation which does not belong to this block");
+        match self.strategy {
+            AllocationStrategy::Linear => {
+                if self.allocations.is_empty() {
+                    self.linear_offset = 0;
+                }
+            },
+            AllocationStrategy::FreeList => {
+                let index = self.free_ranges.partition_point(|&(start, _)| start < offset);
+                self.free_ranges.insert(index, (offset, size));
+                // Coalesce with the following range, then the preceding one.
+                if index + 1 < self.free_ranges.len() && offset + size == self.free_ranges[index + 1].0 {
+                    let (_, next_length) = self.free_ranges.remove(index + 1);
+                    self.free_ranges[index].1 += next_length;
+                }
+                if index > 0 {
+                    let (previous_start, previous_length) = self.free_ranges[index - 1];
+                    if previous_start + previous_length == offset {
+                        self.free_ranges[index - 1].1 += self.free_ranges[index].1;
+                        self.free_ranges.remove(index);
+                    }
+                }
+            },
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.allocations.is_empty()
+    }
+
+    fn used_bytes(&self) -> vk::DeviceSize {
+        self.allocations.values().map(|(size, _)| size).sum()
+    }
+
+    /// Returns the sizes of the ranges which new allocations can be placed in.
+    fn free_range_sizes(&self) -> Vec<vk::DeviceSize> {
+        match self.strategy {
+            AllocationStrategy::Linear => vec![self.size - self.linear_offset],
+            AllocationStrategy::FreeList => self.free_ranges.iter().map(|(_, length)| *length).collect(),
+        }
+    }
+}
+
+/// Sub-allocates device memory from large blocks, rather than calling `vkAllocateMemory` for every resource.
+pub struct Allocator {
+    memory_properties : vk::PhysicalDeviceMemoryProperties,
+    buffer_image_granularity : vk::DeviceSize,
+    blocks : Vec<Block>,
+    next_block_id : u64,
+}
+
+impl Allocator {
+    pub fn new(memory_properties : vk::PhysicalDeviceMemoryProperties, limits : vk::PhysicalDeviceLimits) -> Self {
+        Self {
+            memory_properties,
+            buffer_image_granularity: limits.buffer_image_granularity,
+            blocks: Vec::new(),
+            next_block_id: 0,
+        }
+    }
+
+    /// Allocates memory for a resource. The memory type with both the required and preferred flags of `usage` is
+    /// tried first, falling back to one which only has the required flags.
+    pub fn allocate(&mut self,
+                    device : &ash::Device,
+                    requirements : &vk::MemoryRequirements,
+                    usage : MemoryUsage,
+                    strategy : AllocationStrategy,
+                    tiling : ResourceTiling) -> Result<Allocation,AllocationError> {
+        let (required, preferred) = usage.property_flags();
+        let preferred_type = find_memory_type_index(requirements, &self.memory_properties, required | preferred);
+        let required_type = find_memory_type_index(requirements, &self.memory_properties, required);
+        let mut result = Err(AllocationError::NoSuitableMemoryType);
+        for memory_type_index in [preferred_type, required_type].into_iter().flatten() {
+            result = self.allocate_from_type(device, requirements, memory_type_index, strategy, tiling);
+            if result.is_ok() {
+                break;
+            }
+            debug!("Failed to allocate from memory type {}, trying the next suitable type", memory_type_index);
+        }
+        result
+    }
+
+    fn allocate_from_type(&mut self,
+                          device : &ash::Device,
+                          requirements : &vk::MemoryRequirements,
+                          memory_type_index : u32,
+                          strategy : AllocationStrategy,
+                          tiling : ResourceTiling) -> Result<Allocation,AllocationError> {
+        let granularity = self.buffer_image_granularity;
+        let existing = self.blocks
+            .iter_mut()
+            .filter(|block| block.memory_type_index == memory_type_index && block.strategy == strategy)
+            .find_map(|block| block
+                .allocate(requirements.size, requirements.alignment, tiling, granularity)
+                .map(|offset| (block.id, offset)));
+        let (block_id, offset) = match existing {
+            Some(found) => found,
+            None => {
+                let block = self.create_block(device, memory_type_index, requirements.size, strategy)?;
+                let offset = block
+                    .allocate(requirements.size, requirements.alignment, tiling, granularity)
+                    .expect("A new block must fit the allocation it was created for");
+                (block.id, offset)
+            },
+        };
+
+        let block = self.blocks.iter().find(|block| block.id == block_id).unwrap();
+        let mapped = if block.mapped.is_null() {
+            ptr::null_mut()
+        } else {
+            unsafe { block.mapped.add(offset as usize) }
+        };
+        Ok(Allocation {
+            block_id,
+            memory: block.memory,
+            memory_type_index,
+            offset,
+            size: requirements.size,
+            mapped,
+        })
+    }
+
+    /// Allocates a new block, mapping it persistently if it is host visible.
+    fn create_block(&mut self,
+                    device : &ash::Device,
+                    memory_type_index : u32,
+                    minimum_size : vk::DeviceSize,
+                    strategy : AllocationStrategy) -> Result<&mut Block,AllocationError> {
+        let memory_type = self.memory_properties.memory_types[memory_type_index as usize];
+        let heap_size = self.memory_properties.memory_heaps[memory_type.heap_index as usize].size;
+        // Avoid reserving a large share of small heaps, such as the host visible device local heap on some GPUs.
+        let size = DEFAULT_BLOCK_SIZE.min(heap_size / 8).max(minimum_size);
+
+        let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type_index);
+        let memory = unsafe {
+            device
+                .allocate_memory(&allocate_info, None)
+                .map_err(AllocationError::OutOfMemory)?
+        };
+        let mapped = if memory_type.property_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            unsafe {
+                match device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()) {
+                    Ok(mapped) => mapped as *mut u8,
+                    Err(error) => {
+                        device.free_memory(memory, None);
+                        return Err(AllocationError::OutOfMemory(error));
+                    }
+                }
+            }
+        } else {
+            ptr::null_mut()
+        };
+
+        debug!("Allocated a {} byte {:?} block from memory type {}", size, strategy, memory_type_index);
+        let id = self.next_block_id;
+        self.next_block_id += 1;
+        self.blocks.push(Block::new(id, memory, memory_type_index, size, strategy, mapped));
+        Ok(self.blocks.last_mut().unwrap())
+    }
+
+    /// Returns the allocation's range to its block. Empty blocks are released, unless they are the last block of
+    /// their memory type and strategy.
+    pub fn free(&mut self, device : &ash::Device, allocation : Allocation) {
+        let index = self.blocks
+            .iter()
+            .position(|block| block.id == allocation.block_id)
+            .expect("Freed an allocation which does not belong to this allocator");
+        self.blocks[index].free(allocation.offset);
+
+        let block = &self.blocks[index];
+        let siblings = self.blocks
+            .iter()
+            .filter(|other| other.memory_type_index == block.memory_type_index && other.strategy == block.strategy)
+            .count();
+        if block.is_empty() && siblings > 1 {
+            let block = self.blocks.remove(index);
+            Self::destroy_block(device, &block);
+        }
+    }
+
+    fn destroy_block(device : &ash::Device, block : &Block) {
+        unsafe {
+            if !block.mapped.is_null() {
+                device.unmap_memory(block.memory);
+            }
+            device.free_memory(block.memory, None);
+        }
+    }
+
+    /// Releases every block. All allocations must have been freed beforehand.
+    pub fn destroy(&mut self, device : &ash::Device) {
+        for block in self.blocks.drain(..) {
+            if !block.is_empty() {
+                warn!("Releasing a memory block with {} live allocations", block.allocations.len());
+            }
+            Self::destroy_block(device, &block);
+        }
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats { block_count: self.blocks.len(), ..Default::default() };
+        for block in &self.blocks {
+            stats.allocation_count += block.allocations.len();
+            stats.reserved_bytes += block.size;
+            stats.used_bytes += block.used_bytes();
+            for free_range in block.free_range_sizes() {
+                stats.free_bytes += free_range;
+                stats.largest_free_range = stats.largest_free_range.max(free_range);
+            }
+        }
+        if stats.free_bytes > 0 {
+            stats.fragmentation = 1.0 - stats.largest_free_range as f32 / stats.free_bytes as f32;
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRANULARITY : vk::DeviceSize = 1024;
+
+    fn new_block(strategy : AllocationStrategy, size : vk::DeviceSize) -> Block {
+        Block::new(0, vk::DeviceMemory::null(), 0, size, strategy, ptr::null_mut())
+    }
+
+    #[test]
+    fn splits_free_range_around_allocation() {
+        let mut block = new_block(AllocationStrategy::FreeList, 4096);
+        assert_eq!(block.allocate(100, 1, ResourceTiling::Linear, 1), Some(0));
+        // The padding before the aligned offset stays free, as does the rest of the block.
+        assert_eq!(block.allocate(100, 256, ResourceTiling::Linear, 1), Some(256));
+        assert_eq!(block.free_ranges, [(100, 156), (356, 3740)]);
+        // Small allocations reuse the padding.
+        assert_eq!(block.allocate(50, 4, ResourceTiling::Linear, 1), Some(100));
+        assert_eq!(block.free_ranges, [(150, 106), (356, 3740)]);
+    }
+
+    #[test]
+    fn coalesces_adjacent_frees() {
+        let mut block = new_block(AllocationStrategy::FreeList, 300);
+        let offsets : Vec<_> = (0..3).map(|_| block.allocate(100, 1, ResourceTiling::Linear, 1).unwrap()).collect();
+        assert!(block.free_ranges.is_empty());
+
+        block.free(offsets[0]);
+        block.free(offsets[2]);
+        assert_eq!(block.free_ranges, [(0, 100), (200, 100)]);
+        // Freeing the middle joins both neighbours into a single range.
+        block.free(offsets[1]);
+        assert_eq!(block.free_ranges, [(0, 300)]);
+        assert!(block.is_empty());
+        assert_eq!(block.allocate(300, 1, ResourceTiling::Linear, 1), Some(0));
+    }
+
+    #[test]
+    fn pads_linear_and_optimal_neighbours_by_granularity() {
+        let mut block = new_block(AllocationStrategy::FreeList, 8 * GRANULARITY);
+        assert_eq!(block.allocate(100, 16, ResourceTiling::Linear, GRANULARITY), Some(0));
+        // An optimal resource must start on the next page after a linear one.
+        assert_eq!(block.allocate(100, 16, ResourceTiling::Optimal, GRANULARITY), Some(GRANULARITY));
+        // Resources with the same tiling may share a page.
+        assert_eq!(block.allocate(100, 16, ResourceTiling::Optimal, GRANULARITY), Some(GRANULARITY + 112));
+
+        let mut block = new_block(AllocationStrategy::Linear, 8 * GRANULARITY);
+        assert_eq!(block.allocate(100, 16, ResourceTiling::Optimal, GRANULARITY), Some(0));
+        assert_eq!(block.allocate(100, 16, ResourceTiling::Linear, GRANULARITY), Some(GRANULARITY));
+        assert_eq!(block.allocate(100, 16, ResourceTiling::Linear, GRANULARITY), Some(GRANULARITY + 112));
+    }
+
+    #[test]
+    fn rejects_allocations_which_do_not_fit() {
+        let mut block = new_block(AllocationStrategy::FreeList, 1024);
+        assert_eq!(block.allocate(1025, 1, ResourceTiling::Linear, 1), None);
+        assert_eq!(block.allocate(1000, 1, ResourceTiling::Linear, 1), Some(0));
+        // Alignment pushes the second allocation past the end of the block.
+        assert_eq!(block.allocate(16, 64, ResourceTiling::Linear, 1), None);
+        assert_eq!(block.free_ranges, [(1000, 24)]);
+
+        let mut block = new_block(AllocationStrategy::Linear, 1024);
+        assert_eq!(block.allocate(1000, 1, ResourceTiling::Linear, 1), Some(0));
+        assert_eq!(block.allocate(100, 1, ResourceTiling::Linear, 1), None);
+    }
+
+    #[test]
+    fn resets_linear_block_once_empty() {
+        let mut block = new_block(AllocationStrategy::Linear, 1024);
+        let first = block.allocate(400, 1, ResourceTiling::Linear, 1).unwrap();
+        let second = block.allocate(400, 1, ResourceTiling::Linear, 1).unwrap();
+        assert_eq!(second, 400);
+
+        // Space is only reclaimed once every allocation has been freed.
+        block.free(first);
+        assert_eq!(block.allocate(400, 1, ResourceTiling::Linear, 1), None);
+        block.free(second);
+        assert_eq!(block.free_range_sizes(), [1024]);
+        assert_eq!(block.allocate(1024, 1, ResourceTiling::Linear, 1), Some(0));
+    }
+
+    #[test]
+    fn reports_stats_after_frees() {
+        let mut allocator = Allocator::new(Default::default(), Default::default());
+        let mut block = new_block(AllocationStrategy::FreeList, 1000);
+        let offsets : Vec<_> = (0..4).map(|_| block.allocate(100, 1, ResourceTiling::Linear, 1).unwrap()).collect();
+        block.free(offsets[0]);
+        block.free(offsets[2]);
+        allocator.blocks.push(block);
+
+        let stats = allocator.stats();
+        assert_eq!(stats.block_count, 1);
+        assert_eq!(stats.allocation_count, 2);
+        assert_eq!(stats.reserved_bytes, 1000);
+        assert_eq!(stats.used_bytes, 200);
+        assert_eq!(stats.free_bytes, 800);
+        assert_eq!(stats.largest_free_range, 600);
+        assert!((stats.fragmentation - 0.25).abs() < f32::EPSILON);
+    }
+}
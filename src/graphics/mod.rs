@@ -1,31 +1,57 @@
+/// Scores the available physical devices and selects the most suitable one.
+pub mod adapter;
 pub mod buffer;
 pub mod cmd;
+/// Runs compute work on the compute queue, blocking until its results can be read.
+pub mod compute;
+/// Validation layer settings, and the messenger which logs validation messages.
 pub mod debug;
+/// Descriptor set layouts, pools and sets, which bind buffers and images to shaders.
+pub mod descriptor;
 pub mod device;
+/// The error type shared by the graphics module.
+pub mod error;
+/// Per-frame command buffers and synchronization, allowing several frames to be in flight at once.
+pub mod frame;
 pub mod framebuffer;
+/// Images which own their memory, such as offscreen render targets.
+pub mod image;
 pub mod instance;
 /// Defines the appearance of a renderable object. Currently provides basic options for a `ColoredMaterial` or a `TexturedMaterial`.
 pub mod material;
+/// Sub-allocates device memory for buffers and images from large blocks.
+pub mod memory;
 pub mod pass;
 pub mod pipeline;
+/// Persists compiled pipelines between runs.
+pub mod pipeline_cache;
 /// Platform-specific helper functions.
 pub mod platform;
+/// Reflects SPIR-V modules, so pipeline layouts and vertex inputs can be derived from the shaders which use them.
+pub mod reflect;
 /// Operations for a queue, such as submitting graphics, compute, or transfer operations for execution by the GPU.
 pub mod queue;
-/// Manages a Vulkan surface and swapchain, presenting the acquired images to the screen.
+/// The platform surface which is presented to.
+pub mod surface;
+/// Manages a Vulkan swapchain, presenting the acquired images to the screen.
 pub mod swapchain;
 pub mod renderer;
+/// Uploads data into device local buffers and images through staging buffers on the transfer queue.
+pub mod upload;
 /// Utilities for common functionality used in Vulkan.
 pub mod util;
 
+pub use self::error::Error;
 pub use self::renderer::Renderer;
-use self::buffer::VertexBuffer;
+use self::buffer::{IndexBuffer, VertexBuffer};
 use self::cmd::{CmdBuffer, CmdPool, CmdState};
-use self::device::{Device, DeviceCreationError};
+use self::device::Device;
 use self::framebuffer::{Framebuffer, FramebufferBuilder};
+use self::image::{Image, OffscreenTarget};
 use self::instance::Instance;
 use self::material::{Material, Vertex};
 use self::pass::{RenderPass, RenderPassBuilder};
 use self::pipeline::{Pipeline, PipelineBuilder};
 use self::queue::Queue;
-use self::swapchain::{Swapchain, SwapchainCreationError};
+use self::surface::Surface;
+use self::swapchain::Swapchain;
@@ -1,6 +1,6 @@
-use std::{cell::RefCell, default::Default, rc::Rc};
+use std::{cell::RefCell, rc::Rc};
 use ash::vk;
-use super::Device;
+use super::{Device, error::{Error, VkResultExt}};
 
 /// Represents how the begin to end state for rendering should occur.
 // TODO: Create builder for this object due to somewhat complicated state.
@@ -23,6 +23,11 @@ impl RenderPass {
     pub fn render_pass_raw(&self) -> vk::RenderPass {
         self.render_pass
     }
+
+    /// Names the render pass in validation messages and debuggers. Does nothing unless debug utils are enabled.
+    pub fn set_debug_name(&self, name : &str) {
+        self.device.borrow().set_object_name(self.render_pass, name);
+    }
 }
 
 pub struct RenderPassBuilder {
@@ -40,11 +45,18 @@ impl RenderPassBuilder {
             depth_stencil_attachment: None }
     }
 
-    pub fn add_color_attachment(mut self, format : vk::Format) -> Self {
+    /// Adds a color attachment which will be presented to the swapchain once the render pass ends.
+    pub fn add_color_attachment(self, format : vk::Format) -> Self {
+        self.add_color_attachment_with_layout(format, vk::ImageLayout::PRESENT_SRC_KHR)
+    }
+
+    /// Adds a color attachment which is transitioned to `final_layout` once the render pass ends. Offscreen
+    /// attachments should use this, as `PRESENT_SRC_KHR` is only valid with the swapchain extension.
+    pub fn add_color_attachment_with_layout(mut self, format : vk::Format, final_layout : vk::ImageLayout) -> Self {
         self.color_attachments.push(vk::AttachmentDescription::builder()
             .format(format)
             .samples(vk::SampleCountFlags::TYPE_1)
-            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .final_layout(final_layout)
             .load_op(vk::AttachmentLoadOp::CLEAR)
             .store_op(vk::AttachmentStoreOp::STORE)
             .build());
@@ -67,7 +79,7 @@ impl RenderPassBuilder {
         self
     }
 
-    pub fn build(self) -> RenderPass {
+    pub fn build(self) -> Result<RenderPass,Error> {
         let subpass = if self.depth_stencil_attachment.is_some(){
             let depth_stencil_reference = vk::AttachmentReference::builder()
                 .attachment(self.color_attachments.len() as u32)
@@ -84,17 +96,41 @@ impl RenderPassBuilder {
                 .build()
         };
 
+        let dependencies = [
+            // The layout transition at the start of the pass must wait for the stage which waits on the swapchain's
+            // acquire semaphore, otherwise it may write to an image which is still being presented.
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .build(),
+            // The implicit dependency at the end of the pass does not make color writes visible to later work, so
+            // offscreen attachments could be copied from before rendering had finished.
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .build(),
+        ];
+        let subpasses = [subpass];
         let render_pass_info = vk::RenderPassCreateInfo::builder()
             .attachments(self.color_attachments.as_slice())
-            .subpasses(&[subpass])
+            .subpasses(&subpasses)
+            .dependencies(&dependencies)
             .build();
         let render_pass = unsafe {
             self.device
                 .borrow()
                 .ash_device()
                 .create_render_pass(&render_pass_info, None)
-                .expect("Failed to create render pass")
+                .context("Failed to create a render pass")?
         };
-        RenderPass { device: Rc::clone(&self.device), render_pass }
+        Ok(RenderPass { device: Rc::clone(&self.device), render_pass })
     }
 }
\ No newline at end of file
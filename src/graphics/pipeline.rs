@@ -1,12 +1,16 @@
-use std::{cell::RefCell, ffi::CString, rc::Rc};
+use std::{cell::RefCell, io::Cursor, rc::Rc};
 use ash::vk;
-use super::{Device, Material, RenderPass};
+use super::{Device, Material, RenderPass, error::{Error, VkResultExt}};
+use super::descriptor::{DescriptorSetLayout, DescriptorSetLayoutBuilder};
+use super::reflect::{PipelineInterface, ShaderReflection};
 
 /// Represents the flow of the graphics pipeline from the vertex to fragment stage.
 pub struct Pipeline {
     device : Rc<RefCell<Device>>,
     pipeline : vk::Pipeline,
     layout : vk::PipelineLayout,
+    // Destroyed after the pipeline layout, which is created from them.
+    set_layouts : Vec<DescriptorSetLayout>,
     supports_compute : bool,
     supports_graphics : bool,
 }
@@ -29,65 +33,275 @@ impl Pipeline {
     pub fn layout_raw(&self) -> vk::PipelineLayout {
          self.layout
     }
+    /// Returns the descriptor set layouts created from shader reflection, in set order, so sets can be allocated for
+    /// the pipeline. This is empty if the layouts were passed to `build_compute`.
+    pub fn set_layouts(&self) -> &[DescriptorSetLayout] {
+        &self.set_layouts
+    }
+    /// Names the pipeline and its layout in validation messages and debuggers. Does nothing unless debug utils are
+    /// enabled.
+    pub fn set_debug_name(&self, name : &str) {
+        let device = self.device.borrow();
+        device.set_object_name(self.pipeline, name);
+        device.set_object_name(self.layout, &format!("{} layout", name));
+    }
     pub fn supports_compute(&self) -> bool {
         self.supports_compute
     }
     pub fn supports_graphics(&self) -> bool {
         self.supports_graphics
     }
+
+    /// Returns the bind point which the pipeline and its descriptor sets are bound to.
+    pub fn bind_point(&self) -> vk::PipelineBindPoint {
+        if self.supports_compute {
+            vk::PipelineBindPoint::COMPUTE
+        } else {
+            vk::PipelineBindPoint::GRAPHICS
+        }
+    }
 }
 
+/// A preset for how fragment colors are combined with the contents of a color attachment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Fragments replace the attachment contents.
+    Opaque,
+    /// Fragments are blended by their alpha, for transparent geometry with straight alpha.
+    Alpha,
+    /// Fragments are weighted by their alpha and added to the attachment, for effects such as particles and glows.
+    Additive,
+    /// Fragments are blended by their alpha, for colors which were already multiplied by alpha, such as UI.
+    Premultiplied,
+}
+
+impl BlendMode {
+    fn attachment_state(self) -> vk::PipelineColorBlendAttachmentState {
+        let builder = vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::R | vk::ColorComponentFlags::G |
+                vk::ColorComponentFlags::B | vk::ColorComponentFlags::A);
+        let (src_color, dst_color, src_alpha, dst_alpha) = match self {
+            BlendMode::Opaque => return builder.build(),
+            BlendMode::Alpha => (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+            BlendMode::Additive => (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ZERO,
+                vk::BlendFactor::ONE),
+            BlendMode::Premultiplied => (
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+        };
+        builder
+            .blend_enable(true)
+            .src_color_blend_factor(src_color)
+            .dst_color_blend_factor(dst_color)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(src_alpha)
+            .dst_alpha_blend_factor(dst_alpha)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .build()
+    }
+}
+
+/// Offsets the depth of rasterized fragments, which is commonly used to avoid acne in shadow maps and z-fighting
+/// with decals.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DepthBias {
+    pub constant_factor : f32,
+    pub clamp : f32,
+    pub slope_factor : f32,
+}
+
+/// Configures the fixed-function state of a pipeline. The defaults draw filled, opaque triangle lists without culling,
+/// depth testing or multisampling.
 pub struct PipelineBuilder {
     device : Rc<RefCell<Device>>,
+    topology : vk::PrimitiveTopology,
+    primitive_restart : bool,
+    polygon_mode : vk::PolygonMode,
+    cull_mode : vk::CullModeFlags,
+    front_face : vk::FrontFace,
+    depth_bias : Option<DepthBias>,
+    depth_test : Option<vk::CompareOp>,
+    depth_write : bool,
+    stencil : Option<(vk::StencilOpState, vk::StencilOpState)>,
+    blend_modes : Vec<BlendMode>,
+    samples : vk::SampleCountFlags,
+    dynamic_states : Vec<vk::DynamicState>,
 }
 
 impl PipelineBuilder {
     /// Creates a new pipeline using the initial shader. This would be either a compute shader, or a vertex shader.
     pub fn new(device : Rc<RefCell<Device>>) -> Self {
-        Self { device, }
+        Self { device,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart: false,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            depth_bias: None,
+            depth_test: None,
+            depth_write: false,
+            stencil: None,
+            blend_modes: vec![BlendMode::Opaque],
+            samples: vk::SampleCountFlags::TYPE_1,
+            dynamic_states: Vec::new(),
+        }
+    }
+
+    /// Sets how vertices are assembled into primitives.
+    pub fn topology(mut self, topology : vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    /// Lets the maximum index value restart strip and fan topologies, so several strips can be drawn at once.
+    pub fn primitive_restart(mut self, primitive_restart : bool) -> Self {
+        self.primitive_restart = primitive_restart;
+        self
+    }
+
+    /// Sets whether polygons are filled, or drawn as lines or points, such as for wireframes.
+    pub fn polygon_mode(mut self, polygon_mode : vk::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    /// Sets which faces are culled, and the winding order of front faces.
+    pub fn cull_mode(mut self, cull_mode : vk::CullModeFlags, front_face : vk::FrontFace) -> Self {
+        self.cull_mode = cull_mode;
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn depth_bias(mut self, depth_bias : DepthBias) -> Self {
+        self.depth_bias = Some(depth_bias);
+        self
+    }
+
+    /// Enables depth testing with `compare_op`, optionally writing the depth of passing fragments. The render pass
+    /// must have a depth attachment.
+    pub fn depth_test(mut self, compare_op : vk::CompareOp, depth_write : bool) -> Self {
+        self.depth_test = Some(compare_op);
+        self.depth_write = depth_write;
+        self
+    }
+
+    /// Enables stencil testing, with separate operations for front and back faces. The render pass must have a
+    /// depth-stencil attachment.
+    pub fn stencil_test(mut self, front : vk::StencilOpState, back : vk::StencilOpState) -> Self {
+        self.stencil = Some((front, back));
+        self
+    }
+
+    /// Uses the same blend mode for every color attachment of the render pass.
+    pub fn blend_mode(mut self, blend_mode : BlendMode) -> Self {
+        self.blend_modes = vec![blend_mode];
+        self
+    }
+
+    /// Sets a blend mode for each color attachment, in the order they were added to the render pass.
+    pub fn attachment_blend_modes(mut self, blend_modes : &[BlendMode]) -> Self {
+        self.blend_modes = blend_modes.to_vec();
+        self
+    }
+
+    /// Sets the number of samples per pixel, which must match the attachments of the render pass.
+    pub fn samples(mut self, samples : vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Makes additional state dynamic, such as `LINE_WIDTH`, `DEPTH_BIAS` or `STENCIL_REFERENCE`, so it must be set
+    /// while recording instead of when the pipeline is built. The viewport and scissor are always dynamic. Besides the
+    /// Vulkan 1.0 states, only extension states which need no device features are supported, and only if the device
+    /// enabled their extension; building fails with `Error::UnsupportedDynamicState` otherwise.
+    pub fn dynamic_states(mut self, dynamic_states : &[vk::DynamicState]) -> Self {
+        self.dynamic_states.extend_from_slice(dynamic_states);
+        self
     }
 
-    /// Builds a graphics pipeline.
-    pub fn build_graphics(self, render_pass : &RenderPass, material : &Material, extent : vk::Extent2D) -> Pipeline {
-        let color_blend_attachments = vec![
-            vk::PipelineColorBlendAttachmentState::builder()
-                .color_write_mask(vk::ColorComponentFlags::R | vk::ColorComponentFlags::G |
-                    vk::ColorComponentFlags::B | vk::ColorComponentFlags::A).build()];
+    /// Builds a graphics pipeline. The viewport and scissor are dynamic, so they must be set with
+    /// `CmdBuffer::set_viewport` and `CmdBuffer::set_scissor` before drawing, and the pipeline can be used with
+    /// render targets of any size.
+    pub fn build_graphics(self,
+                          render_pass : &RenderPass,
+                          material : &Material) -> Result<Pipeline,Error> {
+        let color_blend_attachments : Vec<_> = self.blend_modes
+            .iter()
+            .map(|blend_mode| blend_mode.attachment_state())
+            .collect();
 
         let color_blend_info = vk::PipelineColorBlendStateCreateInfo::builder()
             .attachments(color_blend_attachments.as_slice());
 
         let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+            .topology(self.topology)
+            .primitive_restart_enable(self.primitive_restart);
 
         let multisample_info = vk::PipelineMultisampleStateCreateInfo::builder()
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+            .rasterization_samples(self.samples);
 
+        let depth_bias = self.depth_bias.unwrap_or(DepthBias { constant_factor: 0.0, clamp: 0.0, slope_factor: 0.0 });
         let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(self.polygon_mode)
+            .cull_mode(self.cull_mode)
+            .front_face(self.front_face)
+            .depth_bias_enable(self.depth_bias.is_some())
+            .depth_bias_constant_factor(depth_bias.constant_factor)
+            .depth_bias_clamp(depth_bias.clamp)
+            .depth_bias_slope_factor(depth_bias.slope_factor)
             .line_width(1.0);
 
-        let viewports = vec![
-            vk::Viewport::builder()
-                .width(extent.width as _)
-                .height(extent.height as _)
-                .max_depth(1.0).build()];
-        let scissors = vec![
-            vk::Rect2D::builder()
-                .extent(extent).build()];
+        let (stencil_front, stencil_back) = self.stencil.unwrap_or_default();
+        let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(self.depth_test.is_some())
+            .depth_write_enable(self.depth_write)
+            .depth_compare_op(self.depth_test.unwrap_or(vk::CompareOp::ALWAYS))
+            .stencil_test_enable(self.stencil.is_some())
+            .front(stencil_front)
+            .back(stencil_back)
+            .max_depth_bounds(1.0);
+
         let viewport_info = vk::PipelineViewportStateCreateInfo::builder()
-            .viewports(viewports.as_slice())
-            .scissors(scissors.as_slice());
+            .viewport_count(1)
+            .scissor_count(1);
 
-        // Currently we are not using this for anything, but in the future this will hold
-        // information relating to bindings. A layout is required for creating a pipeline.
-        let layout_info = vk::PipelineLayoutCreateInfo::builder();
+        if let Some(state) = self.dynamic_states
+            .iter()
+            .find(|state| !is_dynamic_state_supported(&self.device.borrow(), **state)) {
+            return Err(Error::UnsupportedDynamicState(*state));
+        }
+        let mut dynamic_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        for dynamic_state in self.dynamic_states {
+            if !dynamic_states.contains(&dynamic_state) {
+                dynamic_states.push(dynamic_state);
+            }
+        }
+        let dynamic_info = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(dynamic_states.as_slice());
+
+        // The layout is derived from the descriptors and push constants which the shaders use.
+        let interface = material.interface();
+        let set_layouts = create_set_layouts(&self.device, interface)?;
+        let raw_set_layouts : Vec<_> = set_layouts.iter().map(|layout| layout.layout_raw()).collect();
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&raw_set_layouts)
+            .push_constant_ranges(interface.push_constant_ranges());
 
         let layout = unsafe {
             self.device
                 .borrow()
                 .ash_device()
                 .create_pipeline_layout(&layout_info, None)
-                .unwrap()
+                .context("Failed to create a pipeline layout")?
         };
 
         let stages = material.pipeline_shader_stages();
@@ -104,25 +318,138 @@ impl PipelineBuilder {
             .stages(stages.as_slice())
             .vertex_input_state(&vertex_input_stage)
             .viewport_state(&viewport_info)
+            .dynamic_state(&dynamic_info)
+            // Ignored unless the render pass has a depth-stencil attachment.
+            .depth_stencil_state(&depth_stencil_info)
             .build();
 
         // Create pipeline and destroy unneeded shader modules.
         let pipeline = unsafe {
-            self.device
-                .borrow()
+            let device = self.device.borrow();
+            device
                 .ash_device()
-                .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
-                .expect("Failed to create pipeline").remove(0)
+                .create_graphics_pipelines(device.pipeline_cache(), &[pipeline_info], None)
+                .map_err(|(_, result)| result)
+                .context("Failed to create a graphics pipeline")
+                .inspect_err(|_| device.ash_device().destroy_pipeline_layout(layout, None))?
+                .remove(0)
         };
-        Pipeline { device: self.device,
+        Ok(Pipeline { device: self.device,
             pipeline,
             layout,
+            set_layouts,
             supports_graphics: true,
             supports_compute: false,
-        }
+        })
     }
 
-    pub fn build_compute(self) {
+    /// Builds a compute pipeline from a SPIR-V module with a `main` entry point. The pipeline layout is made of
+    /// `set_layouts`, in set order, and `push_constant_ranges`.
+    pub fn build_compute(self,
+                         spirv : &[u8],
+                         set_layouts : &[&DescriptorSetLayout],
+                         push_constant_ranges : &[vk::PushConstantRange]) -> Result<Pipeline,Error> {
+        let device = self.device.borrow();
+        let code = ash::util::read_spv(&mut Cursor::new(spirv)).map_err(|_| Error::InvalidSpirv)?;
+        let module_info = vk::ShaderModuleCreateInfo::builder().code(&code);
+        let module = unsafe {
+            device
+                .ash_device()
+                .create_shader_module(&module_info, None)
+                .context("Failed to create a compute shader module")?
+        };
+
+        let set_layouts : Vec<_> = set_layouts.iter().map(|layout| layout.layout_raw()).collect();
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(push_constant_ranges);
+        let layout = unsafe {
+            device
+                .ash_device()
+                .create_pipeline_layout(&layout_info, None)
+                .context("Failed to create a pipeline layout")
+                .inspect_err(|_| device.ash_device().destroy_shader_module(module, None))?
+        };
 
+        let entry_point = c"main";
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(module)
+            .name(entry_point)
+            .build();
+        let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage)
+            .layout(layout)
+            .build();
+        let pipeline = unsafe {
+            let pipeline = device
+                .ash_device()
+                .create_compute_pipelines(device.pipeline_cache(), &[pipeline_info], None)
+                .map_err(|(_, result)| result)
+                .context("Failed to create a compute pipeline");
+            // The module is only needed while the pipeline is created.
+            device.ash_device().destroy_shader_module(module, None);
+            pipeline
+                .inspect_err(|_| device.ash_device().destroy_pipeline_layout(layout, None))?
+                .remove(0)
+        };
+        drop(device);
+        Ok(Pipeline { device: self.device,
+            pipeline,
+            layout,
+            set_layouts: Vec::new(),
+            supports_graphics: false,
+            supports_compute: true,
+        })
     }
-}
\ No newline at end of file
+
+    /// Builds a compute pipeline from a SPIR-V module with a `main` entry point, creating the descriptor set layouts
+    /// and push constant range the shader declares. Sets can be allocated with `Pipeline::set_layouts`.
+    pub fn build_compute_reflected(self, spirv : &[u8]) -> Result<Pipeline,Error> {
+        let reflection = ShaderReflection::new(spirv)?;
+        reflection.entry_point("main", vk::ShaderStageFlags::COMPUTE)?;
+        let interface = PipelineInterface::new(&[&reflection])?;
+        let set_layouts = create_set_layouts(&self.device, &interface)?;
+        let mut pipeline = self.build_compute(spirv,
+            &set_layouts.iter().collect::<Vec<_>>(),
+            interface.push_constant_ranges())?;
+        pipeline.set_layouts = set_layouts;
+        Ok(pipeline)
+    }
+}
+
+/// Creates a layout for each set up to the highest one used by `interface`. Sets without any bindings get an empty
+/// layout, as the pipeline layout cannot skip them.
+fn create_set_layouts(device : &Rc<RefCell<Device>>,
+                      interface : &PipelineInterface) -> Result<Vec<DescriptorSetLayout>,Error> {
+    (0..interface.set_count())
+        .map(|set| interface
+            .descriptor_bindings()
+            .iter()
+            .filter(|binding| binding.set == set)
+            .fold(DescriptorSetLayoutBuilder::new(Rc::clone(device)), |builder, binding| {
+                builder.add_binding(binding.binding, binding.descriptor_type, binding.count, binding.stages)
+            })
+            .build())
+        .collect()
+}
+
+/// Returns true if the device can use `state` as dynamic state. Extension states which also need a device feature,
+/// such as those of `VK_EXT_extended_dynamic_state`, are never supported, as devices are created without them.
+fn is_dynamic_state_supported(device : &Device, state : vk::DynamicState) -> bool {
+    match state {
+        vk::DynamicState::VIEWPORT
+        | vk::DynamicState::SCISSOR
+        | vk::DynamicState::LINE_WIDTH
+        | vk::DynamicState::DEPTH_BIAS
+        | vk::DynamicState::BLEND_CONSTANTS
+        | vk::DynamicState::DEPTH_BOUNDS
+        | vk::DynamicState::STENCIL_COMPARE_MASK
+        | vk::DynamicState::STENCIL_WRITE_MASK
+        | vk::DynamicState::STENCIL_REFERENCE => true,
+        vk::DynamicState::DISCARD_RECTANGLE_EXT => device.is_extension_enabled(vk::ExtDiscardRectanglesFn::name()),
+        vk::DynamicState::SAMPLE_LOCATIONS_EXT => device.is_extension_enabled(vk::ExtSampleLocationsFn::name()),
+        vk::DynamicState::VIEWPORT_W_SCALING_NV => device.is_extension_enabled(vk::NvClipSpaceWScalingFn::name()),
+        _ => false,
+    }
+}
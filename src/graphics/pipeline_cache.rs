@@ -0,0 +1,108 @@
+use std::{fs, io, path::{Path, PathBuf}};
+use ash::vk;
+use super::error::{Error, VkResultExt};
+
+/// Size of `VkPipelineCacheHeaderVersionOne`, which precedes the driver specific data.
+const HEADER_SIZE : usize = 32;
+
+/// Caches compiled pipelines, so they are only compiled once rather than on every launch. The cache can be seeded
+/// from a file saved by a previous run, which is only used if it was saved by the same driver on the same device.
+///
+/// The cache is owned and destroyed by the `Device`, as it does not hold a reference to it.
+pub struct PipelineCache {
+    cache : vk::PipelineCache,
+    path : Option<PathBuf>,
+}
+
+impl PipelineCache {
+    /// Creates a cache which is saved to `path`, seeded with the data already there if it is compatible with the
+    /// device. Missing, corrupt or stale data is discarded, leaving the cache empty.
+    pub fn new(device : &ash::Device,
+               properties : &vk::PhysicalDeviceProperties,
+               path : Option<&Path>) -> Result<Self,Error> {
+        let data = match path.map(fs::read) {
+            Some(Ok(data)) if Self::is_compatible(&data, properties) => data,
+            Some(Ok(_)) => {
+                info!("Discarding pipeline cache {:?}, which was saved by a different driver or device", path.unwrap());
+                Vec::new()
+            },
+            Some(Err(error)) if error.kind() != io::ErrorKind::NotFound => {
+                warn!("Failed to read pipeline cache {:?} ({})", path.unwrap(), error);
+                Vec::new()
+            },
+            _ => Vec::new(),
+        };
+
+        let cache = match create_cache(device, &data) {
+            Ok(cache) => cache,
+            // The header matched, but the driver may still reject the data.
+            Err(_) if !data.is_empty() => {
+                warn!("The driver rejected pipeline cache {:?}, starting with an empty cache", path.unwrap());
+                create_cache(device, &[])?
+            },
+            Err(error) => return Err(error),
+        };
+        Ok(Self { cache, path: path.map(Path::to_path_buf) })
+    }
+
+    /// Returns true if `data` starts with a pipeline cache header written by the device's driver. Data which fails
+    /// this check is discarded, as drivers may crash on data meant for another device or driver version.
+    pub fn is_compatible(data : &[u8], properties : &vk::PhysicalDeviceProperties) -> bool {
+        if data.len() < HEADER_SIZE {
+            return false;
+        }
+        let read_u32 = |offset : usize| u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+        let header_size = read_u32(0) as usize;
+        header_size >= HEADER_SIZE
+            && header_size <= data.len()
+            && read_u32(4) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+            && read_u32(8) == properties.vendor_id
+            && read_u32(12) == properties.device_id
+            && data[16..32] == properties.pipeline_cache_uuid
+    }
+
+    /// Writes the cache to its file, if it has one. The data is written to a temporary file which then replaces the
+    /// previous file, so an interrupted save never leaves a truncated cache behind.
+    pub fn save(&self, device : &ash::Device) -> Result<(),Error> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let data = unsafe {
+            device
+                .get_pipeline_cache_data(self.cache)
+                .context("Failed to retrieve the pipeline cache data")?
+        };
+        let temporary_path = path.with_extension("tmp");
+        fs::write(&temporary_path, &data)?;
+        fs::rename(&temporary_path, path)?;
+        debug!("Saved {} bytes of pipeline cache to {:?}", data.len(), path);
+        Ok(())
+    }
+
+    /// Destroys the cache. It must not be used afterwards.
+    pub fn destroy(&mut self, device : &ash::Device) {
+        unsafe {
+            device.destroy_pipeline_cache(self.cache, None);
+        }
+        self.cache = vk::PipelineCache::null();
+    }
+
+    pub fn cache_raw(&self) -> vk::PipelineCache {
+        self.cache
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+}
+
+fn create_cache(device : &ash::Device, data : &[u8]) -> Result<vk::PipelineCache,Error> {
+    let cache_info = vk::PipelineCacheCreateInfo::builder()
+        .initial_data(data);
+    unsafe {
+        device
+            .create_pipeline_cache(&cache_info, None)
+            .context("Failed to create a pipeline cache")
+    }
+}
@@ -0,0 +1,21 @@
+use std::ffi::CStr;
+use ash::extensions::{ext::HeadlessSurface, khr::Surface};
+use ash::prelude::VkResult;
+use ash::vk;
+use ash::{Entry, Instance};
+
+/// Creates a surface which is not backed by any window. Presenting to it only returns the image to the swapchain,
+/// which allows the swapchain to be exercised on machines without a display.
+pub fn create_headless_surface(entry : &Entry, instance : &Instance) -> VkResult<vk::SurfaceKHR> {
+    let headless_create_info = vk::HeadlessSurfaceCreateInfoEXT::builder()
+        .build();
+    let headless_surface_loader = HeadlessSurface::new(entry, instance);
+    unsafe {
+        headless_surface_loader.create_headless_surface(&headless_create_info, None)
+    }
+}
+
+/// Returns the extensions needed for headless surfaces.
+pub fn get_headless_instance_extensions() -> Vec<&'static CStr> {
+    vec![Surface::name(), HeadlessSurface::name()]
+}
@@ -1,26 +1,86 @@
 use std::os::raw::c_void;
-use ash::extensions::{ext::DebugUtils, khr::Surface, khr::XlibSurface};
+use std::ffi::CStr;
+use ash::extensions::{khr::Surface, khr::WaylandSurface, khr::XcbSurface, khr::XlibSurface};
+use ash::prelude::VkResult;
 use ash::vk;
-use ash::{Entry, Instance};
 use winit::platform::unix::WindowExtUnix;
 use winit::window::Window;
+use super::super::Instance;
 
-pub fn create_surface(entry : &Entry, instance : &Instance, window : &Window)
-    -> vk::SurfaceKHR {
-    let xlib_display = window.xlib_display().unwrap();
-    let xlib_window = window.xlib_window().unwrap();
+/// Creates a surface for the backend winit is running on. Wayland windows use `VK_KHR_wayland_surface`, while X11
+/// windows prefer `VK_KHR_xcb_surface` and fall back to `VK_KHR_xlib_surface`, depending on which the instance enabled.
+/// Fails with `ERROR_EXTENSION_NOT_PRESENT` if the instance did not enable an extension for the window's backend.
+pub fn create_surface(instance : &Instance, window : &Window) -> VkResult<vk::SurfaceKHR> {
+    if let (Some(wayland_display), Some(wayland_surface)) = (window.wayland_display(), window.wayland_surface()) {
+        if !instance.is_extension_enabled(WaylandSurface::name()) {
+            return Err(vk::Result::ERROR_EXTENSION_NOT_PRESENT);
+        }
+        return create_wayland_surface(instance, wayland_display, wayland_surface);
+    }
+
+    let xlib_window = window.xlib_window().ok_or(vk::Result::ERROR_INITIALIZATION_FAILED)?;
+    if let Some(xcb_connection) = window.xcb_connection() {
+        if instance.is_extension_enabled(XcbSurface::name()) {
+            return create_xcb_surface(instance, xcb_connection, xlib_window as vk::xcb_window_t);
+        }
+    }
+    if !instance.is_extension_enabled(XlibSurface::name()) {
+        return Err(vk::Result::ERROR_EXTENSION_NOT_PRESENT);
+    }
+    // The display is missing if winit could not connect to the X server through Xlib.
+    let xlib_display = window.xlib_display().ok_or(vk::Result::ERROR_INITIALIZATION_FAILED)?;
+    create_xlib_surface(instance, xlib_display, xlib_window as vk::Window)
+}
+
+fn create_wayland_surface(instance : &Instance, display : *mut c_void, surface : *mut c_void)
+    -> VkResult<vk::SurfaceKHR> {
+    debug!("Creating a Wayland surface");
+    let wayland_create_info = vk::WaylandSurfaceCreateInfoKHR::builder()
+        .display(display as *mut vk::wl_display)
+        .surface(surface as *mut vk::wl_surface)
+        .build();
+
+    let wayland_surface_loader = WaylandSurface::new(instance.ash_entry(), instance.ash_instance());
+    unsafe {
+        wayland_surface_loader.create_wayland_surface(&wayland_create_info, None)
+    }
+}
+
+fn create_xcb_surface(instance : &Instance, connection : *mut c_void, window : vk::xcb_window_t)
+    -> VkResult<vk::SurfaceKHR> {
+    debug!("Creating an XCB surface");
+    let xcb_create_info = vk::XcbSurfaceCreateInfoKHR::builder()
+        .connection(connection as *mut vk::xcb_connection_t)
+        .window(window)
+        .build();
+
+    let xcb_surface_loader = XcbSurface::new(instance.ash_entry(), instance.ash_instance());
+    unsafe {
+        xcb_surface_loader.create_xcb_surface(&xcb_create_info, None)
+    }
+}
+
+fn create_xlib_surface(instance : &Instance, display : *mut c_void, window : vk::Window)
+    -> VkResult<vk::SurfaceKHR> {
+    debug!("Creating an Xlib surface");
     let xlib_create_info = vk::XlibSurfaceCreateInfoKHR::builder()
-        .dpy(xlib_display as *mut vk::Display)
-        .window(xlib_window as vk::Window)
+        .dpy(display as *mut vk::Display)
+        .window(window)
         .build();
 
-    let xlib_surface_loader = XlibSurface::new(entry, instance);
+    let xlib_surface_loader = XlibSurface::new(instance.ash_entry(), instance.ash_instance());
     unsafe {
         xlib_surface_loader.create_xlib_surface(&xlib_create_info, None)
-            .expect("Failed to create surface")
     }
 }
 
-pub fn get_required_instance_extensions() -> Vec<*const i8> {
-    vec![Surface::name().as_ptr(), XlibSurface::name().as_ptr(), DebugUtils::name().as_ptr()] as Vec<*const i8>
+/// Returns the extensions an instance needs to present to any window.
+pub fn get_required_instance_extensions() -> Vec<&'static CStr> {
+    vec![Surface::name()]
+}
+
+/// Returns the surface extensions for every Linux windowing system, as the backend winit picks is only known once a
+/// window has been created. Only those the loader supports are enabled.
+pub fn get_optional_instance_extensions() -> Vec<&'static CStr> {
+    vec![WaylandSurface::name(), XcbSurface::name(), XlibSurface::name()]
 }
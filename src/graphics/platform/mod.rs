@@ -3,8 +3,11 @@
 mod win32;
 #[cfg(target_os = "linux")]
 mod linux;
+/// Surfaces which are not backed by a window, provided by `VK_EXT_headless_surface` on every platform.
+mod headless;
 
 #[cfg(target_os = "windows")]
-pub use self::win32::{create_surface, get_required_instance_extensions};
+pub use self::win32::{create_surface, get_optional_instance_extensions, get_required_instance_extensions};
 #[cfg(target_os = "linux")]
-pub use self::linux::{create_surface, get_required_instance_extensions};
+pub use self::linux::{create_surface, get_optional_instance_extensions, get_required_instance_extensions};
+pub use self::headless::{create_headless_surface, get_headless_instance_extensions};
@@ -1,19 +1,17 @@
 use std::{cell::RefCell, rc::Rc};
 use ash::vk;
-use super::{CmdBuffer, Device};
+use super::{CmdBuffer, Device, error::{Error, VkResultExt}};
 
 pub struct Queue {
     device : Rc<RefCell<Device>>,
     queue : vk::Queue,
     family_index : u32,
-    submit_semaphore : vk::Semaphore,
 }
 
 impl Drop for Queue {
     fn drop(&mut self) {
         unsafe {
             self.device.borrow().ash_device().queue_wait_idle(self.queue).unwrap();
-            self.device.borrow().ash_device().destroy_semaphore(self.submit_semaphore, None);
         }
         info!("Dropped Queue")
     }
@@ -27,56 +25,87 @@ impl Queue {
                 .ash_device()
                 .get_device_queue(family_index, 0)
         };
+        Self { device, queue, family_index }
+    }
 
-        let semaphore_info = vk::SemaphoreCreateInfo::builder();
-        let submit_semaphore = unsafe {
-            device
+    /// Submits the command buffer to the queue for execution. If the queue needs to wait for a swapchain image to be
+    /// acquired before writing color attachments, pass its `wait_semaphore`. Use `signal_semaphore` if other queue
+    /// operations, such as presentation, need to wait for this submission, and `signal_fence` if the CPU does.
+    pub fn submit(&self,
+                  cmd_buffer : &CmdBuffer,
+                  wait_semaphore : Option<vk::Semaphore>,
+                  signal_semaphore : Option<vk::Semaphore>,
+                  signal_fence : Option<vk::Fence>) -> Result<(),Error> {
+        let wait_semaphores : Vec<_> = wait_semaphore
+            .map(|semaphore| (semaphore, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT))
+            .into_iter()
+            .collect();
+        let signal_semaphores : Vec<_> = signal_semaphore.into_iter().collect();
+        self.submit_with(cmd_buffer, &wait_semaphores, &signal_semaphores, signal_fence)
+    }
+
+    /// Submits the command buffer without waiting on or signalling any semaphores. This is used for work which is
+    /// never presented, such as offscreen rendering. Use `signal_fence` if the CPU needs to wait for completion.
+    pub fn submit_offscreen(&self, cmd_buffer : &CmdBuffer, signal_fence : Option<vk::Fence>) -> Result<(),Error> {
+        self.submit_with(cmd_buffer, &[], &[], signal_fence)
+    }
+
+    /// Submits the command buffer, waiting on each semaphore in `wait_semaphores` at its paired stage and signalling
+    /// `signal_semaphores` once execution completes. Use `signal_fence` if the CPU needs to wait for completion.
+    pub fn submit_with(&self,
+                       cmd_buffer : &CmdBuffer,
+                       wait_semaphores : &[(vk::Semaphore, vk::PipelineStageFlags)],
+                       signal_semaphores : &[vk::Semaphore],
+                       signal_fence : Option<vk::Fence>) -> Result<(),Error> {
+        let cmd_buffers = [cmd_buffer.cmd_buffer_raw()];
+        let (semaphores, stages) : (Vec<_>, Vec<_>) = wait_semaphores.iter().copied().unzip();
+        let submit_info = vk::SubmitInfo::builder()
+            .command_buffers(&cmd_buffers)
+            .wait_semaphores(&semaphores)
+            .wait_dst_stage_mask(&stages)
+            .signal_semaphores(signal_semaphores)
+            .build();
+        unsafe {
+            self.device
                 .borrow()
                 .ash_device()
-                .create_semaphore(&semaphore_info, None)
-                .expect("Failed to create semaphore")
-        };
-        Self { device, queue, family_index, submit_semaphore }
+                .queue_submit(self.queue, &[submit_info], signal_fence.unwrap_or_default())
+                .context("Failed to submit a command buffer")
+        }
     }
 
-    /// Submits the command buffer to the queue for execution. The `submit_semaphore` will be signaled when this operation is complete.
-    /// If the queue needs to wait for some work to be done, use `signal_semaphore`. If you need the CPU to wait for the queue to finish,
-    /// i.e acquiring images on the swapchain, use `signal_fence`.
-    pub fn submit(&self, cmd_buffer : &CmdBuffer, wait_semaphore : Option<vk::Semaphore>, signal_fence : Option<vk::Fence>) {
-        let submit_info = match wait_semaphore {
-            Some(semaphore) => vk::SubmitInfo::builder()
-                .command_buffers(&[cmd_buffer.cmd_buffer_raw()])
-                // Signal this semaphore when submission is completed.
-                .signal_semaphores(&[self.submit_semaphore])
-                // Wait on the image to be acquired before submitting the command buffer.
-                .wait_semaphores(&[semaphore])
-                .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
-                .build(),
-            None => vk::SubmitInfo::builder()
-                .command_buffers(&[cmd_buffer.cmd_buffer_raw()])
-                // Signal this semaphore when submission is completed.
-                .signal_semaphores(&[self.submit_semaphore])
-                .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
-                .build()
-        };
+    /// Blocks until all work submitted to the queue has completed.
+    pub fn wait_idle(&self) -> Result<(),Error> {
         unsafe {
-            match signal_fence {
-                Some(fence) => self.device
-                    .borrow()
-                    .ash_device()
-                    // Submits to the queue with the specified semaphores and a acquire fence to signal.
-                    .queue_submit(self.queue, &[submit_info], fence)
-                    .expect("Failed to submit command buffer."),
-                None => self.device
-                    .borrow()
-                    .ash_device()
-                    // Submits to the queue with the specified semaphores and a acquire fence to signal.
-                    .queue_submit(self.queue, &[submit_info], vk::Fence::null())
-                    .expect("Failed to submit command buffer."),
-            }
+            self.device
+                .borrow()
+                .ash_device()
+                .queue_wait_idle(self.queue)
+                .context("Failed to wait for a queue")
         }
     }
 
+    /// Names the queue in validation messages and debuggers. Does nothing unless debug utils are enabled.
+    pub fn set_debug_name(&self, name : &str) {
+        self.device.borrow().set_object_name(self.queue, name);
+    }
+
+    /// Opens a labelled region of submissions, which must be closed by `end_label`. Does nothing unless debug utils
+    /// are enabled.
+    pub fn begin_label(&self, name : &str, color : [f32; 4]) {
+        self.device.borrow().begin_queue_label(self.queue, name, color);
+    }
+
+    /// Closes the most recently opened label region.
+    pub fn end_label(&self) {
+        self.device.borrow().end_queue_label(self.queue);
+    }
+
+    /// Inserts a single label between submissions.
+    pub fn insert_label(&self, name : &str, color : [f32; 4]) {
+        self.device.borrow().insert_queue_label(self.queue, name, color);
+    }
+
     pub fn queue_raw(&self) -> vk::Queue {
         self.queue
     }
@@ -84,7 +113,4 @@ impl Queue {
     pub fn family_index(&self) -> u32 {
         self.family_index
     }
-
-    /// Returns a handle to a semaphore which is signalled when submission to the queue is completed.
-    pub fn submit_semaphore_raw(&self) -> vk::Semaphore { self.submit_semaphore }
 }
\ No newline at end of file
@@ -0,0 +1,684 @@
+use std::{collections::{HashMap, HashSet}, error, fmt, io::Cursor};
+use ash::vk;
+
+const MAGIC_NUMBER : u32 = 0x0723_0203;
+const HEADER_WORDS : usize = 5;
+
+// Opcodes of the instructions which describe a module's interface. Everything else is skipped.
+const OP_NAME : u32 = 5;
+const OP_ENTRY_POINT : u32 = 15;
+const OP_TYPE_BOOL : u32 = 20;
+const OP_TYPE_INT : u32 = 21;
+const OP_TYPE_FLOAT : u32 = 22;
+const OP_TYPE_VECTOR : u32 = 23;
+const OP_TYPE_MATRIX : u32 = 24;
+const OP_TYPE_IMAGE : u32 = 25;
+const OP_TYPE_SAMPLER : u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE : u32 = 27;
+const OP_TYPE_ARRAY : u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY : u32 = 29;
+const OP_TYPE_STRUCT : u32 = 30;
+const OP_TYPE_POINTER : u32 = 32;
+const OP_CONSTANT : u32 = 43;
+const OP_SPEC_CONSTANT : u32 = 50;
+const OP_SPEC_CONSTANT_OP : u32 = 52;
+const OP_VARIABLE : u32 = 59;
+const OP_DECORATE : u32 = 71;
+const OP_MEMBER_DECORATE : u32 = 72;
+const OP_TYPE_ACCELERATION_STRUCTURE : u32 = 5341;
+
+const DECORATION_BUFFER_BLOCK : u32 = 3;
+const DECORATION_ARRAY_STRIDE : u32 = 6;
+const DECORATION_MATRIX_STRIDE : u32 = 7;
+const DECORATION_LOCATION : u32 = 30;
+const DECORATION_BINDING : u32 = 33;
+const DECORATION_DESCRIPTOR_SET : u32 = 34;
+const DECORATION_OFFSET : u32 = 35;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT : u32 = 0;
+const STORAGE_CLASS_INPUT : u32 = 1;
+const STORAGE_CLASS_UNIFORM : u32 = 2;
+const STORAGE_CLASS_OUTPUT : u32 = 3;
+const STORAGE_CLASS_PUSH_CONSTANT : u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER : u32 = 12;
+
+/// The most locations a single interface variable may occupy. This is far above the limits of any device, but is
+/// checked before the locations are listed, so a huge array cannot exhaust memory. Per-vertex arrays of tessellation
+/// and geometry stages are counted as if each element had its own locations, so the limit is not tighter.
+const MAX_LOCATIONS : u32 = 1024;
+
+const DIM_BUFFER : u32 = 5;
+const DIM_SUBPASS_DATA : u32 = 6;
+
+/// Formats with one to four components for each scalar type a shader can pass between stages.
+const FORMATS : [(ScalarKind, u32, [vk::Format; 4]); 6] = [
+    (ScalarKind::Float, 32, [vk::Format::R32_SFLOAT, vk::Format::R32G32_SFLOAT, vk::Format::R32G32B32_SFLOAT,
+        vk::Format::R32G32B32A32_SFLOAT]),
+    (ScalarKind::Sint, 32, [vk::Format::R32_SINT, vk::Format::R32G32_SINT, vk::Format::R32G32B32_SINT,
+        vk::Format::R32G32B32A32_SINT]),
+    (ScalarKind::Uint, 32, [vk::Format::R32_UINT, vk::Format::R32G32_UINT, vk::Format::R32G32B32_UINT,
+        vk::Format::R32G32B32A32_UINT]),
+    (ScalarKind::Float, 64, [vk::Format::R64_SFLOAT, vk::Format::R64G64_SFLOAT, vk::Format::R64G64B64_SFLOAT,
+        vk::Format::R64G64B64A64_SFLOAT]),
+    (ScalarKind::Sint, 64, [vk::Format::R64_SINT, vk::Format::R64G64_SINT, vk::Format::R64G64B64_SINT,
+        vk::Format::R64G64B64A64_SINT]),
+    (ScalarKind::Uint, 64, [vk::Format::R64_UINT, vk::Format::R64G64_UINT, vk::Format::R64G64B64_UINT,
+        vk::Format::R64G64B64A64_UINT]),
+];
+
+/// Describes why a shader could not be reflected, or does not fit the pipeline it is used in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReflectionError {
+    /// The module is not valid SPIR-V. Describes what could not be parsed.
+    Malformed(&'static str),
+    /// An entry point uses an execution model other than the graphics and compute stages.
+    UnsupportedExecutionModel(u32),
+    MissingEntryPoint { name : String, stage : vk::ShaderStageFlags },
+    /// Two stages declare the same binding with a different descriptor type or count.
+    ConflictingBinding { set : u32, binding : u32 },
+    /// The vertex shader reads a location which the vertex layout does not provide.
+    MissingVertexAttribute { location : u32, name : String },
+    /// The vertex shader reads a location with a different numeric type than the vertex layout provides.
+    VertexFormatMismatch { location : u32, name : String, shader : vk::Format, vertex : vk::Format },
+    /// A stage reads a location which the previous stage does not write with the same numeric type and at least as
+    /// many components.
+    UnmatchedStageInput { location : u32, name : String, stage : vk::ShaderStageFlags },
+    /// An interface variable occupies more than `MAX_LOCATIONS` locations.
+    TooManyLocations { location : u32, name : String },
+    /// An array is sized by a specialization constant expression, whose value is only known once the pipeline is
+    /// created.
+    UnsupportedArrayLength,
+}
+
+impl fmt::Display for ReflectionError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReflectionError::Malformed(reason) => write!(f, "The shader is not valid SPIR-V: {}", reason),
+            ReflectionError::UnsupportedExecutionModel(model) =>
+                write!(f, "The shader has an entry point with unsupported execution model {}", model),
+            ReflectionError::MissingEntryPoint { name, stage } =>
+                write!(f, "The shader has no {:?} entry point named \"{}\"", stage, name),
+            ReflectionError::ConflictingBinding { set, binding } =>
+                write!(f, "The shaders declare set {} binding {} with different types or counts", set, binding),
+            ReflectionError::MissingVertexAttribute { location, name } =>
+                write!(f, "The vertex shader reads \"{}\" at location {}, which is not in the vertex layout", name, location),
+            ReflectionError::VertexFormatMismatch { location, name, shader, vertex } =>
+                write!(f, "The vertex shader reads \"{}\" at location {} as {:?}, but the vertex layout provides {:?}",
+                    name, location, shader, vertex),
+            ReflectionError::UnmatchedStageInput { location, name, stage } =>
+                write!(f, "The {:?} shader reads \"{}\" at location {}, which the previous stage does not write",
+                    stage, name, location),
+            ReflectionError::TooManyLocations { location, name } =>
+                write!(f, "The shader declares \"{}\" at location {} with more than {} locations", name, location, MAX_LOCATIONS),
+            ReflectionError::UnsupportedArrayLength =>
+                write!(f, "The shader sizes an array with a specialization constant expression"),
+        }
+    }
+}
+
+impl error::Error for ReflectionError {}
+
+/// A resource which is bound to the shader through a descriptor set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DescriptorBinding {
+    pub set : u32,
+    pub binding : u32,
+    pub descriptor_type : vk::DescriptorType,
+    /// The number of descriptors in the binding, which is 0 for a runtime sized array.
+    pub count : u32,
+    pub stages : vk::ShaderStageFlags,
+    pub name : String,
+}
+
+/// A value passed into or out of a stage at a location. Matrices and arrays are split into one variable per
+/// location. Built-ins such as `gl_Position` have no location, so they are not included.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceVariable {
+    pub location : u32,
+    /// The format of the value, or `UNDEFINED` for types which have no matching format, such as structs.
+    pub format : vk::Format,
+    pub name : String,
+}
+
+/// A function which can be used as a pipeline stage, along with its inputs and outputs sorted by location.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryPoint {
+    pub name : String,
+    pub stage : vk::ShaderStageFlags,
+    pub inputs : Vec<InterfaceVariable>,
+    pub outputs : Vec<InterfaceVariable>,
+}
+
+/// Describes the entry points of a SPIR-V module, and the descriptors and push constants it uses.
+///
+/// Resources are attributed to every entry point of the module, since the call graph is not followed. This is exact
+/// for modules with a single entry point, as produced by most compilers. Arrays sized by a specialization constant use
+/// its default value.
+#[derive(Clone, Debug)]
+pub struct ShaderReflection {
+    entry_points : Vec<EntryPoint>,
+    descriptor_bindings : Vec<DescriptorBinding>,
+    push_constant_range : Option<vk::PushConstantRange>,
+}
+
+impl ShaderReflection {
+    /// Parses the module, which must be the same SPIR-V passed to `vkCreateShaderModule`.
+    pub fn new(spirv : &[u8]) -> Result<Self,ReflectionError> {
+        let words = ash::util::read_spv(&mut Cursor::new(spirv))
+            .map_err(|_| ReflectionError::Malformed("the code is not a whole number of words"))?;
+        Module::parse(&words)?.reflect()
+    }
+
+    pub fn entry_points(&self) -> &[EntryPoint] {
+        &self.entry_points
+    }
+
+    /// Finds the entry point with the given name and stage.
+    pub fn entry_point(&self, name : &str, stage : vk::ShaderStageFlags) -> Result<&EntryPoint,ReflectionError> {
+        self.entry_points
+            .iter()
+            .find(|entry_point| entry_point.name == name && entry_point.stage == stage)
+            .ok_or_else(|| ReflectionError::MissingEntryPoint { name: name.to_string(), stage })
+    }
+
+    /// Returns the bindings sorted by set and binding.
+    pub fn descriptor_bindings(&self) -> &[DescriptorBinding] {
+        &self.descriptor_bindings
+    }
+
+    /// Returns the range covering the push constant block, if the module has one.
+    pub fn push_constant_range(&self) -> Option<vk::PushConstantRange> {
+        self.push_constant_range
+    }
+}
+
+/// The resources used by all stages of a pipeline, from which its layout is created.
+#[derive(Clone, Debug, Default)]
+pub struct PipelineInterface {
+    descriptor_bindings : Vec<DescriptorBinding>,
+    push_constant_ranges : Vec<vk::PushConstantRange>,
+}
+
+impl PipelineInterface {
+    /// Merges the resources of each stage. Bindings used by several stages are visible to all of them, and a single
+    /// push constant range covers the blocks of every stage.
+    pub fn new(stages : &[&ShaderReflection]) -> Result<Self,ReflectionError> {
+        let mut descriptor_bindings : Vec<DescriptorBinding> = Vec::new();
+        for binding in stages.iter().flat_map(|stage| stage.descriptor_bindings()) {
+            match descriptor_bindings.iter_mut().find(|existing| existing.set == binding.set && existing.binding == binding.binding) {
+                Some(existing) if existing.descriptor_type != binding.descriptor_type || existing.count != binding.count =>
+                    return Err(ReflectionError::ConflictingBinding { set: binding.set, binding: binding.binding }),
+                Some(existing) => existing.stages |= binding.stages,
+                None => descriptor_bindings.push(binding.clone()),
+            }
+        }
+        descriptor_bindings.sort_by_key(|binding| (binding.set, binding.binding));
+
+        let push_constant_ranges = stages
+            .iter()
+            .filter_map(|stage| stage.push_constant_range())
+            .reduce(|merged, range| vk::PushConstantRange {
+                stage_flags: merged.stage_flags | range.stage_flags,
+                offset: 0,
+                size: merged.size.max(range.size),
+            })
+            .into_iter()
+            .collect();
+        Ok(Self { descriptor_bindings, push_constant_ranges })
+    }
+
+    /// Returns the bindings sorted by set and binding.
+    pub fn descriptor_bindings(&self) -> &[DescriptorBinding] {
+        &self.descriptor_bindings
+    }
+
+    /// Returns the number of set layouts the pipeline layout needs. Sets below the highest one which have no
+    /// bindings still need an empty layout.
+    pub fn set_count(&self) -> u32 {
+        self.descriptor_bindings.last().map_or(0, |binding| binding.set + 1)
+    }
+
+    pub fn push_constant_ranges(&self) -> &[vk::PushConstantRange] {
+        &self.push_constant_ranges
+    }
+}
+
+/// Returns the attributes of `layout` which are read by the vertex shader `inputs`. Fails if the shader reads a
+/// location which the layout does not provide, or reads it with a different numeric type. The component counts may
+/// differ, as missing components are filled in and extra ones are ignored.
+pub fn vertex_attributes(inputs : &[InterfaceVariable],
+                         layout : &[vk::VertexInputAttributeDescription]) -> Result<Vec<vk::VertexInputAttributeDescription>,ReflectionError> {
+    inputs
+        .iter()
+        .map(|input| {
+            let attribute = layout
+                .iter()
+                .find(|attribute| attribute.location == input.location)
+                .ok_or_else(|| ReflectionError::MissingVertexAttribute {
+                    location: input.location,
+                    name: input.name.clone(),
+                })?;
+            if !same_numeric_type(attribute.format, input.format) {
+                return Err(ReflectionError::VertexFormatMismatch {
+                    location: input.location,
+                    name: input.name.clone(),
+                    shader: input.format,
+                    vertex: attribute.format,
+                });
+            }
+            Ok(*attribute)
+        })
+        .collect()
+}
+
+/// Checks that every input of `next` is written by `outputs` of the previous stage at the same location, with the
+/// same numeric type and at least as many components as are read.
+pub fn check_stage_interface(outputs : &[InterfaceVariable], next : &EntryPoint) -> Result<(),ReflectionError> {
+    let writes = |output : &InterfaceVariable, input : &InterfaceVariable| {
+        match (numeric_type(output.format), numeric_type(input.format)) {
+            (Some((output_type, output_count)), Some((input_type, input_count))) =>
+                output_type == input_type && output_count >= input_count,
+            _ => output.format == input.format,
+        }
+    };
+    for input in &next.inputs {
+        if !outputs.iter().any(|output| output.location == input.location && writes(output, input)) {
+            return Err(ReflectionError::UnmatchedStageInput {
+                location: input.location,
+                name: input.name.clone(),
+                stage: next.stage,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ScalarKind {
+    Bool,
+    Float,
+    Sint,
+    Uint,
+}
+
+/// The subset of SPIR-V types which affect descriptors, push constant sizes and interface formats.
+enum Type {
+    Scalar { kind : ScalarKind, width : u32 },
+    Vector { component : u32, count : u32 },
+    Matrix { column : u32, count : u32 },
+    Image { dim : u32, sampled : u32 },
+    Sampler,
+    SampledImage,
+    AccelerationStructure,
+    Array { element : u32, length : u32 },
+    RuntimeArray { element : u32 },
+    Struct { members : Vec<u32> },
+    Pointer { pointee : u32 },
+}
+
+struct RawEntryPoint {
+    model : u32,
+    name : String,
+    interface : Vec<u32>,
+}
+
+struct Variable {
+    id : u32,
+    pointer_type : u32,
+    storage_class : u32,
+}
+
+/// The instructions of a module which are needed for reflection, indexed by result id.
+#[derive(Default)]
+struct Module {
+    names : HashMap<u32, String>,
+    decorations : HashMap<(u32, u32), u32>,
+    member_decorations : HashMap<(u32, u32, u32), u32>,
+    types : HashMap<u32, Type>,
+    constants : HashMap<u32, u32>,
+    spec_constant_ops : HashSet<u32>,
+    entry_points : Vec<RawEntryPoint>,
+    variables : Vec<Variable>,
+}
+
+impl Module {
+    fn parse(words : &[u32]) -> Result<Self,ReflectionError> {
+        if words.len() < HEADER_WORDS || words[0] != MAGIC_NUMBER {
+            return Err(ReflectionError::Malformed("the header is missing"));
+        }
+        let mut module = Module::default();
+        let mut position = HEADER_WORDS;
+        while position < words.len() {
+            let opcode = words[position] & 0xffff;
+            let word_count = (words[position] >> 16) as usize;
+            if word_count == 0 || position + word_count > words.len() {
+                return Err(ReflectionError::Malformed("an instruction overruns the module"));
+            }
+            let operands = &words[position + 1..position + word_count];
+            module.parse_instruction(opcode, operands)?;
+            position += word_count;
+        }
+        Ok(module)
+    }
+
+    fn parse_instruction(&mut self, opcode : u32, operands : &[u32]) -> Result<(),ReflectionError> {
+        let operand = |index : usize| operands
+            .get(index)
+            .copied()
+            .ok_or(ReflectionError::Malformed("an instruction is missing operands"));
+        match opcode {
+            OP_NAME => {
+                let target = operand(0)?;
+                let (name, _) = parse_string(&operands[1..])?;
+                self.names.insert(target, name);
+            },
+            OP_ENTRY_POINT => {
+                let model = operand(0)?;
+                let (name, length) = parse_string(operands.get(2..).unwrap_or_default())?;
+                let interface = operands.get(2 + length..).unwrap_or_default().to_vec();
+                self.entry_points.push(RawEntryPoint { model, name, interface });
+            },
+            OP_TYPE_BOOL => { self.types.insert(operand(0)?, Type::Scalar { kind: ScalarKind::Bool, width: 32 }); },
+            OP_TYPE_INT => {
+                let kind = if operand(2)? == 1 { ScalarKind::Sint } else { ScalarKind::Uint };
+                self.types.insert(operand(0)?, Type::Scalar { kind, width: operand(1)? });
+            },
+            OP_TYPE_FLOAT => { self.types.insert(operand(0)?, Type::Scalar { kind: ScalarKind::Float, width: operand(1)? }); },
+            OP_TYPE_VECTOR => { self.types.insert(operand(0)?, Type::Vector { component: operand(1)?, count: operand(2)? }); },
+            OP_TYPE_MATRIX => { self.types.insert(operand(0)?, Type::Matrix { column: operand(1)?, count: operand(2)? }); },
+            OP_TYPE_IMAGE => { self.types.insert(operand(0)?, Type::Image { dim: operand(2)?, sampled: operand(6)? }); },
+            OP_TYPE_SAMPLER => { self.types.insert(operand(0)?, Type::Sampler); },
+            OP_TYPE_SAMPLED_IMAGE => { self.types.insert(operand(0)?, Type::SampledImage); },
+            OP_TYPE_ACCELERATION_STRUCTURE => { self.types.insert(operand(0)?, Type::AccelerationStructure); },
+            OP_TYPE_ARRAY => {
+                let length_id = operand(2)?;
+                let length = match self.constants.get(&length_id) {
+                    Some(length) => *length,
+                    None if self.spec_constant_ops.contains(&length_id) => return Err(ReflectionError::UnsupportedArrayLength),
+                    None => return Err(ReflectionError::Malformed("an array length is not a constant")),
+                };
+                self.types.insert(operand(0)?, Type::Array { element: operand(1)?, length });
+            },
+            OP_TYPE_RUNTIME_ARRAY => { self.types.insert(operand(0)?, Type::RuntimeArray { element: operand(1)? }); },
+            OP_TYPE_STRUCT => {
+                let id = operand(0)?;
+                self.types.insert(id, Type::Struct { members: operands[1..].to_vec() });
+            },
+            OP_TYPE_POINTER => { self.types.insert(operand(0)?, Type::Pointer { pointee: operand(2)? }); },
+            // Only the low word is kept, which is enough for array lengths. Specialization constants keep their default.
+            OP_CONSTANT | OP_SPEC_CONSTANT => { self.constants.insert(operand(1)?, operand(2)?); },
+            OP_SPEC_CONSTANT_OP => { self.spec_constant_ops.insert(operand(1)?); },
+            OP_VARIABLE => self.variables.push(Variable {
+                pointer_type: operand(0)?,
+                id: operand(1)?,
+                storage_class: operand(2)?,
+            }),
+            OP_DECORATE => { self.decorations.insert((operand(0)?, operand(1)?), operands.get(2).copied().unwrap_or(0)); },
+            OP_MEMBER_DECORATE => {
+                self.member_decorations.insert((operand(0)?, operand(1)?, operand(2)?), operands.get(3).copied().unwrap_or(0));
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn reflect(&self) -> Result<ShaderReflection,ReflectionError> {
+        let mut entry_points = Vec::new();
+        for raw in &self.entry_points {
+            let stage = execution_model_stage(raw.model)?;
+            let mut inputs = Vec::new();
+            let mut outputs = Vec::new();
+            for variable in self.variables.iter().filter(|variable| raw.interface.contains(&variable.id)) {
+                let interface = match variable.storage_class {
+                    STORAGE_CLASS_INPUT => &mut inputs,
+                    STORAGE_CLASS_OUTPUT => &mut outputs,
+                    _ => continue,
+                };
+                if let Some(&location) = self.decorations.get(&(variable.id, DECORATION_LOCATION)) {
+                    let name = self.name(variable.id);
+                    let pointee = self.pointee(variable.pointer_type)?;
+                    let count = self.location_count(pointee, &mut Vec::new())?;
+                    if count.is_none_or(|count| count > MAX_LOCATIONS || location.checked_add(count).is_none()) {
+                        return Err(ReflectionError::TooManyLocations { location, name });
+                    }
+                    let formats = self.location_formats(pointee, &mut Vec::new())?;
+                    for (offset, format) in formats.into_iter().enumerate() {
+                        interface.push(InterfaceVariable { location: location + offset as u32, format, name: name.clone() });
+                    }
+                }
+            }
+            inputs.sort_by_key(|input| input.location);
+            outputs.sort_by_key(|output| output.location);
+            entry_points.push(EntryPoint { name: raw.name.clone(), stage, inputs, outputs });
+        }
+        let stages = entry_points
+            .iter()
+            .fold(vk::ShaderStageFlags::empty(), |stages, entry_point| stages | entry_point.stage);
+
+        let mut descriptor_bindings = Vec::new();
+        let mut push_constant_range = None;
+        for variable in &self.variables {
+            let pointee = self.pointee(variable.pointer_type)?;
+            if variable.storage_class == STORAGE_CLASS_PUSH_CONSTANT {
+                push_constant_range = Some(vk::PushConstantRange {
+                    stage_flags: stages,
+                    offset: 0,
+                    size: self.size(pointee, None, &mut Vec::new())?,
+                });
+                continue;
+            }
+            let (descriptor_type, count) = match self.descriptor_type(variable.storage_class, pointee, &mut Vec::new())? {
+                Some(descriptor) => descriptor,
+                None => continue,
+            };
+            let decoration = |decoration| self.decorations
+                .get(&(variable.id, decoration))
+                .copied()
+                .ok_or(ReflectionError::Malformed("a resource has no descriptor set or binding"));
+            let name = match self.name(variable.id) {
+                name if name.is_empty() => self.name(self.element_type(pointee, &mut Vec::new())?),
+                name => name,
+            };
+            descriptor_bindings.push(DescriptorBinding {
+                set: decoration(DECORATION_DESCRIPTOR_SET)?,
+                binding: decoration(DECORATION_BINDING)?,
+                descriptor_type,
+                count,
+                stages,
+                name,
+            });
+        }
+        descriptor_bindings.sort_by_key(|binding| (binding.set, binding.binding));
+        Ok(ShaderReflection { entry_points, descriptor_bindings, push_constant_range })
+    }
+
+    fn name(&self, id : u32) -> String {
+        self.names.get(&id).cloned().unwrap_or_default()
+    }
+
+    fn get_type(&self, id : u32) -> Result<&Type,ReflectionError> {
+        self.types.get(&id).ok_or(ReflectionError::Malformed("a type is used before it is declared"))
+    }
+
+    fn pointee(&self, pointer_type : u32) -> Result<u32,ReflectionError> {
+        match self.get_type(pointer_type)? {
+            Type::Pointer { pointee } => Ok(*pointee),
+            _ => Err(ReflectionError::Malformed("a variable does not have a pointer type")),
+        }
+    }
+
+    /// Runs `visit` on a type nested in the types of `path`, which holds the ids currently being visited. Fails if
+    /// the type contains itself, which would otherwise recurse forever.
+    fn visit<T>(&self,
+                id : u32,
+                path : &mut Vec<u32>,
+                visit : impl FnOnce(&mut Vec<u32>) -> Result<T,ReflectionError>) -> Result<T,ReflectionError> {
+        if path.contains(&id) {
+            return Err(ReflectionError::Malformed("a type contains itself"));
+        }
+        path.push(id);
+        let result = visit(path);
+        path.pop();
+        result
+    }
+
+    /// Strips any arrays from the type of a resource, returning the type of a single descriptor.
+    fn element_type(&self, id : u32, path : &mut Vec<u32>) -> Result<u32,ReflectionError> {
+        self.visit(id, path, |path| match self.types.get(&id) {
+            Some(Type::Array { element, .. }) | Some(Type::RuntimeArray { element }) => self.element_type(*element, path),
+            _ => Ok(id),
+        })
+    }
+
+    /// Returns the descriptor type and count of a variable, or `None` if it is not bound through a descriptor set.
+    fn descriptor_type(&self,
+                       storage_class : u32,
+                       id : u32,
+                       path : &mut Vec<u32>) -> Result<Option<(vk::DescriptorType, u32)>,ReflectionError> {
+        self.visit(id, path, |path| {
+            let count = match self.get_type(id)? {
+                Type::Array { element, length } => return self
+                    .descriptor_type(storage_class, *element, path)?
+                    .map(|(descriptor_type, count)| count
+                        .checked_mul(*length)
+                        .map(|count| (descriptor_type, count))
+                        .ok_or(ReflectionError::Malformed("an array has too many descriptors")))
+                    .transpose(),
+                Type::RuntimeArray { element } => return Ok(self
+                    .descriptor_type(storage_class, *element, path)?
+                    .map(|(descriptor_type, _)| (descriptor_type, 0))),
+                _ => 1,
+            };
+            let descriptor_type = match (storage_class, self.get_type(id)?) {
+                (STORAGE_CLASS_UNIFORM, _) if self.decorations.contains_key(&(id, DECORATION_BUFFER_BLOCK)) =>
+                    vk::DescriptorType::STORAGE_BUFFER,
+                (STORAGE_CLASS_UNIFORM, _) => vk::DescriptorType::UNIFORM_BUFFER,
+                (STORAGE_CLASS_STORAGE_BUFFER, _) => vk::DescriptorType::STORAGE_BUFFER,
+                (STORAGE_CLASS_UNIFORM_CONSTANT, Type::Sampler) => vk::DescriptorType::SAMPLER,
+                (STORAGE_CLASS_UNIFORM_CONSTANT, Type::SampledImage) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                (STORAGE_CLASS_UNIFORM_CONSTANT, Type::AccelerationStructure) => vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                (STORAGE_CLASS_UNIFORM_CONSTANT, Type::Image { dim, sampled }) => match (*dim, *sampled) {
+                    (DIM_SUBPASS_DATA, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                    (DIM_BUFFER, 2) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                    (DIM_BUFFER, _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                    (_, 2) => vk::DescriptorType::STORAGE_IMAGE,
+                    _ => vk::DescriptorType::SAMPLED_IMAGE,
+                },
+                _ => return Ok(None),
+            };
+            Ok(Some((descriptor_type, count)))
+        })
+    }
+
+    /// Returns the number of locations occupied by a value of the given type, or `None` if it does not fit in a `u32`.
+    fn location_count(&self, id : u32, path : &mut Vec<u32>) -> Result<Option<u32>,ReflectionError> {
+        self.visit(id, path, |path| Ok(match self.get_type(id)? {
+            Type::Matrix { column, count } => self.location_count(*column, path)?.and_then(|column| column.checked_mul(*count)),
+            Type::Array { element, length } => self.location_count(*element, path)?.and_then(|element| element.checked_mul(*length)),
+            _ => Some(1),
+        }))
+    }
+
+    /// Returns the format of each location occupied by a value of the given type, which must have been checked with
+    /// `location_count`.
+    fn location_formats(&self, id : u32, path : &mut Vec<u32>) -> Result<Vec<vk::Format>,ReflectionError> {
+        self.visit(id, path, |path| Ok(match self.get_type(id)? {
+            Type::Scalar { kind, width } => vec![vector_format(*kind, *width, 1)],
+            Type::Vector { component, count } => match self.get_type(*component)? {
+                Type::Scalar { kind, width } => vec![vector_format(*kind, *width, *count)],
+                _ => return Err(ReflectionError::Malformed("a vector has non-scalar components")),
+            },
+            Type::Matrix { column, count } => self.location_formats(*column, path)?.repeat(*count as usize),
+            Type::Array { element, length } => self.location_formats(*element, path)?.repeat(*length as usize),
+            _ => vec![vk::Format::UNDEFINED],
+        }))
+    }
+
+    /// Returns the size in bytes of a type in a block with an explicit layout. `matrix_stride` is the stride
+    /// decorated on the struct member holding the type, if any.
+    fn size(&self, id : u32, matrix_stride : Option<u32>, path : &mut Vec<u32>) -> Result<u32,ReflectionError> {
+        self.visit(id, path, |path| {
+            let multiply = |size : u32, count : u32| size
+                .checked_mul(count)
+                .ok_or(ReflectionError::Malformed("a push constant block is too large"));
+            Ok(match self.get_type(id)? {
+                Type::Scalar { width, .. } => width / 8,
+                Type::Vector { component, count } => multiply(self.size(*component, None, path)?, *count)?,
+                Type::Matrix { column, count } => match matrix_stride {
+                    Some(stride) => multiply(stride, *count)?,
+                    None => multiply(self.size(*column, None, path)?, *count)?,
+                },
+                Type::Array { element, length } => match self.decorations.get(&(id, DECORATION_ARRAY_STRIDE)) {
+                    Some(stride) => multiply(*stride, *length)?,
+                    None => multiply(self.size(*element, matrix_stride, path)?, *length)?,
+                },
+                // Runtime arrays are only allowed as the last member of a storage buffer, and add nothing to its size.
+                Type::RuntimeArray { .. } => 0,
+                Type::Struct { members } => {
+                    let mut size = 0;
+                    for (index, member) in members.iter().enumerate() {
+                        let index = index as u32;
+                        let offset = self.member_decorations.get(&(id, index, DECORATION_OFFSET)).copied().unwrap_or(size);
+                        let matrix_stride = self.member_decorations.get(&(id, index, DECORATION_MATRIX_STRIDE)).copied();
+                        let end = offset
+                            .checked_add(self.size(*member, matrix_stride, path)?)
+                            .ok_or(ReflectionError::Malformed("a push constant block is too large"))?;
+                        size = size.max(end);
+                    }
+                    size
+                },
+                _ => return Err(ReflectionError::Malformed("a push constant block contains an opaque type")),
+            })
+        })
+    }
+}
+
+fn execution_model_stage(model : u32) -> Result<vk::ShaderStageFlags,ReflectionError> {
+    Ok(match model {
+        0 => vk::ShaderStageFlags::VERTEX,
+        1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        3 => vk::ShaderStageFlags::GEOMETRY,
+        4 => vk::ShaderStageFlags::FRAGMENT,
+        5 => vk::ShaderStageFlags::COMPUTE,
+        _ => return Err(ReflectionError::UnsupportedExecutionModel(model)),
+    })
+}
+
+fn vector_format(kind : ScalarKind, width : u32, count : u32) -> vk::Format {
+    FORMATS
+        .iter()
+        .find(|(format_kind, format_width, _)| *format_kind == kind && *format_width == width)
+        .and_then(|(_, _, formats)| formats.get((count as usize).checked_sub(1)?).copied())
+        .unwrap_or(vk::Format::UNDEFINED)
+}
+
+/// Returns the numeric type and component count of a format which a shader can read or write, or `None` for other
+/// formats, such as `UNDEFINED`.
+fn numeric_type(format : vk::Format) -> Option<((ScalarKind, u32), u32)> {
+    FORMATS.iter().find_map(|(kind, width, formats)| {
+        let count = formats.iter().position(|candidate| *candidate == format)?;
+        Some(((*kind, *width), count as u32 + 1))
+    })
+}
+
+/// Returns true if a vertex attribute of format `vertex` can be read by a shader as `shader`. Formats which do not
+/// map to a shader type must match exactly.
+fn same_numeric_type(vertex : vk::Format, shader : vk::Format) -> bool {
+    match (numeric_type(vertex), numeric_type(shader)) {
+        (Some((vertex_type, _)), Some((shader_type, _))) => vertex_type == shader_type,
+        _ => vertex == shader,
+    }
+}
+
+/// Decodes a nul terminated UTF-8 literal, returning it and the number of words it occupies.
+fn parse_string(words : &[u32]) -> Result<(String, usize),ReflectionError> {
+    let bytes : Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let length = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or(ReflectionError::Malformed("a string is not terminated"))?;
+    let string = String::from_utf8(bytes[..length].to_vec())
+        .map_err(|_| ReflectionError::Malformed("a string is not valid UTF-8"))?;
+    Ok((string, length / 4 + 1))
+}
@@ -1,9 +1,12 @@
-use std::{cell::RefCell, iter, rc::Rc};
-use std::sync::{Arc, Mutex};
-use winit::dpi::{PhysicalPosition, PhysicalSize};
+use std::{cell::RefCell, path::Path, rc::Rc};
+use ash::vk;
+use winit::dpi::PhysicalSize;
 use winit::window::Window;
-use super::{Material, CmdBuffer, CmdPool, CmdState, Device, Framebuffer, FramebufferBuilder, Instance, Pipeline,
-            PipelineBuilder, RenderPass, RenderPassBuilder, Swapchain, Queue};
+use nalgebra::{Vector2, Vector3, Vector4};
+use super::{debug::ValidationMessage, Material, CmdPool, CmdState, Device, Framebuffer, FramebufferBuilder, IndexBuffer, Instance, OffscreenTarget, Pipeline,
+            PipelineBuilder, RenderPass, RenderPassBuilder, Surface, Swapchain, Queue, Vertex,
+            VertexBuffer, frame::Frame, swapchain::{PresentPolicy, SwapchainCreationError, SwapchainStatus}, upload::Uploader, compute::ComputeContext, Error,
+            error::VkResultExt};
 use crate::util::CapturedEvent;
 
 /// The highest level of the graphics module, the `Renderer` manages all render state.
@@ -13,21 +16,48 @@ pub struct Renderer {
     compute_queue : Option<Rc<RefCell<Queue>>>,
     graphics_queue : Option<Rc<RefCell<Queue>>>,
     transfer_queue : Option<Rc<RefCell<Queue>>>,
+    /// Runs compute work on the compute queue.
+    compute : Option<ComputeContext>,
+    /// Uploads mesh and texture data through the transfer queue.
+    uploader : Option<Uploader>,
     swapchain : Option<Swapchain>,
+    /// Set when the swapchain no longer matches the surface, so it is recreated before the next frame.
+    swapchain_out_of_date : bool,
+    /// Targets which are rendered into in place of the swapchain images when running headless.
+    offscreen_targets : Option<Vec<OffscreenTarget>>,
+    /// Index of the offscreen target which was last drawn to, if any frame has been drawn.
+    current_offscreen_target : Option<usize>,
+    /// Set when a headless renderer is resized, so the offscreen targets are recreated at this extent before the
+    /// next frame.
+    resized_offscreen_extent : Option<vk::Extent2D>,
     render_pass: Option<Rc<RefCell<RenderPass>>>,
     colored_graphics_pipeline : Option<Pipeline>,
     framebuffers : Option<Vec<Framebuffer>>,
+    /// Pool for one-off graphics commands, such as reading back offscreen targets.
     graphics_pool : Option<Rc<RefCell<CmdPool>>>,
-    graphics_buffer : Option<CmdBuffer>,
+    /// The frames which are cycled through, allowing the CPU to record a frame while the GPU executes earlier ones.
+    frames : Option<Vec<Frame>>,
+    /// Index of the frame which is recorded next.
+    current_frame : usize,
     material : Option<Material>,
+    vertex_buffer : Option<VertexBuffer>,
+    index_buffer : Option<IndexBuffer>,
 }
 
 impl Drop for Renderer {
     fn drop(&mut self) {
+        self.uploader.take();
+        debug_assert!(self.uploader.is_none());
+        self.compute.take();
+        debug_assert!(self.compute.is_none());
+        self.index_buffer.take();
+        debug_assert!(self.index_buffer.is_none());
+        self.vertex_buffer.take();
+        debug_assert!(self.vertex_buffer.is_none());
         self.material.take();
         debug_assert!(self.material.is_none());
-        self.graphics_buffer.take();
-        debug_assert!(self.graphics_buffer.is_none());
+        self.frames.take();
+        debug_assert!(self.frames.is_none());
         self.graphics_pool.take();
         debug_assert!(self.graphics_pool.is_none());
         self.framebuffers.take();
@@ -36,6 +66,8 @@ impl Drop for Renderer {
         debug_assert!(self.colored_graphics_pipeline.is_none());
         self.render_pass.take();
         debug_assert!(self.render_pass.is_none());
+        self.offscreen_targets.take();
+        debug_assert!(self.offscreen_targets.is_none());
         self.swapchain.take();
         debug_assert!(self.swapchain.is_none());
         self.compute_queue.take();
@@ -53,36 +85,69 @@ impl Drop for Renderer {
 }
 
 impl CapturedEvent for Renderer {
-    /// When this event is captured, the swapchain is recreated, and regenerates all framebuffers from the swapchain images.
-    fn on_resize(&mut self, _size : PhysicalSize<u32>) {
-        debug!("Resizing Swapchain");
-        self.swapchain.as_mut().unwrap().recreate();
-        self.framebuffers.as_mut().unwrap().clear();
-        for image in self.swapchain.as_ref().unwrap().images() {
-            self.framebuffers.as_mut().unwrap().push(FramebufferBuilder::new(
-                Rc::clone(&self.device.clone().unwrap()),
-                Rc::clone(&self.render_pass.clone().unwrap()),
-                image,
-                self.swapchain.as_ref().unwrap().surface_format().format,
-                self.swapchain.as_ref().unwrap().capabilities().current_extent)
-                .build());
+    /// When this event is captured, the swapchain is marked as out of date, and is recreated along with its
+    /// framebuffers before the next frame is drawn. When running headless, the offscreen targets are recreated at the
+    /// new size instead, also before the next frame. Pipelines use a dynamic viewport, so they are unaffected. Zero
+    /// sized extents, such as those of minimized windows, are ignored.
+    fn on_resize(&mut self, size : PhysicalSize<u32>) {
+        let extent = vk::Extent2D { width: size.width, height: size.height };
+        if let Some(swapchain) = self.swapchain.as_mut() {
+            debug!("Swapchain is out of date after a resize");
+            swapchain.set_window_extent(extent);
+            self.swapchain_out_of_date = true;
+        } else if extent.width != 0 && extent.height != 0 {
+            debug!("Offscreen targets are out of date after a resize");
+            self.resized_offscreen_extent = Some(extent);
         }
     }
 }
 
 impl Renderer {
-    /// Initializes the renderer for the specified window.
-    pub fn new(window : &Window) -> Self {
+    /// Initializes the renderer for the specified window. Fails if there is no Vulkan driver, or if no adapter can
+    /// render and present to the window.
+    pub fn new(window : &Window) -> Result<Self,Error> {
+        Self::create(window, None)
+    }
+
+    /// Initializes the renderer like `new`, seeding the pipeline cache from `pipeline_cache` and saving it back there
+    /// when the renderer is dropped. A missing or stale cache file is ignored.
+    pub fn with_pipeline_cache(window : &Window, pipeline_cache : &Path) -> Result<Self,Error> {
+        Self::create(window, Some(pipeline_cache))
+    }
+
+    fn create(window : &Window, pipeline_cache : Option<&Path>) -> Result<Self,Error> {
         info!("Initializing Renderer.");
-        // TODO: Properly handle errors here and present them to the output.
 
-        let instance = Rc::new(RefCell::new(Instance::new()
-            .ok()
-            .unwrap()));
+        let instance = Rc::new(RefCell::new(Instance::new()?));
+
+        // The surface is created first, so the device can be chosen based on its present support.
+        let surface = Surface::new(Rc::clone(&instance), window)?;
+        let window_extent = vk::Extent2D { width: window.inner_size().width, height: window.inner_size().height };
+        Self::with_surface(instance, surface, window_extent, pipeline_cache)
+    }
 
-        let device = Rc::new(RefCell::new(Device::new(&instance.borrow())
-            .ok()
-            .unwrap()));
+    /// Initializes the renderer with a swapchain on a headless surface, which is not backed by any window. This
+    /// exercises the same acquire, present, and recreation paths as a windowed renderer, and requires a driver
+    /// supporting `VK_EXT_headless_surface`. `extent` is used as the size of the surface.
+    pub fn new_headless_surface(extent : vk::Extent2D) -> Result<Self,Error> {
+        info!("Initializing Renderer with a headless surface.");
+
+        let instance = Rc::new(RefCell::new(Instance::new_headless_surface()?));
+        let surface = Surface::new_headless(Rc::clone(&instance))?;
+        Self::with_surface(instance, surface, extent, None)
+    }
+
+    /// Initializes the renderer to present to the surface. `window_extent` is used as the size of the swapchain if
+    /// the surface does not dictate it. The pipeline cache is loaded before any pipelines are built.
+    fn with_surface(instance : Rc<RefCell<Instance>>,
+                    surface : Surface,
+                    window_extent : vk::Extent2D,
+                    pipeline_cache : Option<&Path>) -> Result<Self,Error> {
+        let mut device = Device::new(&instance.borrow(), &surface)?;
+        if let Some(path) = pipeline_cache {
+            device.load_pipeline_cache(path)?;
+        }
+        let device = Rc::new(RefCell::new(device));
 
         // Create our queues.
         let compute_queue = Rc::new(RefCell::new(Queue::new(
@@ -94,25 +159,37 @@ impl Renderer {
         let transfer_queue = Rc::new(RefCell::new(Queue::new(
             Rc::clone(&device),
             device.borrow().transfer_queue_index())));
+        name_queues(&compute_queue.borrow(), &graphics_queue.borrow(), &transfer_queue.borrow());
+        // Present from the graphics queue unless its family cannot present to the surface.
+        let present_index = device.borrow().present_queue_index().unwrap();
+        let present_queue = if present_index == graphics_queue.borrow().family_index() {
+            Rc::clone(&graphics_queue)
+        } else {
+            Rc::new(RefCell::new(Queue::new(Rc::clone(&device), present_index)))
+        };
 
         // Create the swapchain.
         let swapchain = Swapchain::new(
             Rc::clone(&instance),
             Rc::clone(&device),
-            Rc::clone(&graphics_queue),
-            window,
-            2).ok()
-            .unwrap();
+            present_queue,
+            surface,
+            window_extent,
+            2,
+            PresentPolicy::Vsync)?;
 
         let render_pass = Rc::new(RefCell::new(RenderPassBuilder::new(
             Rc::clone(&device))
             .add_color_attachment(swapchain.surface_format().format)
-            .build()));
+            .build()?));
 
-        let material = Material::new(Rc::clone(&device));
+        let material = Material::new(Rc::clone(&device))?;
+        let compute = ComputeContext::new(Rc::clone(&device), Rc::clone(&compute_queue))?;
+        let mut uploader = Uploader::new(Rc::clone(&device), Rc::clone(&transfer_queue), Rc::clone(&graphics_queue))?;
+        let (vertex_buffer, index_buffer) = create_triangle(&device, &mut uploader)?;
 
         let colored_graphics_pipeline = PipelineBuilder::new(Rc::clone(&device))
-            .build_graphics(&render_pass.borrow(), &material, swapchain.capabilities().current_extent);
+            .build_graphics(&render_pass.borrow(), &material)?;
 
         // Grab the swapchain images to create the framebuffers.
         let mut framebuffers = Vec::<Framebuffer>::new();
@@ -122,59 +199,454 @@ impl Renderer {
                 Rc::clone(&render_pass),
                 image,
                 swapchain.surface_format().format,
-                swapchain.capabilities().current_extent
-            ).build());
+                swapchain.extent()
+            ).build()?);
         }
 
         let graphics_pool = Rc::new(RefCell::new(CmdPool::new(
             Rc::clone(&device),
-            &graphics_queue.borrow())));
+            &graphics_queue.borrow())?));
 
-        let graphics_buffer = CmdBuffer::new(
-            Rc::clone(&device),
-            Rc::clone(&graphics_pool));
+        let frames = create_frames(&device, &graphics_queue.borrow(), DEFAULT_FRAMES_IN_FLIGHT)?;
 
         info!("Renderer has been initialized.");
-        Self {
+        Ok(Self {
             instance: Some(instance),
             device: Some(device),
             compute_queue: Some(compute_queue),
             graphics_queue: Some(graphics_queue),
             transfer_queue: Some(transfer_queue),
+            compute: Some(compute),
+            uploader: Some(uploader),
+            // The window may be minimized, in which case the swapchain is created once it has a non-zero extent.
+            swapchain_out_of_date: swapchain.is_deferred(),
             swapchain: Some(swapchain),
+            offscreen_targets: None,
+            current_offscreen_target: None,
+            resized_offscreen_extent: None,
+            render_pass: Some(render_pass),
+            colored_graphics_pipeline : Some(colored_graphics_pipeline),
+            framebuffers: Some(framebuffers),
+            graphics_pool: Some(graphics_pool),
+            frames: Some(frames),
+            current_frame: 0,
+            material: Some(material),
+            vertex_buffer: Some(vertex_buffer),
+            index_buffer: Some(index_buffer),
+        })
+    }
+
+    /// Initializes the renderer without a window. Frames are drawn into offscreen images of the given extent, so no
+    /// surface or swapchain support is required from the driver. Use `read_frame` to retrieve the rendered pixels.
+    pub fn new_headless(extent : vk::Extent2D) -> Result<Self,Error> {
+        info!("Initializing headless Renderer.");
+
+        let instance = Rc::new(RefCell::new(Instance::new_headless()?));
+
+        let device = Rc::new(RefCell::new(Device::new_headless(&instance.borrow())?));
+
+        // Create our queues.
+        let compute_queue = Rc::new(RefCell::new(Queue::new(
+            Rc::clone(&device),
+            device.borrow().compute_queue_index())));
+        let graphics_queue = Rc::new(RefCell::new(Queue::new(
+            Rc::clone(&device),
+            device.borrow().graphics_queue_index())));
+        let transfer_queue = Rc::new(RefCell::new(Queue::new(
+            Rc::clone(&device),
+            device.borrow().transfer_queue_index())));
+        name_queues(&compute_queue.borrow(), &graphics_queue.borrow(), &transfer_queue.borrow());
+
+        let render_pass = Rc::new(RefCell::new(RenderPassBuilder::new(
+            Rc::clone(&device))
+            .add_color_attachment_with_layout(OFFSCREEN_FORMAT, vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .build()?));
+
+        let material = Material::new(Rc::clone(&device))?;
+        let compute = ComputeContext::new(Rc::clone(&device), Rc::clone(&compute_queue))?;
+        let mut uploader = Uploader::new(Rc::clone(&device), Rc::clone(&transfer_queue), Rc::clone(&graphics_queue))?;
+        let (vertex_buffer, index_buffer) = create_triangle(&device, &mut uploader)?;
+
+        let colored_graphics_pipeline = PipelineBuilder::new(Rc::clone(&device))
+            .build_graphics(&render_pass.borrow(), &material)?;
+
+        let offscreen_targets = create_offscreen_targets(&device, extent, DEFAULT_FRAMES_IN_FLIGHT)?;
+        let framebuffers = create_offscreen_framebuffers(&device, &render_pass, &offscreen_targets)?;
+
+        let graphics_pool = Rc::new(RefCell::new(CmdPool::new(
+            Rc::clone(&device),
+            &graphics_queue.borrow())?));
+
+        let frames = create_frames(&device, &graphics_queue.borrow(), DEFAULT_FRAMES_IN_FLIGHT)?;
+
+        info!("Headless Renderer has been initialized.");
+        Ok(Self {
+            instance: Some(instance),
+            device: Some(device),
+            compute_queue: Some(compute_queue),
+            graphics_queue: Some(graphics_queue),
+            transfer_queue: Some(transfer_queue),
+            compute: Some(compute),
+            uploader: Some(uploader),
+            swapchain: None,
+            swapchain_out_of_date: false,
+            offscreen_targets: Some(offscreen_targets),
+            current_offscreen_target: None,
+            resized_offscreen_extent: None,
             render_pass: Some(render_pass),
             colored_graphics_pipeline : Some(colored_graphics_pipeline),
             framebuffers: Some(framebuffers),
             graphics_pool: Some(graphics_pool),
-            graphics_buffer: Some(graphics_buffer),
-            material: Some(material)
+            frames: Some(frames),
+            current_frame: 0,
+            material: Some(material),
+            vertex_buffer: Some(vertex_buffer),
+            index_buffer: Some(index_buffer),
+        })
+    }
+
+    /// Returns the extent of the images which frames are drawn into.
+    pub fn extent(&self) -> vk::Extent2D {
+        match &self.swapchain {
+            Some(swapchain) => swapchain.extent(),
+            None => self.offscreen_targets.as_ref().unwrap().first().unwrap().extent(),
+        }
+    }
+
+    /// Returns the present mode in use, or `None` when running headless.
+    pub fn present_mode(&self) -> Option<vk::PresentModeKHR> {
+        self.swapchain.as_ref().map(|swapchain| swapchain.present_mode())
+    }
+
+    /// Returns the device, which can be used to create resources such as compute pipelines.
+    pub fn device(&self) -> Rc<RefCell<Device>> {
+        Rc::clone(self.device.as_ref().unwrap())
+    }
+
+    /// Returns the context for running compute work on the compute queue.
+    pub fn compute(&self) -> &ComputeContext {
+        self.compute.as_ref().unwrap()
+    }
+
+    /// Returns the validation messages reported since the last call. See `Instance::take_validation_messages`.
+    pub fn take_validation_messages(&self) -> Vec<ValidationMessage> {
+        self.instance.as_ref().unwrap().borrow().take_validation_messages()
+    }
+
+    /// Returns true if the renderer draws into offscreen images rather than a swapchain.
+    pub fn is_headless(&self) -> bool {
+        self.swapchain.is_none()
+    }
+
+    /// Returns the number of frames which can be in flight at once.
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames.as_ref().unwrap().len()
+    }
+
+    /// Changes the number of frames which can be in flight at once. More frames let the CPU run further ahead of the
+    /// GPU, at the cost of latency and memory. This waits for all frames in flight to complete. When running
+    /// headless, the offscreen targets are recreated so each frame has its own target, discarding the last frame.
+    /// Fails if `frames_in_flight` is zero.
+    pub fn set_frames_in_flight(&mut self, frames_in_flight : usize) -> Result<(),Error> {
+        if frames_in_flight == 0 {
+            return Err(Error::NoFramesInFlight);
         }
+        self.wait_for_frames()?;
+        let device = self.device.clone().unwrap();
+        self.frames = Some(create_frames(&device, &self.graphics_queue.as_ref().unwrap().borrow(), frames_in_flight)?);
+        self.current_frame = 0;
+
+        if self.is_headless() {
+            let extent = self.resized_offscreen_extent.take().unwrap_or_else(|| self.extent());
+            self.recreate_offscreen_targets(extent)?;
+        }
+        Ok(())
     }
 
-    pub fn draw_frame(&mut self) {
-        let next_image = &self.swapchain.as_mut().unwrap().acquire_next_image();
+    /// Changes how frames are synchronized with the display, recreating the swapchain before the next frame. Fails
+    /// if the surface does not support the policy. This has no effect when running headless.
+    pub fn set_present_policy(&mut self, present_policy : PresentPolicy) -> Result<(),SwapchainCreationError> {
+        if let Some(swapchain) = self.swapchain.as_mut() {
+            swapchain.set_present_policy(present_policy)?;
+            self.swapchain_out_of_date = true;
+        }
+        Ok(())
+    }
+
+    /// Draws and presents a frame, or draws into the next offscreen target when running headless. Returns the status
+    /// of acquiring or presenting the swapchain image, which is `OutOfDate` if the frame was skipped because the window
+    /// is minimized. Out of date swapchains are recreated before the next frame, but if the surface was lost, the
+    /// caller must create a new renderer for the window. Fails if the device reports an error which recreating the
+    /// swapchain cannot recover from, such as a lost device.
+    pub fn draw_frame(&mut self) -> Result<SwapchainStatus,Error> {
+        self.uploader.as_mut().unwrap().cleanup()?;
+        if self.is_headless() {
+            self.draw_offscreen_frame()?;
+            return Ok(SwapchainStatus::Ok);
+        }
+
+        if self.swapchain_out_of_date && !self.recreate_swapchain()? {
+            // The window is minimized, so there is nothing to draw to.
+            return Ok(SwapchainStatus::OutOfDate);
+        }
+
+        let frame = self.frames.as_ref().unwrap().get(self.current_frame).unwrap();
+        // Wait until the GPU has finished with this frame's resources before reusing them.
+        frame.wait()?;
+        let acquire_status = self.swapchain.as_mut().unwrap().acquire_next_image(frame.acquire_semaphore())?;
+        if !self.handle_swapchain_status(acquire_status) {
+            // No image was acquired, so the frame is skipped. The frame's fence was not reset, so it can be reused.
+            return Ok(acquire_status);
+        }
+        let next_image = self.swapchain.as_ref().unwrap().current_image();
+        let frame = self.frames.as_mut().unwrap().get_mut(self.current_frame).unwrap();
         let cmd_state = CmdState {
             format: self.swapchain.as_ref().unwrap().surface_format().format,
-            extent: self.swapchain.as_ref().unwrap().capabilities().current_extent
+            extent: self.swapchain.as_ref().unwrap().extent()
         };
 
-        self.graphics_buffer
-            .as_mut()
-            .unwrap()
+        frame.cmd_buffer_mut()
             .record_graphics(
                 cmd_state,
                 &self.render_pass.as_ref().unwrap().borrow(),
-                self.framebuffers.as_ref().unwrap().get(next_image.clone() as usize).unwrap(),
-                self.colored_graphics_pipeline.as_ref().unwrap());
+                self.framebuffers.as_ref().unwrap().get(next_image as usize).unwrap(),
+                self.colored_graphics_pipeline.as_ref().unwrap(),
+                self.vertex_buffer.as_ref().unwrap(),
+                self.index_buffer.as_ref())?;
 
         // Queue needs to submit our draw calls, but has to wait for the image to be acquired.
+        frame.reset_fence()?;
         self.graphics_queue
             .as_ref()
             .unwrap()
             .borrow()
-            .submit(self.graphics_buffer.as_ref().unwrap(),
-                    Some(self.swapchain.as_ref().unwrap().current_acquire_semaphore()),
-                    Some(self.swapchain.as_ref().unwrap().current_acquire_fence()));
-        self.swapchain.as_ref().unwrap().present();
+            .submit(frame.cmd_buffer(),
+                    Some(frame.acquire_semaphore()),
+                    Some(self.swapchain.as_ref().unwrap().render_finished_semaphore()),
+                    Some(frame.in_flight_fence()))?;
+        let present_status = self.swapchain
+            .as_ref()
+            .unwrap()
+            .present(self.swapchain.as_ref().unwrap().render_finished_semaphore())?;
+        self.handle_swapchain_status(present_status);
+        self.current_frame = (self.current_frame + 1) % self.frames_in_flight();
+        Ok(present_status)
+    }
+
+    /// Returns the most recently drawn frame as tightly packed RGBA8 pixels, along with its extent. Returns `None`
+    /// if the renderer is not headless, or if no frame has been drawn yet.
+    pub fn read_frame(&self) -> Result<Option<(Vec<u8>, vk::Extent2D)>,Error> {
+        let target = match (&self.offscreen_targets, self.current_offscreen_target) {
+            (Some(targets), Some(index)) => &targets[index],
+            _ => return Ok(None),
+        };
+        let pixels = target.read_pixels(
+            &self.graphics_queue.as_ref().unwrap().borrow(),
+            Rc::clone(self.graphics_pool.as_ref().unwrap()))?;
+        Ok(Some((pixels, target.extent())))
+    }
+
+    /// Records and submits a frame into the next offscreen target.
+    fn draw_offscreen_frame(&mut self) -> Result<(),Error> {
+        if let Some(extent) = self.resized_offscreen_extent.take() {
+            self.recreate_offscreen_targets(extent)?;
+        }
+        // Each frame renders into its own target, so waiting for the frame also makes its target safe to reuse.
+        let next_target = self.current_frame;
+        let frame = self.frames.as_mut().unwrap().get_mut(next_target).unwrap();
+        frame.wait()?;
+        let target = self.offscreen_targets.as_ref().unwrap().get(next_target).unwrap();
+        let cmd_state = CmdState {
+            format: target.format(),
+            extent: target.extent()
+        };
+
+        frame.cmd_buffer_mut()
+            .record_graphics(
+                cmd_state,
+                &self.render_pass.as_ref().unwrap().borrow(),
+                self.framebuffers.as_ref().unwrap().get(next_target).unwrap(),
+                self.colored_graphics_pipeline.as_ref().unwrap(),
+                self.vertex_buffer.as_ref().unwrap(),
+                self.index_buffer.as_ref())?;
+
+        // Nothing is presented, so there is no acquire semaphore to wait on.
+        frame.reset_fence()?;
+        self.graphics_queue
+            .as_ref()
+            .unwrap()
+            .borrow()
+            .submit_offscreen(frame.cmd_buffer(), Some(frame.in_flight_fence()))?;
+        self.current_offscreen_target = Some(next_target);
+        self.current_frame = (self.current_frame + 1) % self.frames_in_flight();
+        Ok(())
+    }
+
+    /// Recreates one offscreen target of `extent` for each frame in flight, along with their framebuffers. This
+    /// discards the last frame.
+    fn recreate_offscreen_targets(&mut self, extent : vk::Extent2D) -> Result<(),Error> {
+        debug!("Recreating offscreen targets");
+        // Frames in flight may still be rendering to the images which are about to be destroyed.
+        self.wait_for_frames()?;
+        let device = self.device.clone().unwrap();
+        // The previous targets are kept if creating the new ones fails.
+        let offscreen_targets = create_offscreen_targets(&device, extent, self.frames_in_flight())?;
+        let framebuffers = create_offscreen_framebuffers(&device, self.render_pass.as_ref().unwrap(), &offscreen_targets)?;
+        self.framebuffers = Some(framebuffers);
+        self.offscreen_targets = Some(offscreen_targets);
+        self.current_offscreen_target = None;
+        Ok(())
     }
+
+    /// Marks the swapchain for recreation if the status requires it, returning true if the operation succeeded.
+    fn handle_swapchain_status(&mut self, status : SwapchainStatus) -> bool {
+        match status {
+            SwapchainStatus::Ok => (),
+            SwapchainStatus::Suboptimal | SwapchainStatus::OutOfDate => {
+                debug!("Swapchain is {:?}", status);
+                self.swapchain_out_of_date = true;
+            },
+            // The surface can only be recreated from the window, which the renderer does not own, so this is left to
+            // the caller.
+            SwapchainStatus::SurfaceLost => warn!("Lost surface, skipping frame"),
+        }
+        status.is_success()
+    }
+
+    /// Recreates the swapchain along with the framebuffers which depend on it. If the surface format changed, the
+    /// render pass and pipelines are rebuilt for the new format. Returns false if the surface has a zero extent, in
+    /// which case the swapchain remains out of date.
+    fn recreate_swapchain(&mut self) -> Result<bool,Error> {
+        debug!("Recreating Swapchain");
+        // The previous swapchain images may still be in use by frames in flight or by presentation.
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .borrow()
+                .ash_device()
+                .device_wait_idle()
+                .context("Failed to wait for the device before recreating the swapchain")?;
+        }
+        let previous_format = self.swapchain.as_ref().unwrap().surface_format().format;
+        if !self.swapchain.as_mut().unwrap().recreate()? {
+            return Ok(false);
+        }
+
+        let swapchain = self.swapchain.as_ref().unwrap();
+        let extent = swapchain.extent();
+        self.framebuffers.as_mut().unwrap().clear();
+        if swapchain.surface_format().format != previous_format {
+            info!("Surface format changed to {:?}, rebuilding the render pass", swapchain.surface_format().format);
+            let device = Rc::clone(self.device.as_ref().unwrap());
+            let render_pass = RenderPassBuilder::new(Rc::clone(&device))
+                .add_color_attachment(swapchain.surface_format().format)
+                .build()?;
+            let pipeline = PipelineBuilder::new(device).build_graphics(&render_pass, self.material.as_ref().unwrap())?;
+            self.colored_graphics_pipeline = Some(pipeline);
+            self.render_pass = Some(Rc::new(RefCell::new(render_pass)));
+        }
+        for image in swapchain.images() {
+            self.framebuffers.as_mut().unwrap().push(FramebufferBuilder::new(
+                Rc::clone(self.device.as_ref().unwrap()),
+                Rc::clone(self.render_pass.as_ref().unwrap()),
+                image,
+                swapchain.surface_format().format,
+                extent)
+                .build()?);
+        }
+        self.swapchain_out_of_date = false;
+        Ok(true)
+    }
+
+    /// Blocks until every frame in flight has completed.
+    fn wait_for_frames(&self) -> Result<(),Error> {
+        for frame in self.frames.as_ref().unwrap() {
+            frame.wait()?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates the default triangle, with a red, green, and blue corner, in device local memory. This blocks until the
+/// upload has completed.
+fn create_triangle(device : &Rc<RefCell<Device>>,
+                   uploader : &mut Uploader) -> Result<(VertexBuffer, IndexBuffer),Error> {
+    let texture_coord = Vector2::new(0.0, 0.0);
+    let vertices = [
+        Vertex::new(Vector3::new(0.0, -0.5, 0.0), Vector4::new(1.0, 0.0, 0.0, 1.0), texture_coord),
+        Vertex::new(Vector3::new(0.5, 0.5, 0.0), Vector4::new(0.0, 1.0, 0.0, 1.0), texture_coord),
+        Vertex::new(Vector3::new(-0.5, 0.5, 0.0), Vector4::new(0.0, 0.0, 1.0, 1.0), texture_coord),
+    ];
+    let indices : [u16; 3] = [0, 1, 2];
+    let (vertex_buffer, _) = VertexBuffer::new_device_local(Rc::clone(device), uploader, &vertices)?;
+    let (index_buffer, _) = IndexBuffer::new_device_local(Rc::clone(device), uploader, &indices)?;
+    vertex_buffer.set_debug_name("Triangle vertices");
+    index_buffer.set_debug_name("Triangle indices");
+    uploader.wait_all()?;
+    debug!("Uploaded the triangle, {:?}", device.borrow().memory_stats());
+    Ok((vertex_buffer, index_buffer))
+}
+
+/// Names the queues. Queues from a shared family are the same queue, which is given one combined name, such as
+/// "Graphics+transfer queue", rather than each role overwriting the last.
+fn name_queues(compute_queue : &Queue, graphics_queue : &Queue, transfer_queue : &Queue) {
+    let roles = [(graphics_queue, "graphics"), (compute_queue, "compute"), (transfer_queue, "transfer")];
+    for (index, (queue, _)) in roles.iter().enumerate() {
+        let is_first = roles[..index].iter().all(|(other, _)| other.queue_raw() != queue.queue_raw());
+        if !is_first {
+            continue;
+        }
+        let name = roles
+            .iter()
+            .filter(|(other, _)| other.queue_raw() == queue.queue_raw())
+            .map(|(_, role)| *role)
+            .collect::<Vec<_>>()
+            .join("+");
+        let mut name = format!("{} queue", name);
+        name[..1].make_ascii_uppercase();
+        queue.set_debug_name(&name);
+    }
+}
+
+/// Color format used by the offscreen targets of a headless renderer.
+const OFFSCREEN_FORMAT : vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// Number of frames which can be in flight at once, unless changed with `Renderer::set_frames_in_flight`.
+const DEFAULT_FRAMES_IN_FLIGHT : usize = 2;
+
+fn create_frames(device : &Rc<RefCell<Device>>, graphics_queue : &Queue, count : usize) -> Result<Vec<Frame>,Error> {
+    (0..count)
+        .map(|index| {
+            let frame = Frame::new(Rc::clone(device), graphics_queue)?;
+            frame.cmd_buffer().set_debug_name(&format!("Frame {} commands", index));
+            Ok(frame)
+        })
+        .collect()
+}
+
+/// Creates one offscreen target for each frame in flight.
+fn create_offscreen_targets(device : &Rc<RefCell<Device>>,
+                            extent : vk::Extent2D,
+                            count : usize) -> Result<Vec<OffscreenTarget>,Error> {
+    (0..count)
+        .map(|index| {
+            let target = OffscreenTarget::new(Rc::clone(device), OFFSCREEN_FORMAT, extent)?;
+            target.set_debug_name(&format!("Offscreen target {}", index));
+            Ok(target)
+        })
+        .collect()
+}
+
+fn create_offscreen_framebuffers(device : &Rc<RefCell<Device>>,
+                                 render_pass : &Rc<RefCell<RenderPass>>,
+                                 targets : &[OffscreenTarget]) -> Result<Vec<Framebuffer>,Error> {
+    targets
+        .iter()
+        .map(|target| FramebufferBuilder::from_target(
+            Rc::clone(device),
+            Rc::clone(render_pass),
+            target)
+            .build())
+        .collect()
 }
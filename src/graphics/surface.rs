@@ -0,0 +1,86 @@
+use std::{cell::RefCell, rc::Rc};
+use ash::extensions::khr::Surface as SurfaceLoader;
+use ash::vk;
+use winit::window::Window;
+use super::{Instance, error::{Error, VkResultExt}, platform::{create_headless_surface, create_surface}};
+
+/// A platform surface which a swapchain presents to. The surface is created before the device, so that adapter
+/// and queue selection can check for present support.
+pub struct Surface {
+    _instance : Rc<RefCell<Instance>>,
+    surface_loader : SurfaceLoader,
+    surface : vk::SurfaceKHR,
+}
+
+impl Drop for Surface {
+    fn drop(&mut self) {
+        unsafe {
+            self.surface_loader.destroy_surface(self.surface, None);
+        }
+        info!("Dropped Surface")
+    }
+}
+
+impl Surface {
+    /// Creates a surface for the window.
+    pub fn new(instance : Rc<RefCell<Instance>>, window : &Window) -> Result<Self,Error> {
+        let (surface_loader, surface) = {
+            let instance = instance.borrow();
+            let surface_loader = SurfaceLoader::new(instance.ash_entry(), instance.ash_instance());
+            let surface = create_surface(&instance, window)
+                .context("Failed to create the window surface")?;
+            (surface_loader, surface)
+        };
+        Ok(Self { _instance: instance, surface_loader, surface })
+    }
+
+    /// Creates a surface which is not backed by a window. The instance must have been created with
+    /// `Instance::new_headless_surface`.
+    pub fn new_headless(instance : Rc<RefCell<Instance>>) -> Result<Self,Error> {
+        let (surface_loader, surface) = {
+            let instance = instance.borrow();
+            let surface_loader = SurfaceLoader::new(instance.ash_entry(), instance.ash_instance());
+            let surface = create_headless_surface(instance.ash_entry(), instance.ash_instance())
+                .context("Failed to create the headless surface")?;
+            (surface_loader, surface)
+        };
+        Ok(Self { _instance: instance, surface_loader, surface })
+    }
+
+    /// Returns true if the queue family of the physical device can present to this surface.
+    pub fn supports_present(&self, physical_device : vk::PhysicalDevice, family_index : u32) -> bool {
+        unsafe {
+            self.surface_loader
+                .get_physical_device_surface_support(physical_device, family_index, self.surface)
+                .unwrap_or(false)
+        }
+    }
+
+    pub fn capabilities(&self, physical_device : vk::PhysicalDevice) -> Result<vk::SurfaceCapabilitiesKHR,Error> {
+        unsafe {
+            self.surface_loader
+                .get_physical_device_surface_capabilities(physical_device, self.surface)
+                .context("Failed to query the surface capabilities")
+        }
+    }
+
+    pub fn formats(&self, physical_device : vk::PhysicalDevice) -> Result<Vec<vk::SurfaceFormatKHR>,Error> {
+        unsafe {
+            self.surface_loader
+                .get_physical_device_surface_formats(physical_device, self.surface)
+                .context("Failed to query the surface formats")
+        }
+    }
+
+    pub fn present_modes(&self, physical_device : vk::PhysicalDevice) -> Result<Vec<vk::PresentModeKHR>,Error> {
+        unsafe {
+            self.surface_loader
+                .get_physical_device_surface_present_modes(physical_device, self.surface)
+                .context("Failed to query the surface present modes")
+        }
+    }
+
+    pub fn surface_raw(&self) -> vk::SurfaceKHR {
+        self.surface
+    }
+}
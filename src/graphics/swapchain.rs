@@ -1,33 +1,124 @@
-use std::{cell::RefCell, iter, rc::Rc};
-use ash::extensions::{khr::Surface as SurfaceLoader, khr::Swapchain as SwapchainLoader};
+use std::{cell::RefCell, error, fmt, rc::Rc};
+use ash::extensions::khr::Swapchain as SwapchainLoader;
 use ash::vk::{self, Result as VkResult};
-use winit::window::Window;
-use super::{Device, Instance, Queue, platform::create_surface, platform::get_required_instance_extensions, util::select_color_format};
+use super::{Device, Instance, Queue, Surface, error::{Error, VkResultExt}, util::select_color_format};
 
 /// Provides a brief overview of why a swapchain failed to be created.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum SwapchainCreationError {
     /// Provided presentation queue does not support presentation.
     QueuePresentUnsupported,
+    /// The requested image count was zero.
     InvalidImageCount,
+    /// The surface does not support the present mode required by the `PresentPolicy`.
+    PresentModeUnsupported(vk::PresentModeKHR),
+}
+
+impl fmt::Display for SwapchainCreationError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SwapchainCreationError::QueuePresentUnsupported => write!(f, "The present queue cannot present to the surface"),
+            SwapchainCreationError::InvalidImageCount => write!(f, "A swapchain needs at least one image"),
+            SwapchainCreationError::PresentModeUnsupported(present_mode) =>
+                write!(f, "The surface does not support the {:?} present mode", present_mode),
+        }
+    }
+}
+
+impl error::Error for SwapchainCreationError {}
+
+/// Controls how presented images are synchronized with the display's refresh.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresentPolicy {
+    /// Waits for the vertical blank, never tearing. This is always supported.
+    Vsync,
+    /// Waits for the vertical blank, but presents immediately if a refresh was missed, which may tear. Falls back
+    /// to `Vsync` if unsupported.
+    Adaptive,
+    /// Replaces the queued image with newer ones, without tearing or blocking rendering. Falls back to `Vsync` if
+    /// unsupported.
+    Mailbox,
+    /// Presents immediately, which may tear. This fails if unsupported, as there is no equivalent fallback.
+    Immediate,
+}
+
+impl PresentPolicy {
+    /// Returns the present mode to use from those supported by the surface.
+    pub fn select(self, supported : &[vk::PresentModeKHR]) -> Result<vk::PresentModeKHR,SwapchainCreationError> {
+        let (preferred, fallback) = match self {
+            PresentPolicy::Vsync => (vk::PresentModeKHR::FIFO, None),
+            PresentPolicy::Adaptive => (vk::PresentModeKHR::FIFO_RELAXED, Some(vk::PresentModeKHR::FIFO)),
+            PresentPolicy::Mailbox => (vk::PresentModeKHR::MAILBOX, Some(vk::PresentModeKHR::FIFO)),
+            PresentPolicy::Immediate => (vk::PresentModeKHR::IMMEDIATE, None),
+        };
+        // FIFO support is required by the specification, even if a driver fails to report it.
+        if supported.contains(&preferred) || preferred == vk::PresentModeKHR::FIFO {
+            return Ok(preferred);
+        }
+        match fallback {
+            Some(fallback) => {
+                warn!("Present mode {:?} is unsupported, falling back to {:?}", preferred, fallback);
+                Ok(fallback)
+            },
+            None => Err(SwapchainCreationError::PresentModeUnsupported(preferred)),
+        }
+    }
+}
+
+/// The outcome of acquiring or presenting a swapchain image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwapchainStatus {
+    Ok,
+    /// The operation succeeded, but the swapchain no longer matches the surface exactly and should be recreated.
+    Suboptimal,
+    /// The swapchain is incompatible with the surface and must be recreated. No image was acquired or presented.
+    OutOfDate,
+    /// The surface is no longer available, and must be recreated along with the swapchain.
+    SurfaceLost,
+}
+
+impl SwapchainStatus {
+    /// Returns true if an image was acquired or presented.
+    pub fn is_success(self) -> bool {
+        matches!(self, SwapchainStatus::Ok | SwapchainStatus::Suboptimal)
+    }
+
+    /// Maps the results which require recreation to a status, and fails on any other result, such as a lost device.
+    fn from_error(error : VkResult, context : &'static str) -> Result<Self,Error> {
+        match error {
+            VkResult::ERROR_OUT_OF_DATE_KHR => Ok(SwapchainStatus::OutOfDate),
+            VkResult::ERROR_SURFACE_LOST_KHR => Ok(SwapchainStatus::SurfaceLost),
+            result => Err(Error::Vulkan { context, result }),
+        }
+    }
+
+    fn from_suboptimal(suboptimal : bool) -> Self {
+        if suboptimal { SwapchainStatus::Suboptimal } else { SwapchainStatus::Ok }
+    }
 }
 
 pub struct Swapchain {
-    instance : Rc<RefCell<Instance>>,
     device : Rc<RefCell<Device>>,
     present_queue : Rc<RefCell<Queue>>,
-    surface_loader : SurfaceLoader,
-    surface : vk::SurfaceKHR,
+    surface : Surface,
     surface_format : vk::SurfaceFormatKHR,
     capabilities : vk::SurfaceCapabilitiesKHR,
     formats : Vec<vk::SurfaceFormatKHR>,
     present_modes : Vec<vk::PresentModeKHR>,
+    present_policy : PresentPolicy,
+    present_mode : vk::PresentModeKHR,
     swapchain_loader : SwapchainLoader,
     swapchain : vk::SwapchainKHR,
-    acquire_semaphores : Vec<vk::Semaphore>,
-    acquire_fences : Vec<vk::Fence>,
     images : Vec<vk::Image>,
+    /// One semaphore per image, signalled when rendering to the image has finished and waited on by its presentation.
+    /// Presentation does not signal a fence, so a semaphore is only known to be unused once its image is acquired
+    /// again, which is why they cannot belong to frames in flight.
+    render_finished_semaphores : Vec<vk::Semaphore>,
+    /// The number of images requested by the caller, before clamping to the surface's limits.
     image_count : u32,
-    current_frame : u32,
+    extent : vk::Extent2D,
+    /// The size of the window, used when the surface lets the swapchain decide its extent.
+    window_extent : vk::Extent2D,
     current_image : u32,
 }
 
@@ -35,14 +126,8 @@ impl Drop for Swapchain {
     fn drop(&mut self) {
         unsafe {
             self.device.borrow().ash_device().device_wait_idle().unwrap();
-            for semaphore in self.acquire_semaphores.clone() {
-                self.device.borrow().ash_device().destroy_semaphore(semaphore, None);
-            }
-            for fence in self.acquire_fences.clone() {
-                self.device.borrow().ash_device().destroy_fence(fence, None);
-            }
+            destroy_semaphores(&self.device.borrow(), &self.render_finished_semaphores);
             self.swapchain_loader.destroy_swapchain(self.swapchain, None);
-            self.surface_loader.destroy_surface(self.surface, None);
         }
         info!("Dropped Swapchain")
     }
@@ -50,51 +135,35 @@ impl Drop for Swapchain {
 
 impl Swapchain {
     /// Creates a new swapchain with the given surface. This function will only need to be called once.
-    /// Any events that break the existing swapchain `should` call `recreate`.
+    /// Any events that break the existing swapchain `should` call `recreate`. `image_count` is clamped to the
+    /// limits of the surface, and `window_extent` is used if the surface does not dictate the extent of its images.
+    /// If the surface has a zero extent, such as when the window starts minimized, the swapchain has no images until
+    /// `recreate` succeeds; see `is_deferred`. Fails if the surface does not support `present_policy`, or if
+    /// `image_count` is zero.
     pub fn new(instance : Rc<RefCell<Instance>>,
                device : Rc<RefCell<Device>>,
                present_queue : Rc<RefCell<Queue>>,
-               window : &Window,
-               image_count : u32) -> Result<Self,SwapchainCreationError> {
-        // Initializes surface entry points and creates one.
-        let surface_loader = SurfaceLoader::new(
-            instance.borrow().ash_entry(),
-            instance.borrow().ash_instance());
-        let surface = create_surface(
-            instance.borrow().ash_entry(),
-            instance.borrow().ash_instance(), window);
-
-        let supports_present = unsafe {
-            surface_loader.get_physical_device_surface_support(
-                device.borrow().physical_device(),
-                0,
-                surface)
-        };
+               surface : Surface,
+               window_extent : vk::Extent2D,
+               image_count : u32,
+               present_policy : PresentPolicy) -> Result<Self,Error> {
+        let physical_device = device.borrow().physical_device();
+        if image_count == 0 {
+            return Err(SwapchainCreationError::InvalidImageCount.into());
+        }
 
-        // Verifies that the device supports presentation.
-        if !supports_present.unwrap() {
-            return Err(SwapchainCreationError::QueuePresentUnsupported);
+        // Verifies that the queue supports presentation.
+        if !surface.supports_present(physical_device, present_queue.borrow().family_index()) {
+            return Err(SwapchainCreationError::QueuePresentUnsupported.into());
         }
 
         // Grab surface capabilities, formats, and present modes.
-        let (capabilities, formats, present_modes) = unsafe {
-            let capabilities = surface_loader
-                .get_physical_device_surface_capabilities(
-                    device.borrow().physical_device(),
-                    surface)
-                .unwrap();
-            let formats = surface_loader
-                .get_physical_device_surface_formats(
-                    device.borrow().physical_device(),
-                    surface)
-                .unwrap();
-            let present_modes = surface_loader
-                .get_physical_device_surface_present_modes(
-                    device.borrow().physical_device(),
-                    surface)
-                .unwrap();
-            (capabilities, formats, present_modes)
-        };
+        let capabilities = surface.capabilities(physical_device)?;
+        let formats = surface.formats(physical_device)?;
+        let present_modes = surface.present_modes(physical_device)?;
+        let present_mode = present_policy.select(&present_modes)?;
+        info!("Presenting with {:?} for {:?}", present_mode, present_policy);
+        let extent = select_extent(&capabilities, window_extent);
 
         let swapchain_loader = SwapchainLoader::new(
             instance.borrow().ash_instance(),
@@ -104,183 +173,206 @@ impl Swapchain {
             formats.clone(),
             vk::Format::B8G8R8A8_SRGB);
 
+        // Images are shared between the graphics and present families when they differ.
+        let queue_family_indices = [device.borrow().graphics_queue_index(), present_queue.borrow().family_index()];
         let swapchain_info = vk::SwapchainCreateInfoKHR::builder()
-            .surface(surface)
-            .image_extent(capabilities.current_extent)
+            .surface(surface.surface_raw())
+            .image_extent(extent)
             .image_format(surface_format.format)
             .image_color_space(surface_format.color_space)
             .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
-            .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
+            .image_sharing_mode(sharing_mode(&queue_family_indices))
+            .queue_family_indices(&queue_family_indices)
+            .pre_transform(select_pre_transform(&capabilities))
             .image_array_layers(1)
-            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-            .min_image_count(image_count)
+            .composite_alpha(select_composite_alpha(&capabilities))
+            .min_image_count(select_image_count(&capabilities, image_count))
+            .present_mode(present_mode)
             .clipped(true);
-        let swapchain = unsafe {
-            swapchain_loader
-                .create_swapchain(&swapchain_info, None)
-                .expect("Failed to create swapchain")
-        };
-
-        // Initialize our acquire semaphores.
-        let semaphore_info = vk::SemaphoreCreateInfo::builder()
-            .build();
-        let acquire_semaphores = iter::repeat_with(||
-            unsafe {
-                device
-                    .borrow()
-                    .ash_device()
-                    .create_semaphore(&semaphore_info, None)
-                    .expect("Failed to create semaphore")
-            })
-            .take(image_count as _)
-            .collect();
-
-        let fence_info = vk::FenceCreateInfo::builder()
-            .flags(vk::FenceCreateFlags::SIGNALED);
-        let acquire_fences = iter::repeat_with(||
+        // A minimized window has a zero extent, which a swapchain cannot be created with, so creation is deferred
+        // until `recreate` succeeds.
+        let (swapchain, images) = if extent.width == 0 || extent.height == 0 {
+            debug!("Surface has a zero extent, deferring swapchain creation");
+            (vk::SwapchainKHR::null(), Vec::new())
+        } else {
             unsafe {
-                device
-                    .borrow()
-                    .ash_device()
-                    .create_fence(&fence_info, None)
-                    .expect("Failed to create fence")
-            })
-            .take(image_count as _)
-            .collect();
-
-        let images = unsafe {
-            swapchain_loader
-                .get_swapchain_images(swapchain)
-                .unwrap()
+                let swapchain = swapchain_loader
+                    .create_swapchain(&swapchain_info, None)
+                    .context("Failed to create the swapchain")?;
+                let images = swapchain_loader
+                    .get_swapchain_images(swapchain)
+                    .context("Failed to retrieve the swapchain images")
+                    .inspect_err(|_| swapchain_loader.destroy_swapchain(swapchain, None))?;
+                (swapchain, images)
+            }
         };
+        let render_finished_semaphores = create_semaphores(&device.borrow(), images.len())
+            .inspect_err(|_| unsafe { swapchain_loader.destroy_swapchain(swapchain, None) })?;
 
-        Ok(Self { instance,
+        Ok(Self {
             device,
             present_queue,
-            surface_loader,
             surface,
             surface_format,
             capabilities,
             formats,
             present_modes,
+            present_policy,
+            present_mode,
             swapchain_loader,
             swapchain,
-            acquire_semaphores,
-            acquire_fences,
             images,
+            render_finished_semaphores,
             image_count,
-            current_frame: 0,
+            extent,
+            window_extent,
             current_image: 0,
         })
     }
 
-    /// Returns the next image index in the swapchain. This is typically used at the beginning of a render pass.
-    pub fn acquire_next_image(&mut self) -> u32 {
-        self.current_frame = (self.current_frame + 1) % self.image_count;
+    /// Acquires the next image in the swapchain, whose index is then returned by `current_image`. This is typically
+    /// used at the beginning of a render pass. `signal_semaphore` is signalled once the image is ready to be rendered
+    /// to, and should be waited on by the submission which renders to it. If the status is not a success, no image
+    /// was acquired and the semaphore will not be signalled. Fails on errors which recreation cannot fix.
+    pub fn acquire_next_image(&mut self, signal_semaphore : vk::Semaphore) -> Result<SwapchainStatus,Error> {
         let acquire_result = unsafe {
-            // Wait for these fences to be signalled then reset them to a non-signalled state.
-            self.device
-                .borrow()
-                .ash_device()
-                .wait_for_fences(&[self.acquire_fences.get(self.current_frame as usize).unwrap().clone()], true, u64::max_value())
-                .unwrap();
-            self.device
-                .borrow()
-                .ash_device()
-                .reset_fences(&[self.acquire_fences.get(self.current_frame as usize).unwrap().clone()])
-                .unwrap();
             // Attempt to acquire the next image from the swapchain.
             self.swapchain_loader
                 .acquire_next_image(
                     self.swapchain,
-                    u64::max_value(),
-                    // Signal this semaphore on completion. Present queue waits for this to complete before submission.
-                    self.acquire_semaphores.get(self.current_frame as usize).unwrap().clone(),
+                    u64::MAX,
+                    signal_semaphore,
                     vk::Fence::null())
         };
         match acquire_result {
-            Ok(index) => self.current_image = index.0,
-            // TODO: Handle these events.
-            Err(error) => match error {
-                VkResult::ERROR_SURFACE_LOST_KHR => error!("Lost surface"),
-                VkResult::ERROR_OUT_OF_DATE_KHR => error!("Images are out of date"),
-                _ => (),
-            }
+            Ok((index, suboptimal)) => {
+                self.current_image = index;
+                Ok(SwapchainStatus::from_suboptimal(suboptimal))
+            },
+            Err(error) => SwapchainStatus::from_error(error, "Failed to acquire a swapchain image"),
         }
-        self.current_image
     }
 
     /// Presents the image to the screen, using the specified present queue. The present queue can be any queue
-    /// graphics, transfer, compute which supports present operations.
-    pub fn present(&self) {
+    /// graphics, transfer, compute which supports present operations. Presentation waits on `wait_semaphore`, which
+    /// should be signalled once rendering to the image has completed. Fails on errors which recreation cannot fix.
+    pub fn present(&self, wait_semaphore : vk::Semaphore) -> Result<SwapchainStatus,Error> {
+        let image_indices = [self.current_image];
+        let swapchains = [self.swapchain];
+        let wait_semaphores = [wait_semaphore];
         let present_info = vk::PresentInfoKHR::builder()
-            .image_indices(&[self.current_image])
-            .swapchains(&[self.swapchain])
+            .image_indices(&image_indices)
+            .swapchains(&swapchains)
             // Wait on submission to be completed before presenting.
-            .wait_semaphores(&[self.present_queue.borrow().submit_semaphore_raw()])
+            .wait_semaphores(&wait_semaphores)
             .build();
-        // TODO: Use value to validate present status.
-        let present_status = unsafe {
+        let present_result = unsafe {
             self.swapchain_loader.queue_present(
                 self.present_queue.borrow().queue_raw(),
                 &present_info)
         };
-        match present_status {
-            Ok(status) => (),
-            Err(error) => (),
+        match present_result {
+            Ok(suboptimal) => Ok(SwapchainStatus::from_suboptimal(suboptimal)),
+            Err(error) => SwapchainStatus::from_error(error, "Failed to present a swapchain image"),
         }
     }
 
-    /// Recreates the swapchain. This is particularly useful in the event of resizes.
-    pub fn recreate(&mut self) {
-        unsafe {
-            self.capabilities = self.surface_loader
-                .get_physical_device_surface_capabilities(
-                    self.device.borrow().physical_device(),
-                    self.surface)
-                .unwrap();
-            self.formats = self.surface_loader
-                .get_physical_device_surface_formats(
-                    self.device.borrow().physical_device(),
-                    self.surface)
-                .unwrap();
-            self.present_modes = self.surface_loader
-                .get_physical_device_surface_present_modes(
-                    self.device.borrow().physical_device(),
-                    self.surface)
-                .unwrap();
+    /// Changes the present policy, which takes effect the next time the swapchain is recreated. Fails without
+    /// changing the policy if the surface does not support it.
+    pub fn set_present_policy(&mut self, present_policy : PresentPolicy) -> Result<(),SwapchainCreationError> {
+        present_policy.select(&self.present_modes)?;
+        self.present_policy = present_policy;
+        Ok(())
+    }
+
+    /// Recreates the swapchain. This is particularly useful in the event of resizes, or to apply a new present
+    /// policy. If the present policy is no longer supported by the surface, `Vsync` is used. The images of the previous
+    /// swapchain must no longer be in use. Returns false without recreating the swapchain if the surface has a zero
+    /// extent, which happens while the window is minimized; recreation should be attempted again later. If recreation
+    /// fails, the previous swapchain is still retired, so no more images can be acquired until `recreate` succeeds.
+    pub fn recreate(&mut self) -> Result<bool,Error> {
+        let physical_device = self.device.borrow().physical_device();
+        self.capabilities = self.surface.capabilities(physical_device)?;
+        let extent = select_extent(&self.capabilities, self.window_extent);
+        if extent.width == 0 || extent.height == 0 {
+            debug!("Surface has a zero extent, deferring swapchain recreation");
+            return Ok(false);
         }
+        self.formats = self.surface.formats(physical_device)?;
+        self.present_modes = self.surface.present_modes(physical_device)?;
+        let present_mode = self.present_policy
+            .select(&self.present_modes)
+            .unwrap_or_else(|_| {
+                warn!("{:?} is no longer supported, falling back to Vsync", self.present_policy);
+                vk::PresentModeKHR::FIFO
+            });
 
-        self.surface_format = select_color_format(
+        let surface_format = select_color_format(
             self.formats.clone(),
             vk::Format::B8G8R8A8_SRGB);
 
+        let queue_family_indices = [
+            self.device.borrow().graphics_queue_index(),
+            self.present_queue.borrow().family_index()];
         let swapchain_info = vk::SwapchainCreateInfoKHR::builder()
-            .surface(self.surface)
+            .surface(self.surface.surface_raw())
             .old_swapchain(self.swapchain)
-            .image_extent(self.capabilities.current_extent)
-            .image_format(self.surface_format.format)
-            .image_color_space(self.surface_format.color_space)
+            .image_extent(extent)
+            .image_format(surface_format.format)
+            .image_color_space(surface_format.color_space)
             .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
-            .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
+            .image_sharing_mode(sharing_mode(&queue_family_indices))
+            .queue_family_indices(&queue_family_indices)
+            .pre_transform(select_pre_transform(&self.capabilities))
             .image_array_layers(1)
-            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-            .min_image_count(self.image_count)
+            .composite_alpha(select_composite_alpha(&self.capabilities))
+            .min_image_count(select_image_count(&self.capabilities, self.image_count))
+            .present_mode(present_mode)
             .clipped(true);
-        self.swapchain = unsafe {
-            let new_swapchain = self.swapchain_loader
+        let swapchain = unsafe {
+            self.swapchain_loader
                 .create_swapchain(&swapchain_info, None)
-                .expect("Failed to create swapchain");
-            self.swapchain_loader.destroy_swapchain(self.swapchain, None);
-            new_swapchain
+                .context("Failed to recreate the swapchain")?
         };
-
-        self.images = unsafe {
+        // Nothing is replaced until every fallible step has succeeded, so the images and semaphores always belong to
+        // `self.swapchain`.
+        let destroy_swapchain = |_ : &Error| unsafe { self.swapchain_loader.destroy_swapchain(swapchain, None) };
+        let images = unsafe {
             self.swapchain_loader
-                .get_swapchain_images(self.swapchain)
-                .unwrap()
+                .get_swapchain_images(swapchain)
+                .context("Failed to retrieve the swapchain images")
+                .inspect_err(destroy_swapchain)?
         };
-        info!("Recreated Swapchain")
+        let render_finished_semaphores = if images.len() != self.render_finished_semaphores.len() {
+            Some(create_semaphores(&self.device.borrow(), images.len()).inspect_err(destroy_swapchain)?)
+        } else {
+            None
+        };
+
+        unsafe { self.swapchain_loader.destroy_swapchain(self.swapchain, None) };
+        self.swapchain = swapchain;
+        self.images = images;
+        if let Some(render_finished_semaphores) = render_finished_semaphores {
+            let device = self.device.borrow();
+            unsafe { destroy_semaphores(&device, &self.render_finished_semaphores) };
+            self.render_finished_semaphores = render_finished_semaphores;
+        }
+        self.extent = extent;
+        self.surface_format = surface_format;
+        self.present_mode = present_mode;
+        info!("Recreated Swapchain");
+        Ok(true)
+    }
+
+    /// Returns true if creation was deferred because the surface had a zero extent, in which case `recreate` must
+    /// succeed before any image can be acquired.
+    pub fn is_deferred(&self) -> bool {
+        self.swapchain == vk::SwapchainKHR::null()
+    }
+
+    /// Returns the semaphore which rendering to the current image should signal, and which `present` should wait on.
+    pub fn render_finished_semaphore(&self) -> vk::Semaphore {
+        self.render_finished_semaphores[self.current_image as usize]
     }
 
     /// Returns the images associated with this Swapchain, used in the creation of a Framebuffer.
@@ -293,6 +385,17 @@ impl Swapchain {
         self.current_image
     }
 
+    /// Returns the extent of the swapchain images.
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    /// Updates the size of the window, which is used the next time the swapchain is recreated if the surface does
+    /// not dictate the extent of its images.
+    pub fn set_window_extent(&mut self, window_extent : vk::Extent2D) {
+        self.window_extent = window_extent;
+    }
+
     /// Returns the capabilities provided by the surface which initialized this Swapchain.
     pub fn capabilities(&self) -> vk::SurfaceCapabilitiesKHR {
         self.capabilities
@@ -308,24 +411,93 @@ impl Swapchain {
         self.surface_format
     }
 
+    pub fn present_policy(&self) -> PresentPolicy {
+        self.present_policy
+    }
+
+    /// Returns the present mode which was selected for the swapchain from its present policy.
+    pub fn present_mode(&self) -> vk::PresentModeKHR {
+        self.present_mode
+    }
+
     /// Returns all present modes supported by the surface initialized with the Swapchain.
     pub fn supported_present_modes(&self) -> Vec<vk::PresentModeKHR> {
         self.present_modes.clone()
     }
+}
+
+/// Creates a semaphore for each swapchain image, destroying any already created if one fails.
+fn create_semaphores(device : &Device, count : usize) -> Result<Vec<vk::Semaphore>,Error> {
+    let mut semaphores = Vec::with_capacity(count);
+    for _ in 0..count {
+        let semaphore = unsafe {
+            device
+                .ash_device()
+                .create_semaphore(&vk::SemaphoreCreateInfo::builder(), None)
+                .context("Failed to create a swapchain semaphore")
+                .inspect_err(|_| destroy_semaphores(device, &semaphores))?
+        };
+        semaphores.push(semaphore);
+    }
+    Ok(semaphores)
+}
+
+/// Destroys the semaphores, which must no longer be in use.
+unsafe fn destroy_semaphores(device : &Device, semaphores : &[vk::Semaphore]) {
+    for semaphore in semaphores {
+        device.ash_device().destroy_semaphore(*semaphore, None);
+    }
+}
+
+/// Returns `CONCURRENT` if the images are used by more than one queue family.
+fn sharing_mode(queue_family_indices : &[u32; 2]) -> vk::SharingMode {
+    if queue_family_indices[0] == queue_family_indices[1] {
+        vk::SharingMode::EXCLUSIVE
+    } else {
+        vk::SharingMode::CONCURRENT
+    }
+}
 
-    /// Returns the semaphore being used by the swapchain.
-    pub fn current_acquire_semaphore(&self) -> vk::Semaphore {
-        self.acquire_semaphores
-            .get(self.current_frame as usize)
-            .unwrap()
-            .clone()
+/// Clamps the requested image count to the limits of the surface, where a maximum of zero means there is no limit.
+pub(crate) fn select_image_count(capabilities : &vk::SurfaceCapabilitiesKHR, image_count : u32) -> u32 {
+    let max_image_count = if capabilities.max_image_count == 0 { u32::MAX } else { capabilities.max_image_count };
+    let clamped = image_count.clamp(capabilities.min_image_count, max_image_count);
+    if clamped != image_count {
+        warn!("Requested {} swapchain images, using {} to satisfy the surface", image_count, clamped);
     }
+    clamped
+}
 
-    /// Returns the current fence being used by the swapchain.
-    pub fn current_acquire_fence(&self) -> vk::Fence {
-        self.acquire_fences
-            .get(self.current_frame as usize)
-            .unwrap()
-            .clone()
+/// Returns the surface's current extent, or the window's extent clamped to the surface's limits if the surface lets
+/// the swapchain decide, which it signals with a current extent of `0xFFFFFFFF`.
+pub(crate) fn select_extent(capabilities : &vk::SurfaceCapabilitiesKHR, window_extent : vk::Extent2D) -> vk::Extent2D {
+    if capabilities.current_extent.width != u32::MAX {
+        return capabilities.current_extent;
+    }
+    vk::Extent2D {
+        width: window_extent.width.clamp(capabilities.min_image_extent.width, capabilities.max_image_extent.width),
+        height: window_extent.height.clamp(capabilities.min_image_extent.height, capabilities.max_image_extent.height),
     }
-}
\ No newline at end of file
+}
+
+/// Avoids rotating images when possible, otherwise matches the surface's current transform.
+pub(crate) fn select_pre_transform(capabilities : &vk::SurfaceCapabilitiesKHR) -> vk::SurfaceTransformFlagsKHR {
+    if capabilities.supported_transforms.contains(vk::SurfaceTransformFlagsKHR::IDENTITY) {
+        vk::SurfaceTransformFlagsKHR::IDENTITY
+    } else {
+        capabilities.current_transform
+    }
+}
+
+/// Prefers an opaque surface, falling back to the first other mode the surface supports.
+pub(crate) fn select_composite_alpha(capabilities : &vk::SurfaceCapabilitiesKHR) -> vk::CompositeAlphaFlagsKHR {
+    [
+        vk::CompositeAlphaFlagsKHR::OPAQUE,
+        vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+        vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+        vk::CompositeAlphaFlagsKHR::INHERIT,
+    ]
+        .into_iter()
+        .find(|composite_alpha| capabilities.supported_composite_alpha.contains(*composite_alpha))
+        .unwrap_or(vk::CompositeAlphaFlagsKHR::OPAQUE)
+}
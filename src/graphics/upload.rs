@@ -0,0 +1,360 @@
+use std::{cell::RefCell, error, fmt, mem::size_of_val, rc::Rc};
+use ash::vk;
+use super::{CmdBuffer, CmdPool, Device, Image, Queue, error::{Error, VkResultExt}, util::format_size};
+use super::buffer::{Buffer, StagingBuffer};
+
+/// Provides a brief overview of why an upload was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UploadError {
+    /// The data is larger than the destination buffer.
+    BufferOverflow { size : vk::DeviceSize, capacity : vk::DeviceSize },
+    /// The number of texels does not match the extent of the destination image.
+    TexelCountMismatch { size : vk::DeviceSize, expected : vk::DeviceSize },
+    /// The size of a texel is unknown for the format of the destination image, such as for compressed formats.
+    UnsupportedFormat(vk::Format),
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadError::BufferOverflow { size, capacity } =>
+                write!(f, "Cannot upload {} bytes into a buffer of {} bytes", size, capacity),
+            UploadError::TexelCountMismatch { size, expected } =>
+                write!(f, "Cannot upload {} bytes of texels into an image of {} bytes", size, expected),
+            UploadError::UnsupportedFormat(format) => write!(f, "Cannot upload texels of the {:?} format", format),
+        }
+    }
+}
+
+impl error::Error for UploadError {}
+
+/// Identifies an upload submitted through an `Uploader`, which can be polled or waited on until the data is
+/// resident in the destination resource.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UploadId(u64);
+
+/// Copies data into device local buffers and images. Data is written into host visible staging buffers, and the
+/// copies are recorded and submitted to the transfer queue. When the transfer queue belongs to a different family
+/// than the graphics queue, ownership of the destination is released by the transfer queue and acquired by the
+/// graphics queue, so the resource can be used by graphics work once the upload completes.
+///
+/// Staging buffers are kept alive until their upload completes, and are released by `cleanup`.
+pub struct Uploader {
+    device : Rc<RefCell<Device>>,
+    transfer_queue : Rc<RefCell<Queue>>,
+    graphics_queue : Rc<RefCell<Queue>>,
+    transfer_pool : Rc<RefCell<CmdPool>>,
+    graphics_pool : Rc<RefCell<CmdPool>>,
+    pending : Vec<PendingUpload>,
+    next_id : u64,
+}
+
+impl Drop for Uploader {
+    fn drop(&mut self) {
+        if let Err(error) = self.wait_all() {
+            error!("Failed to wait for uploads before dropping the uploader: {}", error);
+        }
+        info!("Dropped Uploader")
+    }
+}
+
+impl Uploader {
+    pub fn new(device : Rc<RefCell<Device>>,
+               transfer_queue : Rc<RefCell<Queue>>,
+               graphics_queue : Rc<RefCell<Queue>>) -> Result<Self,Error> {
+        let transfer_pool = Rc::new(RefCell::new(CmdPool::new(Rc::clone(&device), &transfer_queue.borrow())?));
+        let graphics_pool = Rc::new(RefCell::new(CmdPool::new(Rc::clone(&device), &graphics_queue.borrow())?));
+        Ok(Self {
+            device,
+            transfer_queue,
+            graphics_queue,
+            transfer_pool,
+            graphics_pool,
+            pending: Vec::new(),
+            next_id: 0,
+        })
+    }
+
+    /// Uploads `data` to the start of `buffer`, which must have been created with `TRANSFER_DST` usage. Fails if
+    /// `data` is larger than the buffer.
+    pub fn upload_buffer<T : Copy>(&mut self, buffer : &Buffer, data : &[T]) -> Result<UploadId,Error> {
+        let size = size_of_val(data) as vk::DeviceSize;
+        if size > buffer.size() {
+            return Err(UploadError::BufferOverflow { size, capacity: buffer.size() }.into());
+        }
+        let staging_buffer = StagingBuffer::new(Rc::clone(&self.device), data)?;
+        let destination = Destination::Buffer(buffer.buffer_raw());
+        self.submit(staging_buffer, destination, |cmd_buffer, staging_buffer| {
+            cmd_buffer.copy_buffer(staging_buffer, buffer);
+        })
+    }
+
+    /// Uploads tightly packed texels to the whole of `image`, which must have been created with `TRANSFER_DST`
+    /// usage. Any previous contents of the image are discarded, and it is left in `final_layout`. Fails unless there
+    /// is exactly one texel of the image's format for each texel of its extent.
+    pub fn upload_image(&mut self,
+                        image : &Image,
+                        texels : &[u8],
+                        final_layout : vk::ImageLayout) -> Result<UploadId,Error> {
+        let texel_size = format_size(image.format()).ok_or(UploadError::UnsupportedFormat(image.format()))?;
+        let extent = image.extent();
+        let expected = extent.width as vk::DeviceSize * extent.height as vk::DeviceSize * texel_size as vk::DeviceSize;
+        let size = texels.len() as vk::DeviceSize;
+        if size != expected {
+            return Err(UploadError::TexelCountMismatch { size, expected }.into());
+        }
+        let staging_buffer = StagingBuffer::new(Rc::clone(&self.device), texels)?;
+        let destination = Destination::Image { image: image.image_raw(), final_layout };
+        self.submit(staging_buffer, destination, |cmd_buffer, staging_buffer| {
+            let barrier = vk::ImageMemoryBarrier::builder()
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image.image_raw())
+                .subresource_range(color_subresource_range())
+                .build();
+            cmd_buffer.pipeline_barrier(
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                &[],
+                &[barrier]);
+            cmd_buffer.copy_buffer_to_image(staging_buffer, image, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        })
+    }
+
+    /// Returns true once the upload has completed and the destination can be used on the graphics queue.
+    pub fn is_complete(&self, upload : UploadId) -> Result<bool,Error> {
+        match self.pending.iter().find(|pending| pending.id == upload) {
+            Some(pending) => pending.is_complete(),
+            None => Ok(true),
+        }
+    }
+
+    /// Blocks until the upload has completed.
+    pub fn wait(&mut self, upload : UploadId) -> Result<(),Error> {
+        if let Some(pending) = self.pending.iter().find(|pending| pending.id == upload) {
+            pending.wait()?;
+        }
+        self.cleanup()
+    }
+
+    /// Blocks until every pending upload has completed.
+    pub fn wait_all(&mut self) -> Result<(),Error> {
+        for pending in &self.pending {
+            pending.wait()?;
+        }
+        self.cleanup()
+    }
+
+    /// Releases the staging buffers and command buffers of completed uploads. This should be called regularly, such
+    /// as once per frame. Uploads whose status cannot be queried are kept.
+    pub fn cleanup(&mut self) -> Result<(),Error> {
+        let mut result = Ok(());
+        self.pending.retain(|pending| match pending.is_complete() {
+            Ok(complete) => !complete,
+            Err(error) => {
+                result = Err(error);
+                true
+            },
+        });
+        result
+    }
+
+    /// Records and submits an upload. `record_copy` records the copy from the staging buffer into the destination,
+    /// after which the barriers making the copy visible to the graphics queue are recorded.
+    fn submit<F>(&mut self,
+                 staging_buffer : StagingBuffer,
+                 destination : Destination,
+                 record_copy : F) -> Result<UploadId,Error>
+        where F : FnOnce(&CmdBuffer, &StagingBuffer) {
+        let device = Rc::clone(&self.device);
+        let transfer_family = self.transfer_queue.borrow().family_index();
+        let graphics_family = self.graphics_queue.borrow().family_index();
+        let fence = unsafe {
+            device
+                .borrow()
+                .ash_device()
+                .create_fence(&vk::FenceCreateInfo::builder(), None)
+                .context("Failed to create an upload fence")?
+        };
+        // The upload owns its fence and semaphore from here on, so they are destroyed if recording or submission fails.
+        let mut pending = PendingUpload {
+            device: Rc::clone(&device),
+            id: UploadId(self.next_id),
+            fence,
+            semaphore: None,
+            staging_buffer,
+            cmd_buffers: Vec::new(),
+        };
+
+        let mut transfer_cmd_buffer = CmdBuffer::new(Rc::clone(&device), Rc::clone(&self.transfer_pool))?;
+        transfer_cmd_buffer.begin()?;
+        record_copy(&transfer_cmd_buffer, &pending.staging_buffer);
+
+        if transfer_family == graphics_family {
+            destination.record_barrier(
+                &transfer_cmd_buffer,
+                (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+                (vk::PipelineStageFlags::ALL_COMMANDS, vk::AccessFlags::MEMORY_READ),
+                (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED));
+            transfer_cmd_buffer.end()?;
+            self.transfer_queue.borrow().submit_with(&transfer_cmd_buffer, &[], &[], Some(fence))?;
+            pending.cmd_buffers.push(transfer_cmd_buffer);
+        } else {
+            // The release and acquire barriers must describe the same transfer of ownership, and the acquire must not
+            // execute until the release has, which the semaphore guarantees.
+            let semaphore = unsafe {
+                device
+                    .borrow()
+                    .ash_device()
+                    .create_semaphore(&vk::SemaphoreCreateInfo::builder(), None)
+                    .context("Failed to create an upload semaphore")?
+            };
+            pending.semaphore = Some(semaphore);
+            destination.record_barrier(
+                &transfer_cmd_buffer,
+                (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+                (vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::AccessFlags::empty()),
+                (transfer_family, graphics_family));
+            transfer_cmd_buffer.end()?;
+            self.transfer_queue.borrow().submit_with(&transfer_cmd_buffer, &[], &[semaphore], None)?;
+            pending.cmd_buffers.push(transfer_cmd_buffer);
+
+            // The release has already been submitted, so it must finish before its semaphore can be destroyed.
+            let wait_for_release = |_ : &Error| if let Err(error) = self.transfer_queue.borrow().wait_idle() {
+                error!("Failed to wait for a released upload: {}", error);
+            };
+            let mut acquire_cmd_buffer = CmdBuffer::new(Rc::clone(&device), Rc::clone(&self.graphics_pool))
+                .inspect_err(wait_for_release)?;
+            acquire_cmd_buffer.begin().inspect_err(wait_for_release)?;
+            destination.record_barrier(
+                &acquire_cmd_buffer,
+                (vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::empty()),
+                (vk::PipelineStageFlags::ALL_COMMANDS, vk::AccessFlags::MEMORY_READ),
+                (transfer_family, graphics_family));
+            acquire_cmd_buffer.end().inspect_err(wait_for_release)?;
+            self.graphics_queue
+                .borrow()
+                .submit_with(
+                    &acquire_cmd_buffer,
+                    &[(semaphore, vk::PipelineStageFlags::ALL_COMMANDS)],
+                    &[],
+                    Some(fence))
+                .inspect_err(wait_for_release)?;
+            pending.cmd_buffers.push(acquire_cmd_buffer);
+        }
+
+        let id = pending.id;
+        self.next_id += 1;
+        self.pending.push(pending);
+        Ok(id)
+    }
+}
+
+/// The resource an upload writes to.
+#[derive(Clone, Copy)]
+enum Destination {
+    Buffer(vk::Buffer),
+    Image { image : vk::Image, final_layout : vk::ImageLayout },
+}
+
+impl Destination {
+    /// Records a barrier after the copy, given as `(stage, access)` pairs and a `(source, destination)` pair of queue
+    /// families. Images are transitioned from `TRANSFER_DST_OPTIMAL` to their final layout.
+    fn record_barrier(self,
+                      cmd_buffer : &CmdBuffer,
+                      src : (vk::PipelineStageFlags, vk::AccessFlags),
+                      dst : (vk::PipelineStageFlags, vk::AccessFlags),
+                      families : (u32, u32)) {
+        match self {
+            Destination::Buffer(buffer) => {
+                let barrier = vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(src.1)
+                    .dst_access_mask(dst.1)
+                    .src_queue_family_index(families.0)
+                    .dst_queue_family_index(families.1)
+                    .buffer(buffer)
+                    .size(vk::WHOLE_SIZE)
+                    .build();
+                cmd_buffer.pipeline_barrier(src.0, dst.0, &[barrier], &[]);
+            },
+            Destination::Image { image, final_layout } => {
+                let barrier = vk::ImageMemoryBarrier::builder()
+                    .src_access_mask(src.1)
+                    .dst_access_mask(dst.1)
+                    .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .new_layout(final_layout)
+                    .src_queue_family_index(families.0)
+                    .dst_queue_family_index(families.1)
+                    .image(image)
+                    .subresource_range(color_subresource_range())
+                    .build();
+                cmd_buffer.pipeline_barrier(src.0, dst.0, &[], &[barrier]);
+            },
+        }
+    }
+}
+
+/// An upload which may still be executing, owning the resources it uses until it completes.
+struct PendingUpload {
+    device : Rc<RefCell<Device>>,
+    id : UploadId,
+    fence : vk::Fence,
+    semaphore : Option<vk::Semaphore>,
+    staging_buffer : StagingBuffer,
+    cmd_buffers : Vec<CmdBuffer>,
+}
+
+impl Drop for PendingUpload {
+    fn drop(&mut self) {
+        // Once the fence has signalled, the command buffers can be freed without waiting for the device to idle.
+        match self.is_complete() {
+            Ok(true) => {
+                for cmd_buffer in self.cmd_buffers.drain(..) {
+                    cmd_buffer.free_completed();
+                }
+            },
+            Ok(false) => (),
+            Err(error) => error!("Failed to query an upload before dropping it: {}", error),
+        }
+        unsafe {
+            let device = self.device.borrow();
+            device.ash_device().destroy_fence(self.fence, None);
+            if let Some(semaphore) = self.semaphore {
+                device.ash_device().destroy_semaphore(semaphore, None);
+            }
+        }
+    }
+}
+
+impl PendingUpload {
+    fn is_complete(&self) -> Result<bool,Error> {
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .get_fence_status(self.fence)
+                .context("Failed to query an upload fence")
+        }
+    }
+
+    fn wait(&self) -> Result<(),Error> {
+        unsafe {
+            self.device
+                .borrow()
+                .ash_device()
+                .wait_for_fences(&[self.fence], true, u64::MAX)
+                .context("Failed to wait for an upload fence")
+        }
+    }
+}
+
+fn color_subresource_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .level_count(1)
+        .layer_count(1)
+        .build()
+}
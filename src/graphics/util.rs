@@ -6,9 +6,9 @@ pub fn select_color_format(formats : Vec<vk::SurfaceFormatKHR>, preferred : vk::
         .iter()
         .find(|format| {
             format.format == preferred
-        }).or(formats.get(0))
+        }).or(formats.first())
         .unwrap();
-    returned_format.clone()
+    *returned_format
 }
 
 /// Returns the optimal depth-stencil format, if one exists. Returns `Some(vk::Format)` when a format exists, and None if
@@ -16,7 +16,8 @@ pub fn select_color_format(formats : Vec<vk::SurfaceFormatKHR>, preferred : vk::
 pub fn select_depth_stencil_format(instance : ash::Instance,
                                    physical_device : vk::PhysicalDevice,
                                    preferred : vk::Format) -> Option<vk::Format> {
-    let depth_stencil_formats = vec![vk::Format::D32_SFLOAT_S8_UINT,
+    let depth_stencil_formats = vec![preferred,
+                                     vk::Format::D32_SFLOAT_S8_UINT,
                                      vk::Format::D32_SFLOAT,
                                      vk::Format::D24_UNORM_S8_UINT,
                                      vk::Format::D16_UNORM_S8_UINT,
@@ -31,17 +32,47 @@ pub fn select_depth_stencil_format(instance : ash::Instance,
     None
 }
 
+/// Returns the first memory type allowed by the requirements which has all of the property `flags`.
 pub fn find_memory_type_index(memory_req: &vk::MemoryRequirements,
                              memory_prop: &vk::PhysicalDeviceMemoryProperties,
                              flags: vk::MemoryPropertyFlags) -> Option<u32> {
-    let mut memory_type_bits = memory_req.memory_type_bits;
-    for (index, ref memory_type) in memory_prop.memory_types.iter().enumerate() {
-        if memory_type_bits & 1 == 1 {
-            return Some(index as u32);
-        }
-        memory_type_bits = memory_type_bits >> 1;
-    }
-    None
+    memory_prop.memory_types[..memory_prop.memory_type_count as usize]
+        .iter()
+        .enumerate()
+        .find(|(index, memory_type)| {
+            memory_req.memory_type_bits & (1 << index) != 0 && memory_type.property_flags.contains(flags)
+        })
+        .map(|(index, _)| index as u32)
+}
+
+/// Returns the size in bytes of a single texel of an uncompressed color format, or `None` for depth-stencil,
+/// compressed, and multi-planar formats.
+pub fn format_size(format : vk::Format) -> Option<u32> {
+    // Formats with the same components are declared consecutively, differing only in their numeric type, so each
+    // range covers one component layout.
+    let size = match format.as_raw() {
+        1 => 1,            // R4G4_UNORM_PACK8
+        2 ..= 8 => 2,      // 16 bit packed formats
+        9 ..= 15 => 1,     // R8
+        16 ..= 22 => 2,    // R8G8
+        23 ..= 36 => 3,    // R8G8B8, B8G8R8
+        37 ..= 69 => 4,    // R8G8B8A8, B8G8R8A8, and 32 bit packed formats
+        70 ..= 76 => 2,    // R16
+        77 ..= 83 => 4,    // R16G16
+        84 ..= 90 => 6,    // R16G16B16
+        91 ..= 97 => 8,    // R16G16B16A16
+        98 ..= 100 => 4,   // R32
+        101 ..= 103 => 8,  // R32G32
+        104 ..= 106 => 12, // R32G32B32
+        107 ..= 109 => 16, // R32G32B32A32
+        110 ..= 112 => 8,  // R64
+        113 ..= 115 => 16, // R64G64
+        116 ..= 118 => 24, // R64G64B64
+        119 ..= 121 => 32, // R64G64B64A64
+        122 ..= 123 => 4,  // B10G11R11_UFLOAT_PACK32, E5B9G9R9_UFLOAT_PACK32
+        _ => return None,
+    };
+    Some(size)
 }
 
 pub fn get_max_multisampling_value(limits : vk::PhysicalDeviceLimits) -> vk::SampleCountFlags {
@@ -62,3 +93,29 @@ pub fn get_max_multisampling_value(limits : vk::PhysicalDeviceLimits) -> vk::Sam
     vk::SampleCountFlags::TYPE_1
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_color_formats() {
+        assert_eq!(format_size(vk::Format::R4G4_UNORM_PACK8), Some(1));
+        assert_eq!(format_size(vk::Format::A1R5G5B5_UNORM_PACK16), Some(2));
+        assert_eq!(format_size(vk::Format::R8_SRGB), Some(1));
+        assert_eq!(format_size(vk::Format::B8G8R8_SRGB), Some(3));
+        assert_eq!(format_size(vk::Format::R8G8B8A8_SRGB), Some(4));
+        assert_eq!(format_size(vk::Format::A2B10G10R10_SINT_PACK32), Some(4));
+        assert_eq!(format_size(vk::Format::R16G16B16_SFLOAT), Some(6));
+        assert_eq!(format_size(vk::Format::R32G32B32A32_SFLOAT), Some(16));
+        assert_eq!(format_size(vk::Format::R64G64B64A64_SFLOAT), Some(32));
+        assert_eq!(format_size(vk::Format::E5B9G9R9_UFLOAT_PACK32), Some(4));
+    }
+
+    #[test]
+    fn rejects_depth_and_compressed_formats() {
+        assert_eq!(format_size(vk::Format::UNDEFINED), None);
+        assert_eq!(format_size(vk::Format::D32_SFLOAT), None);
+        assert_eq!(format_size(vk::Format::BC1_RGB_UNORM_BLOCK), None);
+    }
+}
@@ -7,7 +7,10 @@ extern crate winit;
 /// High-level wrapper for [ash](https://github.com/MaikKlein/ash) around typical types.
 pub mod graphics;
 pub mod util;
+#[cfg(test)]
+mod tests;
 
+use std::path::Path;
 use log::LevelFilter;
 use log4rs::append::console::ConsoleAppender;
 use log4rs::config::{Appender, Config, Root};
@@ -15,6 +18,7 @@ use winit::dpi::LogicalSize;
 use winit::event::{Event, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
+use crate::graphics::swapchain::SwapchainStatus;
 use crate::util::CapturedEvent;
 
 fn main() {
@@ -24,7 +28,7 @@ fn main() {
         .build(Root::builder().appender("stdout_appender").build(LevelFilter::Info))
         .unwrap();
 
-    let handle = log4rs::init_config(config).unwrap();
+    let _handle = log4rs::init_config(config).unwrap();
 
     let events_loop = EventLoop::new();
     let window = WindowBuilder::new()
@@ -34,7 +38,14 @@ fn main() {
         .build(&events_loop)
         .expect("Failed to create window.");
 
-    let mut renderer = graphics::Renderer::new(&window);
+    let pipeline_cache = Path::new("pipeline_cache.bin");
+    let mut renderer = match graphics::Renderer::with_pipeline_cache(&window, pipeline_cache) {
+        Ok(renderer) => renderer,
+        Err(error) => {
+            error!("Failed to initialize the renderer: {}", error);
+            return;
+        }
+    };
 
     events_loop.run(move |event, _, control_flow| {
         if let Event::WindowEvent { event, .. } = event {
@@ -44,6 +55,16 @@ fn main() {
                 _ => (),
             }
         }
-        renderer.draw_frame();
+        match renderer.draw_frame() {
+            Ok(SwapchainStatus::SurfaceLost) => {
+                error!("The window surface was lost");
+                *control_flow = ControlFlow::Exit;
+            },
+            Ok(_) => (),
+            Err(error) => {
+                error!("Failed to draw a frame: {}", error);
+                *control_flow = ControlFlow::Exit;
+            },
+        }
     });
 }
\ No newline at end of file
@@ -0,0 +1,72 @@
+use ash::vk;
+use super::{assert_no_validation_errors, headless_renderer};
+use crate::graphics::Renderer;
+use crate::graphics::buffer::StorageBuffer;
+use crate::graphics::descriptor::{DescriptorPool, DescriptorSetLayoutBuilder};
+use crate::graphics::pipeline::PipelineBuilder;
+
+const EXTENT : vk::Extent2D = vk::Extent2D { width: 16, height: 16 };
+
+/// Runs the prefix sum shader over `values`, dispatching either directly or with indirect arguments.
+fn prefix_sum(renderer : &Renderer, values : &[u32], indirect : bool) -> Vec<u32> {
+    let device = renderer.device();
+    let set_layout = DescriptorSetLayoutBuilder::new(device.clone())
+        .add_binding(0, vk::DescriptorType::STORAGE_BUFFER, 1, vk::ShaderStageFlags::COMPUTE)
+        .add_binding(1, vk::DescriptorType::STORAGE_BUFFER, 1, vk::ShaderStageFlags::COMPUTE)
+        .build()
+        .unwrap();
+    let push_constants = [vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .size(4)
+        .build()];
+    let pipeline = PipelineBuilder::new(device.clone())
+        .build_compute(include_bytes!("../assets/shaders/prefix_sum.spv"), &[&set_layout], &push_constants)
+        .unwrap();
+    assert!(pipeline.supports_compute());
+
+    let pool = DescriptorPool::new(device.clone(), 1, &[vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::STORAGE_BUFFER,
+        descriptor_count: 2,
+    }]).unwrap();
+    let set = pool.allocate(&set_layout).unwrap();
+    let input = StorageBuffer::new(device.clone(), values).unwrap();
+    let output = StorageBuffer::new(device.clone(), &vec![0u32; values.len()]).unwrap();
+    let dispatch_arguments = StorageBuffer::new(device.clone(), &[1u32, 1, 1]).unwrap();
+    set.write_buffer(0, vk::DescriptorType::STORAGE_BUFFER, input.buffer_raw());
+    set.write_buffer(1, vk::DescriptorType::STORAGE_BUFFER, output.buffer_raw());
+
+    renderer.compute().run(|cmd_buffer| {
+        cmd_buffer.bind_pipeline(&pipeline);
+        cmd_buffer.bind_descriptor_sets(&pipeline, 0, &[&set]);
+        cmd_buffer.push_constants(&pipeline, vk::ShaderStageFlags::COMPUTE, 0, &[values.len() as u32]);
+        if indirect {
+            cmd_buffer.dispatch_indirect(&dispatch_arguments, 0);
+        } else {
+            cmd_buffer.dispatch(1, 1, 1);
+        }
+    }).unwrap();
+
+    output
+        .read()
+        .chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+}
+
+#[test]
+#[ignore = "requires a Vulkan driver"]
+fn prefix_sum_on_compute_queue() {
+    let renderer = headless_renderer(EXTENT);
+    let values : Vec<u32> = (1..=200).collect();
+    let expected : Vec<u32> = values
+        .iter()
+        .scan(0, |sum, value| {
+            *sum += value;
+            Some(*sum)
+        })
+        .collect();
+
+    assert_eq!(prefix_sum(&renderer, &values, false), expected);
+    assert_eq!(prefix_sum(&renderer, &values, true), expected);
+    assert_no_validation_errors(&renderer);
+}
@@ -0,0 +1,75 @@
+use ash::vk;
+use crate::graphics::device::QueuePlan;
+
+fn family(queue_flags : vk::QueueFlags) -> vk::QueueFamilyProperties {
+    vk::QueueFamilyProperties { queue_flags, queue_count: 1, ..Default::default() }
+}
+
+const UNIVERSAL : vk::QueueFlags = vk::QueueFlags::from_raw(
+    vk::QueueFlags::GRAPHICS.as_raw() | vk::QueueFlags::COMPUTE.as_raw() | vk::QueueFlags::TRANSFER.as_raw());
+const ASYNC_COMPUTE : vk::QueueFlags = vk::QueueFlags::from_raw(
+    vk::QueueFlags::COMPUTE.as_raw() | vk::QueueFlags::TRANSFER.as_raw());
+
+#[test]
+fn prefers_dedicated_compute_and_transfer_families() {
+    let families = [family(UNIVERSAL), family(ASYNC_COMPUTE), family(vk::QueueFlags::TRANSFER)];
+    let plan = QueuePlan::new(&families, None).unwrap();
+    assert_eq!(plan, QueuePlan { graphics_family: 0, compute_family: 1, transfer_family: 2, present_family: None });
+    assert!(plan.async_compute());
+    assert!(plan.async_transfer());
+}
+
+#[test]
+fn transfer_falls_back_to_compute_then_graphics() {
+    let plan = QueuePlan::new(&[family(UNIVERSAL), family(ASYNC_COMPUTE)], None).unwrap();
+    assert_eq!(plan.compute_family, 1);
+    assert_eq!(plan.transfer_family, 1);
+
+    let plan = QueuePlan::new(&[family(UNIVERSAL)], None).unwrap();
+    assert_eq!(plan.compute_family, 0);
+    assert_eq!(plan.transfer_family, 0);
+    assert!(!plan.async_compute());
+    assert!(!plan.async_transfer());
+}
+
+#[test]
+fn skips_families_without_queues() {
+    let mut empty = family(vk::QueueFlags::TRANSFER);
+    empty.queue_count = 0;
+    let plan = QueuePlan::new(&[family(UNIVERSAL), empty], None).unwrap();
+    assert_eq!(plan.transfer_family, 0);
+}
+
+#[test]
+fn requires_a_graphics_family() {
+    assert_eq!(QueuePlan::new(&[family(ASYNC_COMPUTE)], None), None);
+}
+
+#[test]
+fn presents_from_graphics_family_when_possible() {
+    let families = [family(UNIVERSAL), family(UNIVERSAL)];
+    // Graphics moves to the second family so that it can present as well.
+    let plan = QueuePlan::new(&families, Some(&|index| index == 1)).unwrap();
+    assert_eq!(plan.graphics_family, 1);
+    assert_eq!(plan.present_family, Some(1));
+}
+
+#[test]
+fn presents_from_separate_family() {
+    let families = [family(UNIVERSAL), family(vk::QueueFlags::TRANSFER)];
+    let plan = QueuePlan::new(&families, Some(&|index| index == 1)).unwrap();
+    assert_eq!(plan.graphics_family, 0);
+    assert_eq!(plan.present_family, Some(1));
+
+    assert_eq!(QueuePlan::new(&families, Some(&|_| false)), None);
+}
+
+#[test]
+fn deduplicates_shared_families() {
+    let plan = QueuePlan::new(&[family(UNIVERSAL)], Some(&|_| true)).unwrap();
+    assert_eq!(plan.unique_families(), [0]);
+
+    let families = [family(vk::QueueFlags::TRANSFER), family(UNIVERSAL), family(ASYNC_COMPUTE)];
+    let plan = QueuePlan::new(&families, Some(&|index| index == 1)).unwrap();
+    assert_eq!(plan.unique_families(), [0, 1, 2]);
+}
@@ -0,0 +1,94 @@
+use std::{env, fs::{self, File}, io::BufWriter, path::PathBuf};
+use ash::vk;
+
+/// When set, rendered frames overwrite their reference images instead of being compared against them.
+const BLESS_VARIABLE : &str = "HALOGEN_BLESS_GOLDEN";
+
+/// Directory holding the checked-in reference images.
+fn reference_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/assets/golden")
+}
+
+/// Directory which actual and diff images are written to when a comparison fails.
+fn output_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/golden")
+}
+
+fn read_png(path : &PathBuf) -> (Vec<u8>, vk::Extent2D) {
+    let decoder = png::Decoder::new(File::open(path).expect("Failed to open reference image"));
+    let mut reader = decoder.read_info().expect("Failed to read reference image");
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).expect("Failed to decode reference image");
+    assert_eq!((info.color_type, info.bit_depth), (png::ColorType::Rgba, png::BitDepth::Eight),
+        "Reference images must be 8 bit RGBA");
+    pixels.truncate(info.buffer_size());
+    (pixels, vk::Extent2D { width: info.width, height: info.height })
+}
+
+fn write_png(path : &PathBuf, pixels : &[u8], extent : vk::Extent2D) {
+    fs::create_dir_all(path.parent().unwrap()).expect("Failed to create image directory");
+    let file = File::create(path).expect("Failed to create image");
+    let mut encoder = png::Encoder::new(BufWriter::new(file), extent.width, extent.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(pixels))
+        .expect("Failed to write image");
+}
+
+/// Returns the number of pixels where any channel differs by more than `tolerance`, along with an image which
+/// highlights those pixels in red over a faded copy of the reference.
+fn diff(actual : &[u8], reference : &[u8], tolerance : u8) -> (usize, Vec<u8>) {
+    let mut mismatched = 0;
+    let mut diff_image = Vec::with_capacity(reference.len());
+    for (actual, reference) in actual.chunks_exact(4).zip(reference.chunks_exact(4)) {
+        let matches = actual
+            .iter()
+            .zip(reference)
+            .all(|(a, r)| a.abs_diff(*r) <= tolerance);
+        if matches {
+            let luma = ((reference[0] as u32 + reference[1] as u32 + reference[2] as u32) / 9) as u8;
+            diff_image.extend_from_slice(&[luma, luma, luma, 255]);
+        } else {
+            mismatched += 1;
+            diff_image.extend_from_slice(&[255, 0, 0, 255]);
+        }
+    }
+    (mismatched, diff_image)
+}
+
+/// Asserts that tightly packed RGBA8 `pixels` match the reference image `name`, allowing every channel of every
+/// pixel to differ by up to `tolerance`. On failure the actual and diff images are written to `target/golden`.
+/// Running the tests with `HALOGEN_BLESS_GOLDEN=1` replaces the reference with `pixels` instead.
+pub fn assert_matches_golden(name : &str, pixels : &[u8], extent : vk::Extent2D, tolerance : u8) {
+    let reference_path = reference_dir().join(format!("{}.png", name));
+    let actual_path = output_dir().join(format!("{}.actual.png", name));
+    let diff_path = output_dir().join(format!("{}.diff.png", name));
+
+    if env::var_os(BLESS_VARIABLE).is_some() {
+        write_png(&reference_path, pixels, extent);
+        return;
+    }
+
+    if !reference_path.exists() {
+        write_png(&actual_path, pixels, extent);
+        panic!("Missing reference image {:?}. The rendered frame was written to {:?}, run with {}=1 to accept it.",
+            reference_path, actual_path, BLESS_VARIABLE);
+    }
+
+    let (reference, reference_extent) = read_png(&reference_path);
+    if reference_extent != extent {
+        write_png(&actual_path, pixels, extent);
+        panic!("{} was rendered at {}x{} but the reference is {}x{}. The rendered frame was written to {:?}.",
+            name, extent.width, extent.height, reference_extent.width, reference_extent.height, actual_path);
+    }
+
+    let (mismatched, diff_image) = diff(pixels, &reference, tolerance);
+    if mismatched > 0 {
+        write_png(&actual_path, pixels, extent);
+        write_png(&diff_path, &diff_image, extent);
+        panic!("{} has {} pixels which differ from the reference by more than {}. See {:?} and {:?}.",
+            name, mismatched, tolerance, actual_path, diff_path);
+    }
+}
@@ -0,0 +1,62 @@
+use std::ffi::CStr;
+use crate::graphics::{debug::ValidationSettings, instance::{InstanceBuilder, InstanceCreationError}, Error};
+
+const MISSING_LAYER : &[u8] = b"VK_LAYER_HALOGEN_missing\0";
+const MISSING_EXTENSION : &[u8] = b"VK_HALOGEN_missing_extension\0";
+
+fn name(bytes : &'static [u8]) -> &'static CStr {
+    CStr::from_bytes_with_nul(bytes).unwrap()
+}
+
+#[test]
+#[ignore = "requires a Vulkan driver"]
+fn reports_missing_required_names() {
+    let result = InstanceBuilder::new()
+        .require_extensions(&[name(MISSING_EXTENSION)])
+        .build();
+    match result {
+        Err(Error::Instance(InstanceCreationError::MissingExtensions(names))) =>
+            assert_eq!(names, vec!["VK_HALOGEN_missing_extension".to_string()]),
+        Err(error) => panic!("Unexpected error: {}", error),
+        Ok(_) => panic!("Instance was created without a required extension"),
+    }
+
+    let result = InstanceBuilder::new()
+        .require_layers(&[name(MISSING_LAYER)])
+        .build();
+    match result {
+        Err(Error::Instance(InstanceCreationError::MissingLayers(names))) =>
+            assert_eq!(names, vec!["VK_LAYER_HALOGEN_missing".to_string()]),
+        Err(error) => panic!("Unexpected error: {}", error),
+        Ok(_) => panic!("Instance was created without a required layer"),
+    }
+}
+
+#[test]
+#[ignore = "requires a Vulkan driver"]
+fn skips_missing_optional_names() {
+    let instance = InstanceBuilder::new()
+        .request_layers(&[name(MISSING_LAYER)])
+        .request_extensions(&[name(MISSING_EXTENSION)])
+        .validation(true)
+        .build()
+        .unwrap_or_else(|error| panic!("Failed to create instance: {}", error));
+    assert!(!instance.is_layer_enabled(name(MISSING_LAYER)));
+    assert!(!instance.is_extension_enabled(name(MISSING_EXTENSION)));
+}
+
+#[test]
+#[ignore = "requires a Vulkan driver"]
+fn enables_validation_features() {
+    let settings = ValidationSettings {
+        enabled: true,
+        gpu_assisted: true,
+        synchronization: true,
+        suppressed_message_ids: vec!["VUID-vkCmdDraw-None-02699".to_string()],
+        ..ValidationSettings::default()
+    };
+    // Validation features are optional, so this succeeds whether or not the validation layer is installed.
+    if let Err(error) = InstanceBuilder::new().validation_settings(settings).build() {
+        panic!("Failed to create instance: {}", error);
+    }
+}
@@ -0,0 +1,54 @@
+/// Compares rendered frames against the reference images in `src/assets/golden`.
+pub mod golden;
+mod compute;
+mod device;
+mod instance;
+mod pipeline;
+mod pipeline_cache;
+mod reflect;
+mod renderer;
+mod swapchain;
+
+use std::{cell::RefCell, rc::Rc};
+use ash::vk;
+use crate::graphics::{device::Device, instance::Instance, Renderer};
+
+/// Creates a headless renderer. Tests which need a driver are ignored by default, and are run explicitly with
+/// `cargo test -- --include-ignored`, so a missing driver fails the test rather than skipping it. A software driver such as
+/// lavapipe is enough to run them.
+pub fn headless_renderer(extent : vk::Extent2D) -> Renderer {
+    Renderer::new_headless(extent).unwrap_or_else(|error| panic!("Failed to create headless renderer: {}", error))
+}
+
+/// Creates a renderer presenting to a headless surface, which requires a driver supporting
+/// `VK_EXT_headless_surface`. Mesa's lavapipe supports it.
+pub fn headless_surface_renderer(extent : vk::Extent2D) -> Renderer {
+    Renderer::new_headless_surface(extent)
+        .unwrap_or_else(|error| panic!("Failed to create headless surface renderer: {}", error))
+}
+
+/// Fails the test if the validation layer reported any errors since the last call. This passes trivially on machines
+/// without the validation layer installed.
+pub fn assert_no_validation_errors(renderer : &Renderer) {
+    let errors : Vec<String> = renderer
+        .take_validation_messages()
+        .into_iter()
+        .filter(|message| message.is_error())
+        .map(|message| format!("{}: {}", message.message_id_name, message.message))
+        .collect();
+    assert!(errors.is_empty(), "Validation errors were reported:\n{}", errors.join("\n"));
+}
+
+/// A device without a surface, for tests which create resources directly. The device is declared first, so it is
+/// dropped before the instance.
+pub struct HeadlessContext {
+    pub device : Rc<RefCell<Device>>,
+    _instance : Instance,
+}
+
+/// Creates a headless device for tests which create resources directly.
+pub fn headless_context() -> HeadlessContext {
+    let instance = Instance::new_headless().unwrap_or_else(|error| panic!("Failed to create instance: {}", error));
+    let device = Device::new_headless(&instance).expect("Failed to create device");
+    HeadlessContext { device: Rc::new(RefCell::new(device)), _instance: instance }
+}
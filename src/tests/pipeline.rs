@@ -0,0 +1,61 @@
+use std::rc::Rc;
+use ash::vk;
+use super::headless_context;
+use crate::graphics::Error;
+use crate::graphics::material::Material;
+use crate::graphics::pass::RenderPassBuilder;
+use crate::graphics::pipeline::{BlendMode, DepthBias, PipelineBuilder};
+
+#[test]
+#[ignore = "requires a Vulkan driver"]
+fn builds_fixed_function_variants() {
+    let context = headless_context();
+    let render_pass = RenderPassBuilder::new(Rc::clone(&context.device))
+        .add_color_attachment_with_layout(vk::Format::R8G8B8A8_UNORM, vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .build()
+        .expect("Failed to create the render pass");
+    let material = Material::new(Rc::clone(&context.device)).unwrap();
+
+    let variants = vec![
+        ("opaque", PipelineBuilder::new(Rc::clone(&context.device))
+            .cull_mode(vk::CullModeFlags::BACK, vk::FrontFace::CLOCKWISE)),
+        ("transparent", PipelineBuilder::new(Rc::clone(&context.device))
+            .blend_mode(BlendMode::Alpha)),
+        ("additive", PipelineBuilder::new(Rc::clone(&context.device))
+            .attachment_blend_modes(&[BlendMode::Additive])),
+        ("wireframe", PipelineBuilder::new(Rc::clone(&context.device))
+            .polygon_mode(vk::PolygonMode::LINE)
+            .depth_bias(DepthBias { constant_factor: 1.0, clamp: 0.0, slope_factor: 1.0 })),
+        ("ui", PipelineBuilder::new(Rc::clone(&context.device))
+            .topology(vk::PrimitiveTopology::TRIANGLE_STRIP)
+            .primitive_restart(true)
+            .blend_mode(BlendMode::Premultiplied)),
+    ];
+    for (name, builder) in variants {
+        let pipeline = builder
+            .build_graphics(&render_pass, &material)
+            .unwrap_or_else(|error| panic!("Failed to build the {} pipeline: {}", name, error));
+        pipeline.set_debug_name(name);
+    }
+}
+
+#[test]
+#[ignore = "requires a Vulkan driver"]
+fn rejects_unsupported_dynamic_state() {
+    let context = headless_context();
+    let render_pass = RenderPassBuilder::new(Rc::clone(&context.device))
+        .add_color_attachment_with_layout(vk::Format::R8G8B8A8_UNORM, vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .build()
+        .expect("Failed to create the render pass");
+    let material = Material::new(Rc::clone(&context.device)).unwrap();
+
+    PipelineBuilder::new(Rc::clone(&context.device))
+        .dynamic_states(&[vk::DynamicState::LINE_WIDTH, vk::DynamicState::STENCIL_REFERENCE])
+        .build_graphics(&render_pass, &material)
+        .unwrap();
+    // The device is created without the extended dynamic state feature.
+    let result = PipelineBuilder::new(Rc::clone(&context.device))
+        .dynamic_states(&[vk::DynamicState::CULL_MODE])
+        .build_graphics(&render_pass, &material);
+    assert!(matches!(result, Err(Error::UnsupportedDynamicState(vk::DynamicState::CULL_MODE))));
+}
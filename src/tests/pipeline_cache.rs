@@ -0,0 +1,61 @@
+use std::fs;
+use ash::vk;
+use super::headless_context;
+use crate::graphics::pipeline_cache::PipelineCache;
+
+fn properties() -> vk::PhysicalDeviceProperties {
+    vk::PhysicalDeviceProperties {
+        vendor_id: 0x10de,
+        device_id: 0x2204,
+        pipeline_cache_uuid: [7; vk::UUID_SIZE],
+        ..Default::default()
+    }
+}
+
+/// Builds cache data with a `VkPipelineCacheHeaderVersionOne` header followed by some driver specific bytes.
+fn cache_data(vendor_id : u32, device_id : u32, uuid : [u8; vk::UUID_SIZE]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&32u32.to_le_bytes());
+    data.extend_from_slice(&(vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32).to_le_bytes());
+    data.extend_from_slice(&vendor_id.to_le_bytes());
+    data.extend_from_slice(&device_id.to_le_bytes());
+    data.extend_from_slice(&uuid);
+    data.extend_from_slice(&[0xab; 64]);
+    data
+}
+
+#[test]
+fn validates_header_against_device() {
+    let properties = properties();
+    assert!(PipelineCache::is_compatible(&cache_data(0x10de, 0x2204, [7; vk::UUID_SIZE]), &properties));
+    assert!(!PipelineCache::is_compatible(&cache_data(0x1002, 0x2204, [7; vk::UUID_SIZE]), &properties));
+    assert!(!PipelineCache::is_compatible(&cache_data(0x10de, 0x73bf, [7; vk::UUID_SIZE]), &properties));
+    assert!(!PipelineCache::is_compatible(&cache_data(0x10de, 0x2204, [8; vk::UUID_SIZE]), &properties));
+    assert!(!PipelineCache::is_compatible(&cache_data(0x10de, 0x2204, [7; vk::UUID_SIZE])[..31], &properties));
+    assert!(!PipelineCache::is_compatible(&[], &properties));
+
+    let mut oversized_header = cache_data(0x10de, 0x2204, [7; vk::UUID_SIZE]);
+    oversized_header[0..4].copy_from_slice(&1024u32.to_le_bytes());
+    assert!(!PipelineCache::is_compatible(&oversized_header, &properties));
+}
+
+#[test]
+#[ignore = "requires a Vulkan driver"]
+fn saves_and_reloads_cache() {
+    let context = headless_context();
+    let path = std::env::temp_dir().join(format!("halogen_pipeline_cache_{}.bin", std::process::id()));
+
+    // Stale data is discarded rather than handed to the driver.
+    fs::write(&path, cache_data(0xdead, 0xbeef, [0; vk::UUID_SIZE])).unwrap();
+    context.device.borrow_mut().load_pipeline_cache(&path).unwrap();
+    context.device.borrow().save_pipeline_cache().unwrap();
+
+    let data = fs::read(&path).unwrap();
+    assert!(PipelineCache::is_compatible(&data, &context.device.borrow().properties()));
+    context.device.borrow_mut().load_pipeline_cache(&path).unwrap();
+    assert_ne!(context.device.borrow().pipeline_cache(), vk::PipelineCache::null());
+
+    // The device saves the cache again when dropped, so it must be gone before the file is removed.
+    drop(context);
+    fs::remove_file(&path).unwrap();
+}
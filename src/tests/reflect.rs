@@ -0,0 +1,192 @@
+use std::mem::offset_of;
+use ash::vk;
+use super::headless_context;
+use crate::graphics::material::Vertex;
+use crate::graphics::pipeline::PipelineBuilder;
+use crate::graphics::reflect::{self, InterfaceVariable, PipelineInterface, ReflectionError, ShaderReflection};
+
+const VERTEX_SHADER : &[u8] = include_bytes!("../assets/shaders/vert.spv");
+const FRAGMENT_SHADER : &[u8] = include_bytes!("../assets/shaders/frag.spv");
+const PREFIX_SUM_SHADER : &[u8] = include_bytes!("../assets/shaders/prefix_sum.spv");
+
+fn locations(variables : &[InterfaceVariable]) -> Vec<(u32, vk::Format)> {
+    variables.iter().map(|variable| (variable.location, variable.format)).collect()
+}
+
+#[test]
+fn reflects_default_shaders() {
+    let vertex = ShaderReflection::new(VERTEX_SHADER).unwrap();
+    let fragment = ShaderReflection::new(FRAGMENT_SHADER).unwrap();
+    let vertex_entry_point = vertex.entry_point("main", vk::ShaderStageFlags::VERTEX).unwrap();
+    let fragment_entry_point = fragment.entry_point("main", vk::ShaderStageFlags::FRAGMENT).unwrap();
+
+    assert_eq!(locations(&vertex_entry_point.inputs),
+        [(0, vk::Format::R32G32B32_SFLOAT), (1, vk::Format::R32G32B32A32_SFLOAT)]);
+    assert_eq!(locations(&vertex_entry_point.outputs), [(0, vk::Format::R32G32B32_SFLOAT)]);
+    assert_eq!(locations(&fragment_entry_point.inputs), [(0, vk::Format::R32G32B32_SFLOAT)]);
+    assert_eq!(locations(&fragment_entry_point.outputs), [(0, vk::Format::R32G32B32A32_SFLOAT)]);
+    reflect::check_stage_interface(&vertex_entry_point.outputs, fragment_entry_point).unwrap();
+
+    // The texture coordinate is not read, so it is left out of the vertex input state.
+    let attributes = reflect::vertex_attributes(&vertex_entry_point.inputs, &Vertex::attribute_descriptions()).unwrap();
+    let attributes : Vec<_> = attributes.iter().map(|attribute| (attribute.location, attribute.offset)).collect();
+    assert_eq!(attributes, [(0, offset_of!(Vertex, position) as u32), (1, offset_of!(Vertex, color) as u32)]);
+
+    let interface = PipelineInterface::new(&[&vertex, &fragment]).unwrap();
+    assert_eq!(interface.set_count(), 0);
+    assert!(interface.push_constant_ranges().is_empty());
+}
+
+#[test]
+fn reflects_compute_resources() {
+    let reflection = ShaderReflection::new(PREFIX_SUM_SHADER).unwrap();
+    reflection.entry_point("main", vk::ShaderStageFlags::COMPUTE).unwrap();
+
+    let bindings : Vec<_> = reflection
+        .descriptor_bindings()
+        .iter()
+        .map(|binding| (binding.set, binding.binding, binding.descriptor_type, binding.count, binding.stages))
+        .collect();
+    assert_eq!(bindings, [
+        (0, 0, vk::DescriptorType::STORAGE_BUFFER, 1, vk::ShaderStageFlags::COMPUTE),
+        (0, 1, vk::DescriptorType::STORAGE_BUFFER, 1, vk::ShaderStageFlags::COMPUTE),
+    ]);
+
+    let range = reflection.push_constant_range().unwrap();
+    assert_eq!((range.stage_flags, range.offset, range.size), (vk::ShaderStageFlags::COMPUTE, 0, 4));
+}
+
+#[test]
+fn reports_mismatches() {
+    let vertex = ShaderReflection::new(VERTEX_SHADER).unwrap();
+    let fragment = ShaderReflection::new(FRAGMENT_SHADER).unwrap();
+    let inputs = &vertex.entry_point("main", vk::ShaderStageFlags::VERTEX).unwrap().inputs;
+
+    assert!(matches!(vertex.entry_point("main", vk::ShaderStageFlags::FRAGMENT),
+        Err(ReflectionError::MissingEntryPoint { .. })));
+
+    let mut layout = Vertex::attribute_descriptions();
+    layout.retain(|attribute| attribute.location != 1);
+    assert!(matches!(reflect::vertex_attributes(inputs, &layout),
+        Err(ReflectionError::MissingVertexAttribute { location: 1, .. })));
+
+    let mut layout = Vertex::attribute_descriptions();
+    layout[0].format = vk::Format::R32G32B32_SINT;
+    assert!(matches!(reflect::vertex_attributes(inputs, &layout),
+        Err(ReflectionError::VertexFormatMismatch { location: 0, .. })));
+
+    // Missing components are filled in and extra ones ignored, so only the numeric type has to match.
+    let mut layout = Vertex::attribute_descriptions();
+    layout[0].format = vk::Format::R32G32B32A32_SFLOAT;
+    layout[1].format = vk::Format::R32G32B32_SFLOAT;
+    reflect::vertex_attributes(inputs, &layout).unwrap();
+
+    // The fragment shader writes nothing the vertex shader could read.
+    let fragment_entry_point = fragment.entry_point("main", vk::ShaderStageFlags::FRAGMENT).unwrap();
+    assert!(matches!(reflect::check_stage_interface(&[], fragment_entry_point),
+        Err(ReflectionError::UnmatchedStageInput { location: 0, .. })));
+
+    // A stage may write more components than the next reads, but not fewer.
+    let output = |format| InterfaceVariable { location: 0, format, name: String::new() };
+    reflect::check_stage_interface(&[output(vk::Format::R32G32B32A32_SFLOAT)], fragment_entry_point).unwrap();
+    assert!(matches!(reflect::check_stage_interface(&[output(vk::Format::R32G32_SFLOAT)], fragment_entry_point),
+        Err(ReflectionError::UnmatchedStageInput { location: 0, .. })));
+    assert!(matches!(reflect::check_stage_interface(&[output(vk::Format::R32G32B32_UINT)], fragment_entry_point),
+        Err(ReflectionError::UnmatchedStageInput { location: 0, .. })));
+
+    assert!(matches!(ShaderReflection::new(&PREFIX_SUM_SHADER[..6]), Err(ReflectionError::Malformed(_))));
+    assert!(matches!(ShaderReflection::new(&PREFIX_SUM_SHADER[..64]), Err(ReflectionError::Malformed(_))));
+}
+
+// Opcodes and operands used to assemble modules by hand.
+const OP_ENTRY_POINT : u32 = 15;
+const OP_TYPE_INT : u32 = 21;
+const OP_TYPE_FLOAT : u32 = 22;
+const OP_TYPE_ARRAY : u32 = 28;
+const OP_TYPE_STRUCT : u32 = 30;
+const OP_TYPE_POINTER : u32 = 32;
+const OP_CONSTANT : u32 = 43;
+const OP_SPEC_CONSTANT : u32 = 50;
+const OP_SPEC_CONSTANT_OP : u32 = 52;
+const OP_VARIABLE : u32 = 59;
+const OP_DECORATE : u32 = 71;
+const INPUT : u32 = 1;
+const PUSH_CONSTANT : u32 = 9;
+const LOCATION : u32 = 30;
+/// "main" followed by its nul terminator.
+const MAIN : [u32; 2] = [0x6e69_616d, 0];
+
+/// Assembles a module from instructions, each given as its opcode and operands.
+fn assemble(instructions : &[(u32, &[u32])]) -> Vec<u8> {
+    let mut words = vec![0x0723_0203, 0x0001_0000, 0, 16, 0];
+    for (opcode, operands) in instructions {
+        words.push((operands.len() as u32 + 1) << 16 | opcode);
+        words.extend_from_slice(operands);
+    }
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+#[test]
+fn rejects_malformed_types() {
+    // A push constant block which contains itself.
+    let recursive = assemble(&[
+        (OP_TYPE_STRUCT, &[1, 1]),
+        (OP_TYPE_POINTER, &[2, PUSH_CONSTANT, 1]),
+        (OP_VARIABLE, &[2, 3, PUSH_CONSTANT]),
+    ]);
+    assert!(matches!(ShaderReflection::new(&recursive), Err(ReflectionError::Malformed("a type contains itself"))));
+
+    // A push constant block whose size does not fit in 32 bits.
+    let oversized = assemble(&[
+        (OP_TYPE_INT, &[1, 32, 0]),
+        (OP_CONSTANT, &[1, 2, u32::MAX]),
+        (OP_TYPE_ARRAY, &[3, 1, 2]),
+        (OP_TYPE_STRUCT, &[4, 3]),
+        (OP_TYPE_POINTER, &[5, PUSH_CONSTANT, 4]),
+        (OP_VARIABLE, &[5, 6, PUSH_CONSTANT]),
+    ]);
+    assert!(matches!(ShaderReflection::new(&oversized),
+        Err(ReflectionError::Malformed("a push constant block is too large"))));
+}
+
+/// Assembles a vertex shader reading a float array at location 0, whose length is declared by `length`.
+fn vertex_input_array(length : (u32, &[u32])) -> Vec<u8> {
+    assemble(&[
+        (OP_ENTRY_POINT, &[0, 1, MAIN[0], MAIN[1], 7]),
+        (OP_DECORATE, &[7, LOCATION, 0]),
+        (OP_TYPE_FLOAT, &[2, 32]),
+        (OP_TYPE_INT, &[3, 32, 0]),
+        length,
+        (OP_TYPE_ARRAY, &[5, 2, 4]),
+        (OP_TYPE_POINTER, &[6, INPUT, 5]),
+        (OP_VARIABLE, &[6, 7, INPUT]),
+    ])
+}
+
+#[test]
+fn rejects_oversized_interfaces() {
+    let oversized = vertex_input_array((OP_CONSTANT, &[3, 4, u32::MAX]));
+    assert!(matches!(ShaderReflection::new(&oversized), Err(ReflectionError::TooManyLocations { location: 0, .. })));
+}
+
+#[test]
+fn reads_spec_constant_array_lengths() {
+    let reflection = ShaderReflection::new(&vertex_input_array((OP_SPEC_CONSTANT, &[3, 4, 3]))).unwrap();
+    let entry_point = reflection.entry_point("main", vk::ShaderStageFlags::VERTEX).unwrap();
+    assert_eq!(locations(&entry_point.inputs),
+        [(0, vk::Format::R32_SFLOAT), (1, vk::Format::R32_SFLOAT), (2, vk::Format::R32_SFLOAT)]);
+
+    // An expression of specialization constants could only be evaluated once the pipeline is specialized.
+    let expression = vertex_input_array((OP_SPEC_CONSTANT_OP, &[3, 4, 128, 8, 8]));
+    assert!(matches!(ShaderReflection::new(&expression), Err(ReflectionError::UnsupportedArrayLength)));
+}
+
+#[test]
+#[ignore = "requires a Vulkan driver"]
+fn builds_reflected_compute_pipeline() {
+    let context = headless_context();
+    let pipeline = PipelineBuilder::new(context.device.clone())
+        .build_compute_reflected(PREFIX_SUM_SHADER)
+        .unwrap();
+    assert_eq!(pipeline.set_layouts().len(), 1);
+}
@@ -0,0 +1,40 @@
+use ash::vk;
+use winit::dpi::PhysicalSize;
+use super::{assert_no_validation_errors, golden::assert_matches_golden, headless_renderer};
+use crate::graphics::Error;
+use crate::util::CapturedEvent;
+
+/// Allows for rounding differences between drivers when converting to sRGB.
+const TOLERANCE : u8 = 2;
+
+#[test]
+#[ignore = "requires a Vulkan driver"]
+fn default_triangle() {
+    let extent = vk::Extent2D { width: 64, height: 64 };
+    let mut renderer = headless_renderer(extent);
+    renderer.draw_frame().unwrap();
+    let (pixels, extent) = renderer.read_frame().unwrap().expect("No frame was drawn");
+    assert_matches_golden("default_triangle", &pixels, extent, TOLERANCE);
+    assert_no_validation_errors(&renderer);
+}
+
+#[test]
+#[ignore = "requires a Vulkan driver"]
+fn default_triangle_after_resize() {
+    let mut renderer = headless_renderer(vk::Extent2D { width: 32, height: 32 });
+    renderer.draw_frame().unwrap();
+    // The pipeline is kept, so the triangle only fills the new extent if the viewport is set while recording.
+    renderer.on_resize(PhysicalSize::new(64, 64));
+    renderer.draw_frame().unwrap();
+    let (pixels, extent) = renderer.read_frame().unwrap().expect("No frame was drawn");
+    assert_matches_golden("default_triangle", &pixels, extent, TOLERANCE);
+    assert_no_validation_errors(&renderer);
+}
+
+#[test]
+#[ignore = "requires a Vulkan driver"]
+fn rejects_zero_frames_in_flight() {
+    let mut renderer = headless_renderer(vk::Extent2D { width: 32, height: 32 });
+    assert!(matches!(renderer.set_frames_in_flight(0), Err(Error::NoFramesInFlight)));
+    renderer.draw_frame().unwrap();
+}
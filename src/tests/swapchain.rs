@@ -0,0 +1,155 @@
+use ash::vk;
+use winit::dpi::PhysicalSize;
+use super::{assert_no_validation_errors, headless_surface_renderer};
+use crate::graphics::swapchain::{PresentPolicy, SwapchainCreationError, SwapchainStatus};
+use crate::graphics::swapchain::{select_composite_alpha, select_extent, select_image_count, select_pre_transform};
+use crate::util::CapturedEvent;
+
+const EXTENT : vk::Extent2D = vk::Extent2D { width: 64, height: 64 };
+
+#[test]
+#[ignore = "requires a Vulkan driver"]
+fn presents_more_frames_than_are_in_flight() {
+    let mut renderer = headless_surface_renderer(EXTENT);
+    for _ in 0..renderer.frames_in_flight() * 3 {
+        renderer.draw_frame().unwrap();
+    }
+    assert_eq!(renderer.extent(), EXTENT);
+    assert_no_validation_errors(&renderer);
+}
+
+#[test]
+#[ignore = "requires a Vulkan driver"]
+fn recreates_swapchain_after_resize() {
+    let mut renderer = headless_surface_renderer(EXTENT);
+    renderer.draw_frame().unwrap();
+    renderer.on_resize(PhysicalSize::new(128, 96));
+    renderer.draw_frame().unwrap();
+    assert_eq!(renderer.extent(), vk::Extent2D { width: 128, height: 96 });
+    assert_no_validation_errors(&renderer);
+}
+
+#[test]
+#[ignore = "requires a Vulkan driver"]
+fn survives_zero_extent() {
+    let mut renderer = headless_surface_renderer(EXTENT);
+    renderer.on_resize(PhysicalSize::new(0, 0));
+    // Nothing can be drawn until the window has a non-zero extent again.
+    assert_eq!(renderer.draw_frame().unwrap(), SwapchainStatus::OutOfDate);
+    renderer.on_resize(PhysicalSize::new(32, 32));
+    assert!(renderer.draw_frame().unwrap().is_success());
+    assert_eq!(renderer.extent(), vk::Extent2D { width: 32, height: 32 });
+    assert_no_validation_errors(&renderer);
+}
+
+#[test]
+#[ignore = "requires a Vulkan driver"]
+fn changes_present_policy() {
+    let mut renderer = headless_surface_renderer(EXTENT);
+    renderer.draw_frame().unwrap();
+
+    // Mailbox falls back to FIFO, so it is always accepted.
+    assert!(renderer.set_present_policy(PresentPolicy::Mailbox).is_ok());
+    renderer.draw_frame().unwrap();
+    assert!(matches!(renderer.present_mode(), Some(vk::PresentModeKHR::MAILBOX | vk::PresentModeKHR::FIFO)));
+
+    // Immediate has no fallback, so it is either applied or rejected without affecting the swapchain.
+    match renderer.set_present_policy(PresentPolicy::Immediate) {
+        Ok(()) => {
+            renderer.draw_frame().unwrap();
+            assert_eq!(renderer.present_mode(), Some(vk::PresentModeKHR::IMMEDIATE));
+        },
+        Err(SwapchainCreationError::PresentModeUnsupported(mode)) => assert_eq!(mode, vk::PresentModeKHR::IMMEDIATE),
+        Err(_) => panic!("Unexpected error when changing the present policy"),
+    }
+    renderer.draw_frame().unwrap();
+}
+
+#[test]
+fn selects_preferred_present_mode() {
+    let supported = [
+        vk::PresentModeKHR::IMMEDIATE,
+        vk::PresentModeKHR::MAILBOX,
+        vk::PresentModeKHR::FIFO,
+        vk::PresentModeKHR::FIFO_RELAXED,
+    ];
+    assert_eq!(PresentPolicy::Vsync.select(&supported), Ok(vk::PresentModeKHR::FIFO));
+    assert_eq!(PresentPolicy::Adaptive.select(&supported), Ok(vk::PresentModeKHR::FIFO_RELAXED));
+    assert_eq!(PresentPolicy::Mailbox.select(&supported), Ok(vk::PresentModeKHR::MAILBOX));
+    assert_eq!(PresentPolicy::Immediate.select(&supported), Ok(vk::PresentModeKHR::IMMEDIATE));
+}
+
+#[test]
+fn falls_back_to_fifo() {
+    let supported = [vk::PresentModeKHR::FIFO];
+    assert_eq!(PresentPolicy::Adaptive.select(&supported), Ok(vk::PresentModeKHR::FIFO));
+    assert_eq!(PresentPolicy::Mailbox.select(&supported), Ok(vk::PresentModeKHR::FIFO));
+    // FIFO is always supported, even when a driver fails to report it.
+    assert_eq!(PresentPolicy::Vsync.select(&[]), Ok(vk::PresentModeKHR::FIFO));
+    assert_eq!(PresentPolicy::Mailbox.select(&[vk::PresentModeKHR::IMMEDIATE]), Ok(vk::PresentModeKHR::FIFO));
+}
+
+#[test]
+fn rejects_unsupported_immediate_mode() {
+    // Disabling vsync has no tearing-free equivalent to fall back to.
+    let supported = [vk::PresentModeKHR::FIFO, vk::PresentModeKHR::MAILBOX];
+    assert_eq!(PresentPolicy::Immediate.select(&supported),
+        Err(SwapchainCreationError::PresentModeUnsupported(vk::PresentModeKHR::IMMEDIATE)));
+}
+
+fn capabilities() -> vk::SurfaceCapabilitiesKHR {
+    vk::SurfaceCapabilitiesKHR {
+        min_image_count: 2,
+        max_image_count: 4,
+        current_extent: vk::Extent2D { width: 640, height: 480 },
+        min_image_extent: vk::Extent2D { width: 1, height: 1 },
+        max_image_extent: vk::Extent2D { width: 1024, height: 1024 },
+        supported_transforms: vk::SurfaceTransformFlagsKHR::IDENTITY | vk::SurfaceTransformFlagsKHR::ROTATE_90,
+        current_transform: vk::SurfaceTransformFlagsKHR::ROTATE_90,
+        supported_composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE | vk::CompositeAlphaFlagsKHR::INHERIT,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn clamps_image_count() {
+    let mut capabilities = capabilities();
+    assert_eq!(select_image_count(&capabilities, 3), 3);
+    assert_eq!(select_image_count(&capabilities, 1), 2);
+    assert_eq!(select_image_count(&capabilities, 8), 4);
+    // A maximum of zero means the count is unbounded.
+    capabilities.max_image_count = 0;
+    assert_eq!(select_image_count(&capabilities, 8), 8);
+    assert_eq!(select_image_count(&capabilities, 1), 2);
+}
+
+#[test]
+fn uses_window_extent_when_surface_does_not_decide() {
+    let mut capabilities = capabilities();
+    let window_extent = vk::Extent2D { width: 800, height: 2000 };
+    assert_eq!(select_extent(&capabilities, window_extent), capabilities.current_extent);
+
+    capabilities.current_extent = vk::Extent2D { width: u32::MAX, height: u32::MAX };
+    assert_eq!(select_extent(&capabilities, window_extent), vk::Extent2D { width: 800, height: 1024 });
+    assert_eq!(select_extent(&capabilities, vk::Extent2D { width: 0, height: 0 }),
+        vk::Extent2D { width: 1, height: 1 });
+}
+
+#[test]
+fn uses_current_transform_without_identity() {
+    let mut capabilities = capabilities();
+    assert_eq!(select_pre_transform(&capabilities), vk::SurfaceTransformFlagsKHR::IDENTITY);
+    capabilities.supported_transforms = vk::SurfaceTransformFlagsKHR::ROTATE_90;
+    assert_eq!(select_pre_transform(&capabilities), vk::SurfaceTransformFlagsKHR::ROTATE_90);
+}
+
+#[test]
+fn falls_back_when_opaque_is_unsupported() {
+    let mut capabilities = capabilities();
+    assert_eq!(select_composite_alpha(&capabilities), vk::CompositeAlphaFlagsKHR::OPAQUE);
+    capabilities.supported_composite_alpha = vk::CompositeAlphaFlagsKHR::INHERIT;
+    assert_eq!(select_composite_alpha(&capabilities), vk::CompositeAlphaFlagsKHR::INHERIT);
+    capabilities.supported_composite_alpha =
+        vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED | vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED;
+    assert_eq!(select_composite_alpha(&capabilities), vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED);
+}